@@ -0,0 +1,43 @@
+//! Renders PNG thumbnails of shader projects over HTTP, without a window or browser.
+//!
+//! Listens on `--addr`, `SHADERWHEELS_THUMBNAIL_ADDR` or 127.0.0.1:8787, in that order.
+
+use shaderwheels_logic::{
+    rendering::headless::HeadlessRenderer,
+    thumbnail_server::{DEFAULT_ADDR, ServerError, ThumbnailServer},
+};
+
+const ADDR_ENV: &str = "SHADERWHEELS_THUMBNAIL_ADDR";
+
+fn main() {
+    env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
+
+    let mut addr = std::env::var(ADDR_ENV).unwrap_or_else(|_| DEFAULT_ADDR.to_string());
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--addr", Some(value)) => addr = value,
+            _ => {
+                eprintln!("Usage: shaderwheels-thumbnails [--addr HOST:PORT]");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let server = pollster::block_on(HeadlessRenderer::new())
+        .map_err(ServerError::Renderer)
+        .and_then(|renderer| ThumbnailServer::bind(&addr, renderer));
+    let server = match server {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Could not start the thumbnail server: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    match server.local_addr() {
+        Some(local) => eprintln!("Serving thumbnails on http://{local}/"),
+        None => eprintln!("Serving thumbnails on {addr}"),
+    }
+    server.run();
+}
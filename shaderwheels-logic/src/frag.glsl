@@ -0,0 +1,12 @@
+#version 450
+// Fragment shader
+// size.xy is the output size in pixels, size.z the time in milliseconds.
+layout(set = 0, binding = 0) uniform Size {
+    uvec4 size;
+};
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    out_color = vec4(gl_FragCoord.x / float(size.x), gl_FragCoord.y / float(size.y), 0.0, 1.0);
+}
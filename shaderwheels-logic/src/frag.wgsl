@@ -1,5 +1,6 @@
 // Fragment shader
 // https://sotrh.github.io/learn-wgpu/beginner/tutorial3-pipeline/#writing-the-shaders
+// size.xy is the output size in pixels, size.z the time in milliseconds.
 @group(0) @binding(0) var<uniform> size: vec4<u32>;
 
 @fragment
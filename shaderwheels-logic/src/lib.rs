@@ -1,2 +1,4 @@
 pub mod rendering;
+#[cfg(all(feature = "thumbnail-server", not(target_arch = "wasm32")))]
+pub mod thumbnail_server;
 pub mod web;
@@ -3,6 +3,8 @@ pub mod shader_config;
 pub mod communication;
 pub mod graphics_backend_client;
 pub mod graphics_backend_worker;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod headless;
 
 pub const DEFAULT_WGSL_COMPUTE: &str = include_str!("compute.wgsl");
 pub const WGSL_ENTRY: &str = "main";
@@ -12,3 +14,5 @@ pub const WGSL_VERT_ENTRY: &str = "vs_main";
 
 pub const DEFAULT_WGSL_FRAG: &str = include_str!("frag.wgsl");
 pub const WGSL_FRAG_ENTRY: &str = "fs_main";
+
+pub const DEFAULT_GLSL_FRAG: &str = include_str!("frag.glsl");
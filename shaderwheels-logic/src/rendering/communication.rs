@@ -1,67 +1,193 @@
 use crate::rendering::shader_config::{GPUAdapterInfo, ShaderConfig};
 use std::sync::mpsc::{self, Receiver, Sender};
-use wgpu::TextureView;
+use std::time::Duration;
+use wgpu::{TextureFormat, TextureView};
 
-macro_rules! channel_side {
-    ($name:ident, $kind:ident) => {
-        pub struct $name {
-            pub shader_content: $kind<ShaderConfig>,
+/// Called by the worker after each frame it renders, e.g. to repaint the UI.
+pub type RenderNotifier = Box<dyn Fn() + Send>;
 
-            pub hardware: $kind<GPUAdapterInfo>,
-            pub output_texture_view: $kind<TextureView>,
-            pub preout_size: $kind<(u32, u32)>,
+/// Position of a message in its stream. Commands and events are numbered
+/// separately, each starting at 1.
+pub type Seq = u64;
 
-            pub kill: $kind<()>,
+pub struct Sequenced<T> {
+    pub seq: Seq,
+    pub message: T,
+}
+
+/// How the worker keeps heavy shaders from stalling the GPU.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TilingConfig {
+    /// Frames are drawn as this many by this many tiles, one per step.
+    /// 1 draws the whole frame at once.
+    pub tiles_per_side: u32,
+    /// Renders at a lower resolution while frames take longer than this on the GPU.
+    pub time_budget: Option<Duration>,
+}
+
+impl Default for TilingConfig {
+    fn default() -> Self {
+        Self {
+            tiles_per_side: 1,
+            time_budget: None,
         }
-    };
+    }
+}
+
+/// Format of the texture shaders draw into, before it is tonemapped for display.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PreoutFormat {
+    #[default]
+    Rgba8Unorm,
+    Rgba16Float,
+    Rgba32Float,
+}
+
+impl PreoutFormat {
+    pub const ALL: [PreoutFormat; 3] = [
+        PreoutFormat::Rgba8Unorm,
+        PreoutFormat::Rgba16Float,
+        PreoutFormat::Rgba32Float,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PreoutFormat::Rgba8Unorm => "RGBA8 Unorm",
+            PreoutFormat::Rgba16Float => "RGBA16 Float",
+            PreoutFormat::Rgba32Float => "RGBA32 Float",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+
+    pub fn texture_format(&self) -> TextureFormat {
+        match self {
+            PreoutFormat::Rgba8Unorm => TextureFormat::Rgba8Unorm,
+            PreoutFormat::Rgba16Float => TextureFormat::Rgba16Float,
+            PreoutFormat::Rgba32Float => TextureFormat::Rgba32Float,
+        }
+    }
 }
-channel_side! {SettingsReceivers, Receiver}
-channel_side! {SettingsSenders, Sender}
-
-pub fn create_pair() -> (SettingsSenders, SettingsReceivers) {
-    let (cfg_send, cfg_receive) = mpsc::channel::<ShaderConfig>();
-    let (hardware_send, hardware_receive) = mpsc::channel::<GPUAdapterInfo>();
-    let (output_tex_view_send, output_tex_view_receive) = mpsc::channel::<TextureView>();
-    let (preout_size_send, preout_size_receive) = mpsc::channel::<(u32, u32)>();
-    let (kill_send, kill_receive) = mpsc::channel::<()>();
-
-    (
-        SettingsSenders {
-            shader_content: cfg_send,
-            hardware: hardware_send,
-            output_texture_view: output_tex_view_send,
-            preout_size: preout_size_send,
-            kill: kill_send,
-        },
-        SettingsReceivers {
-            shader_content: cfg_receive,
-            hardware: hardware_receive,
-            output_texture_view: output_tex_view_receive,
-            preout_size: preout_size_receive,
-            kill: kill_receive,
-        },
-    )
+
+/// The curve that brings a frame's colors into the displayable 0 to 1 range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ToneMapper {
+    #[default]
+    Clamp,
+    Reinhard,
+    Aces,
+    /// Leaves colors as they are, for float outputs such as HDR exports.
+    None,
 }
 
-macro_rules! backtalk_side {
-    ($name:ident, $kind:ident) => {
-        pub struct $name {
-            pub render_success: $kind<()>,
+impl ToneMapper {
+    /// The curves for display, which leaves out `None`.
+    pub const ALL: [ToneMapper; 3] = [ToneMapper::Clamp, ToneMapper::Reinhard, ToneMapper::Aces];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneMapper::Clamp => "Linear Clamp",
+            ToneMapper::Reinhard => "Reinhard",
+            ToneMapper::Aces => "ACES",
+            ToneMapper::None => "None",
         }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .chain([ToneMapper::None])
+            .find(|mapper| mapper.name() == name)
+    }
+}
+
+/// How a frame is brought to the output view.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tonemapping {
+    pub mapper: ToneMapper,
+    /// In stops: every 1 doubles the brightness before the curve.
+    pub exposure: f32,
+}
+
+impl Tonemapping {
+    /// Copies frames over untouched.
+    pub const NONE: Tonemapping = Tonemapping {
+        mapper: ToneMapper::None,
+        exposure: 0.0,
     };
 }
-backtalk_side! {BacktalkReceivers, Receiver}
-backtalk_side! {BacktalkSenders, Sender}
-
-pub fn create_backtalk_pair() -> (BacktalkSenders, BacktalkReceivers) {
-    let (render_send, render_recv) = mpsc::channel::<()>();
-
-    (
-        BacktalkSenders {
-            render_success: render_send,
-        },
-        BacktalkReceivers {
-            render_success: render_recv,
-        },
-    )
+
+pub enum WorkerCommand {
+    /// Its sequence number identifies the shader revision in later events.
+    SetShader(ShaderConfig),
+    SetHardware(GPUAdapterInfo),
+    SetOutputView(TextureView),
+    SetOutputFormat(TextureFormat),
+    SetPreoutSize((u32, u32)),
+    /// While animating, this is where the clock continues from.
+    SetTimeMs(u32),
+    SetAnimating(bool),
+    SetTiling(TilingConfig),
+    SetPreoutFormat(PreoutFormat),
+    SetTonemapping(Tonemapping),
+    SetRenderNotifier(RenderNotifier),
+    Kill,
+}
+
+/// Timings of one worker step. Stages the step did not go through are `None`.
+#[derive(Clone, Debug, Default)]
+pub struct WorkerStats {
+    /// Wall time of the whole step.
+    pub step_time: Duration,
+    pub compile_time: Option<Duration>,
+    pub pipeline_time: Option<Duration>,
+    /// CPU time spent recording and submitting the frame's commands.
+    pub encode_time: Option<Duration>,
+    /// Time the shader's pass took on the GPU. Needs `Features::TIMESTAMP_QUERY`.
+    pub gpu_time: Option<Duration>,
+    /// Time since the previous frame, while animating.
+    pub frame_interval: Option<Duration>,
+    /// Time the GPU took for all tiles of a frame, set when its last tile is drawn.
+    pub frame_time: Option<Duration>,
+    /// Fraction of the requested size the frame was rendered at.
+    pub render_scale: Option<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub enum WorkerEvent {
+    CompileStarted {
+        shader_seq: Seq,
+    },
+    /// `error` holds the compiler's message when the shader did not compile.
+    CompileFinished {
+        shader_seq: Seq,
+        error: Option<String>,
+    },
+    /// A frame is in the output view last sent.
+    RenderDone {
+        shader_seq: Seq,
+        time_ms: u32,
+    },
+    /// A wgpu error nothing else caught, such as a validation error.
+    Error(String),
+    Stats(WorkerStats),
+    /// The device stopped working, e.g. after the driver reset it. The worker
+    /// renders nothing more until it gets new hardware.
+    DeviceLost(String),
+}
+
+pub type CommandSender = async_std::channel::Sender<Sequenced<WorkerCommand>>;
+/// Asynchronous, so the worker can sleep until a command arrives.
+pub type CommandReceiver = async_std::channel::Receiver<Sequenced<WorkerCommand>>;
+pub type EventSender = Sender<Sequenced<WorkerEvent>>;
+pub type EventReceiver = Receiver<Sequenced<WorkerEvent>>;
+
+pub fn create_command_pair() -> (CommandSender, CommandReceiver) {
+    async_std::channel::unbounded()
+}
+
+pub fn create_event_pair() -> (EventSender, EventReceiver) {
+    mpsc::channel()
 }
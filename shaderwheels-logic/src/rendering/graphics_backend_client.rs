@@ -1,43 +1,112 @@
-use std::sync::mpsc::Sender;
+use std::{cell::Cell, collections::VecDeque};
 
-use wgpu::TextureView;
+use wgpu::{TextureFormat, TextureView};
 
 use crate::rendering::{
-    communication::{BacktalkReceivers, SettingsSenders, create_backtalk_pair, create_pair},
+    communication::{
+        CommandSender, EventReceiver, PreoutFormat, RenderNotifier, Seq, Sequenced, TilingConfig,
+        Tonemapping, WorkerCommand, WorkerEvent, WorkerStats, create_command_pair,
+        create_event_pair,
+    },
     graphics_backend_worker::{self, Worker},
     shader_config::{GPUAdapterInfo, ShaderConfig},
 };
 
+/// How many steps of stats the client keeps.
+pub const STATS_HISTORY: usize = 240;
+/// How many worker errors the client keeps, as a broken shader can cause one per frame.
+pub const ERROR_HISTORY: usize = 64;
+
 struct LocalSettings {
     preout_size: Option<(u32, u32)>,
     shader_cfg: ShaderConfig,
+    animating: bool,
+    tiling: TilingConfig,
+    preout_format: PreoutFormat,
+    tonemapping: Tonemapping,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum CompileState {
+    #[default]
+    Idle,
+    Compiling(Seq),
+    Finished {
+        shader_seq: Seq,
+        error: Option<String>,
+    },
+}
+
+/// Which shader revision, at which time, a rendered frame shows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameInfo {
+    pub shader_seq: Seq,
+    pub time_ms: u32,
+}
+
+/// What the client has heard back from the worker.
+#[derive(Default)]
+struct WorkerState {
+    compile: CompileState,
+    ready_frame: Option<FrameInfo>,
+    presented_frame: Option<FrameInfo>,
+    errors: Vec<String>,
+    stats: VecDeque<WorkerStats>,
+    device_lost: Option<String>,
 }
 
 pub struct GraphicsClient {
     // Inputs
-    senders: SettingsSenders,
+    commands: CommandSender,
+    next_seq: Cell<Seq>,
+    shader_seq: Seq,
     local_settings: LocalSettings,
-    receivers: BacktalkReceivers,
+    events: EventReceiver,
+    worker_state: WorkerState,
 }
 
 impl GraphicsClient {
     pub fn new(shader_cfg: ShaderConfig) -> Self {
-        let (sends, recvs) = create_pair();
-        let (b_sends, b_recvs) = create_backtalk_pair();
+        let (command_send, command_recv) = create_command_pair();
+        let (event_send, event_recv) = create_event_pair();
 
-        let worker = Worker::new(recvs, b_sends);
+        let worker = Worker::new(command_recv, event_send);
         worker.start_in_background();
 
-        let _ = sends.shader_content.send(shader_cfg.clone());
-
-        GraphicsClient {
-            senders: sends,
+        let mut client = GraphicsClient {
+            commands: command_send,
+            next_seq: Cell::new(1),
+            shader_seq: 0,
             local_settings: LocalSettings {
                 preout_size: None,
                 shader_cfg: shader_cfg,
+                animating: false,
+                tiling: TilingConfig::default(),
+                preout_format: PreoutFormat::default(),
+                tonemapping: Tonemapping::default(),
             },
-            receivers: b_recvs,
-        }
+            events: event_recv,
+            worker_state: WorkerState::default(),
+        };
+        client.send_shader();
+        client
+    }
+
+    /// Numbers `command` and hands it to the worker, which wakes up for it.
+    fn send(&self, command: WorkerCommand) -> Seq {
+        let seq = self.next_seq.get();
+        self.next_seq.set(seq + 1);
+        let _ = self.commands.try_send(Sequenced {
+            seq,
+            message: command,
+        });
+        seq
+    }
+
+    fn send_shader(&mut self) {
+        self.shader_seq = self.send(WorkerCommand::SetShader(
+            self.local_settings.shader_cfg.clone(),
+        ));
     }
 
     pub fn get_preout_size(&self) -> Option<(u32, u32)> {
@@ -47,33 +116,193 @@ impl GraphicsClient {
     pub fn set_preout_size(&mut self, size: (u32, u32)) {
         if self.local_settings.preout_size != Some(size) {
             self.local_settings.preout_size = Some(size);
-            let _ = self.senders.preout_size.send(size);
+            self.send(WorkerCommand::SetPreoutSize(size));
+        }
+    }
+
+    /// Sets the time shaders see in `size.z`, in milliseconds.
+    /// While animating, this is where the clock continues from.
+    pub fn set_time_ms(&self, time_ms: u32) {
+        self.send(WorkerCommand::SetTimeMs(time_ms));
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.local_settings.animating
+    }
+
+    /// Lets the time advance on its own, rendering a frame whenever an output
+    /// view is available. Without this the worker only renders on changes.
+    pub fn set_animating(&mut self, animating: bool) {
+        if self.local_settings.animating != animating {
+            self.local_settings.animating = animating;
+            self.send(WorkerCommand::SetAnimating(animating));
+        }
+    }
+
+    pub fn tiling(&self) -> TilingConfig {
+        self.local_settings.tiling
+    }
+
+    pub fn set_tiling(&mut self, tiling: TilingConfig) {
+        if self.local_settings.tiling != tiling {
+            self.local_settings.tiling = tiling;
+            self.send(WorkerCommand::SetTiling(tiling));
+        }
+    }
+
+    pub fn preout_format(&self) -> PreoutFormat {
+        self.local_settings.preout_format
+    }
+
+    /// Float formats keep colors above 1 until tonemapping. Compute shaders have
+    /// to declare their storage texture in the same format.
+    pub fn set_preout_format(&mut self, format: PreoutFormat) {
+        if self.local_settings.preout_format != format {
+            self.local_settings.preout_format = format;
+            self.send(WorkerCommand::SetPreoutFormat(format));
         }
     }
 
+    pub fn tonemapping(&self) -> Tonemapping {
+        self.local_settings.tonemapping
+    }
+
+    pub fn set_tonemapping(&mut self, tonemapping: Tonemapping) {
+        if self.local_settings.tonemapping != tonemapping {
+            self.local_settings.tonemapping = tonemapping;
+            self.send(WorkerCommand::SetTonemapping(tonemapping));
+        }
+    }
+
+    pub fn set_render_notifier(&self, notifier: impl Fn() + Send + 'static) {
+        let notifier: RenderNotifier = Box::new(notifier);
+        self.send(WorkerCommand::SetRenderNotifier(notifier));
+    }
+
     pub fn set_output_view(&self, output_view: TextureView) {
-        let _ = self.senders.output_texture_view.send(output_view);
+        self.send(WorkerCommand::SetOutputView(output_view));
+    }
+
+    /// The format of the views given to [`Self::set_output_view`], which is
+    /// `Rgba8Unorm` until set.
+    pub fn set_output_format(&self, format: TextureFormat) {
+        self.send(WorkerCommand::SetOutputFormat(format));
     }
 
     pub fn set_hardware(&self, hw: GPUAdapterInfo) {
-        let _ = self.senders.hardware.send(hw);
+        self.send(WorkerCommand::SetHardware(hw));
+    }
+
+    /// Moves the worker onto `hw` after the device was lost. It rebuilds
+    /// everything from the settings it already has, but needs a new output
+    /// view made on `hw` before it renders.
+    pub fn recreate_device(&mut self, hw: GPUAdapterInfo) {
+        self.worker_state = WorkerState {
+            stats: std::mem::take(&mut self.worker_state.stats),
+            ..Default::default()
+        };
+        self.set_hardware(hw);
     }
 
     pub fn set_shader_text(&mut self, text: String) {
         self.local_settings.shader_cfg.content = text;
-        let _ = self
-            .senders
-            .shader_content
-            .send(self.local_settings.shader_cfg.clone());
+        self.send_shader();
+    }
+
+    pub fn set_shader_config(&mut self, cfg: ShaderConfig) {
+        self.local_settings.shader_cfg = cfg;
+        self.send_shader();
+    }
+
+    /// The sequence number of the shader sent last, as events refer to it.
+    pub fn shader_seq(&self) -> Seq {
+        self.shader_seq
+    }
+
+    /// Takes in everything the worker has reported since the last call.
+    pub fn poll_events(&mut self) {
+        let state = &mut self.worker_state;
+        while let Ok(Sequenced { message, .. }) = self.events.try_recv() {
+            match message {
+                WorkerEvent::CompileStarted { shader_seq } => {
+                    state.compile = CompileState::Compiling(shader_seq)
+                }
+                WorkerEvent::CompileFinished { shader_seq, error } => {
+                    state.compile = CompileState::Finished { shader_seq, error }
+                }
+                WorkerEvent::RenderDone {
+                    shader_seq,
+                    time_ms,
+                } => {
+                    state.ready_frame = Some(FrameInfo {
+                        shader_seq,
+                        time_ms,
+                    })
+                }
+                WorkerEvent::Error(e) => {
+                    if state.errors.len() == ERROR_HISTORY {
+                        state.errors.remove(0);
+                    }
+                    state.errors.push(e);
+                }
+                WorkerEvent::Stats(stats) => {
+                    if state.stats.len() == STATS_HISTORY {
+                        state.stats.pop_front();
+                    }
+                    state.stats.push_back(stats);
+                }
+                WorkerEvent::DeviceLost(reason) => state.device_lost = Some(reason),
+            }
+        }
     }
 
+    /// True once a new frame is in the output view, which then counts as presented.
     pub fn get_should_swap(&mut self) -> bool {
-        self.receivers.render_success.try_recv().is_ok()
+        self.poll_events();
+        match self.worker_state.ready_frame.take() {
+            Some(frame) => {
+                self.worker_state.presented_frame = Some(frame);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn presented_frame(&self) -> Option<FrameInfo> {
+        self.worker_state.presented_frame
+    }
+
+    /// Whether the presented frame comes from the shader sent last.
+    pub fn is_frame_current(&self) -> bool {
+        self.presented_frame()
+            .is_some_and(|frame| frame.shader_seq == self.shader_seq)
+    }
+
+    pub fn compile_state(&self) -> &CompileState {
+        &self.worker_state.compile
+    }
+
+    /// Errors the worker ran into outside of compiling, oldest first.
+    pub fn errors(&self) -> &[String] {
+        &self.worker_state.errors
+    }
+
+    pub fn take_errors(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.worker_state.errors)
+    }
+
+    /// Stats of the most recent steps that compiled or rendered, oldest first.
+    pub fn stats_history(&self) -> &VecDeque<WorkerStats> {
+        &self.worker_state.stats
+    }
+
+    pub fn device_lost(&self) -> Option<&str> {
+        self.worker_state.device_lost.as_deref()
     }
 }
 
 impl Drop for GraphicsClient {
     fn drop(&mut self) {
-        let _ = self.senders.kill.send(());
+        self.send(WorkerCommand::Kill);
     }
 }
@@ -1,24 +1,44 @@
-use std::sync::mpsc::Receiver;
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
 
 use cardigan_incremental::{ReceivedVersioned, Versioned, VersionedInputs, memoized};
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen_futures::wasm_bindgen::prelude::Closure;
+use web_time::Instant;
 use wgpu::{TextureFormat, TextureView};
 
 use crate::rendering::{
-    communication::{BacktalkSenders, SettingsReceivers},
+    communication::{
+        CommandReceiver, EventSender, PreoutFormat, RenderNotifier, Seq, Sequenced, TilingConfig,
+        Tonemapping, WorkerCommand, WorkerEvent, WorkerStats,
+    },
     graphics_backend_worker::{
         compute_worker::ComputeWorkerPart,
         fragment_worker::FragmentWorkerPart,
-        shared::{blitter, module_comp, BackendWorker},
+        shared::{blitter, module_comp, tonemap_pipeline, BackendWorker, Rendered, TonemapBlitter},
+    },
+    shader_config::{
+        DeviceEvent, DeviceListenerGuard, GPUAdapterInfo, ShaderConfig, ShaderLanguage,
     },
-    shader_config::{GPUAdapterInfo, ShaderConfig, ShaderLanguage}, WGSL_ENTRY,
 };
 
 mod compute_worker;
 mod fragment_worker;
+mod gpu_timer;
 mod shared;
 
+/// How often the clock ticks while animating.
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+/// The lowest resolution the time budget scales down to.
+const MIN_RENDER_SCALE: f32 = 0.25;
+/// How much the resolution changes per frame over or well under budget.
+const RENDER_SCALE_STEP: f32 = 0.75;
+
 enum ArbitraryWorker {
     ComputeWorker(ComputeWorkerPart),
     FragmentWorker(FragmentWorkerPart),
@@ -28,24 +48,32 @@ impl BackendWorker for ArbitraryWorker {
     async fn step(
         &mut self,
         preout_size: &Versioned<(u32, u32)>,
+        time_ms: &Versioned<u32>,
         hardware: &Versioned<&GPUAdapterInfo>,
         module: &Versioned<&wgpu::ShaderModule>,
         entry_point: &Versioned<&String>,
-        blitter: &Versioned<&wgpu::util::TextureBlitter>,
+        preout_format: &Versioned<TextureFormat>,
+        blitter: &Versioned<&TonemapBlitter>,
         render_output_on_invalidated: bool,
         output_view: &Option<&TextureView>,
-    ) -> bool {
+        tiles_per_side: u32,
+        stats: &mut WorkerStats,
+    ) -> Rendered {
         match self {
             ArbitraryWorker::ComputeWorker(compute_worker_part) => {
                 compute_worker_part
                     .step(
                         preout_size,
+                        time_ms,
                         hardware,
                         module,
                         entry_point,
+                        preout_format,
                         blitter,
                         render_output_on_invalidated,
                         output_view,
+                        tiles_per_side,
+                        stats,
                     )
                     .await
             }
@@ -53,12 +81,16 @@ impl BackendWorker for ArbitraryWorker {
                 fragment_worker_part
                     .step(
                         preout_size,
+                        time_ms,
                         hardware,
                         module,
                         entry_point,
+                        preout_format,
                         blitter,
                         render_output_on_invalidated,
                         output_view,
+                        tiles_per_side,
+                        stats,
                     )
                     .await
             }
@@ -66,15 +98,52 @@ impl BackendWorker for ArbitraryWorker {
     }
 }
 
-fn latest_from_receiver<T>(recvr: &Receiver<T>) -> Option<T> {
-    if let Ok(mut val) = recvr.try_recv() {
-        while let Ok(new_val) = recvr.try_recv() {
-            val = new_val;
+/// Numbers events in the order they are sent. Shared with the device
+/// callbacks, which report from whichever thread wgpu calls them on.
+#[derive(Clone)]
+struct EventStream {
+    sender: EventSender,
+    next_seq: Arc<AtomicU64>,
+    notifier: Arc<Mutex<Option<RenderNotifier>>>,
+}
+
+impl EventStream {
+    /// Returns false once the client is gone.
+    fn send(&self, event: WorkerEvent) -> bool {
+        let seq = self.next_seq.fetch_add(1, Ordering::SeqCst) + 1;
+        self.sender
+            .send(Sequenced {
+                seq,
+                message: event,
+            })
+            .is_ok()
+    }
+
+    fn notify(&self) {
+        if let Some(notify) = &*self.notifier.lock().unwrap() {
+            notify();
         }
+    }
+}
+
+/// Advances the shader time while animating, from where it was when started.
+struct FrameClock {
+    started: Instant,
+    start_ms: u32,
+}
+
+impl FrameClock {
+    fn new(start_ms: u32) -> Self {
+        Self {
+            started: Instant::now(),
+            start_ms,
+        }
+    }
 
-        return Some(val);
+    fn now_ms(&self) -> u32 {
+        let elapsed = self.started.elapsed().as_millis().min(u32::MAX as u128) as u32;
+        self.start_ms.wrapping_add(elapsed)
     }
-    None
 }
 
 #[derive(Default)]
@@ -82,75 +151,267 @@ pub struct VersionedSettings {
     pub shader_text: Versioned<String>,
     pub append_environment: Versioned<bool>,
     pub shader_lang: Versioned<ShaderLanguage>,
+    pub entry_point: Versioned<String>,
     pub preout_size: Versioned<(u32, u32)>,
+    /// `preout_size` at the current render scale, which is what gets drawn.
+    pub scaled_preout_size: Versioned<(u32, u32)>,
+    pub time_ms: Versioned<u32>,
     pub hardware: Versioned<GPUAdapterInfo>,
     pub output_texture_view: Option<TextureView>,
     pub output_texture_format: Versioned<TextureFormat>,
+    pub preout_format: Versioned<TextureFormat>,
+    pub tonemapping: Versioned<Tonemapping>,
 }
 
 pub struct Worker {
-    settings_recvrs: SettingsReceivers,
-    backtalk_senders: BacktalkSenders,
+    commands: CommandReceiver,
+    events: EventStream,
     settings: VersionedSettings,
+    /// The command that set the current shader.
+    shader_seq: Seq,
+    /// The command that set the shader whose pass is in the output.
+    drawn_seq: Seq,
     backend: ArbitraryWorker,
 
     render_on_invalid: bool,
-
+    clock: Option<FrameClock>,
+    /// Where the clock resumes the next time animation starts.
+    paused_time_ms: u32,
+    last_frame: Option<Instant>,
+    tiling: TilingConfig,
+    render_scale: f32,
+    /// Set by the current device's lost callback, or once the device is replaced.
+    device_lost: Arc<AtomicBool>,
+    /// Keeps the worker listening to the current device's events.
+    device_listener: Option<DeviceListenerGuard>,
+
+    tonemap_pipeline: tonemap_pipeline,
     blitter: blitter,
     mod_comp: module_comp,
+    when_send_comp_started: VersionedInputs<3>,
     when_send_comp_errs: VersionedInputs<1>,
 }
 
 impl Worker {
-    pub fn new(recvs: SettingsReceivers, sends: BacktalkSenders) -> Self {
+    pub fn new(commands: CommandReceiver, events: EventSender) -> Self {
         Self {
-            settings_recvrs: recvs,
-            backtalk_senders: sends,
-            settings: VersionedSettings::default(),
+            commands,
+            events: EventStream {
+                sender: events,
+                next_seq: Default::default(),
+                notifier: Default::default(),
+            },
+            settings: VersionedSettings {
+                time_ms: Versioned::default().next(Some(0)),
+                output_texture_format: Versioned::default().next(Some(TextureFormat::Rgba8Unorm)),
+                preout_format: Versioned::default()
+                    .next(Some(PreoutFormat::default().texture_format())),
+                tonemapping: Versioned::default().next(Some(Tonemapping::default())),
+                ..Default::default()
+            },
+            shader_seq: 0,
+            drawn_seq: 0,
             backend: //ArbitraryWorker::ComputeWorker(ComputeWorkerPart::default()),
             ArbitraryWorker::FragmentWorker(FragmentWorkerPart::default()),
             render_on_invalid: true,
+            clock: None,
+            paused_time_ms: 0,
+            last_frame: None,
+            tiling: TilingConfig::default(),
+            render_scale: 1.0,
+            device_lost: Default::default(),
+            device_listener: None,
+            tonemap_pipeline: Default::default(),
             blitter: Default::default(),
             mod_comp: Default::default(),
+            when_send_comp_started: Default::default(),
             when_send_comp_errs: Default::default(),
         }
     }
 
-    fn read_recvrs(&mut self) {
-        if let Some(cfg) = latest_from_receiver(&self.settings_recvrs.shader_content) {
-            self.settings.shader_lang.set_to_next(Some(cfg.language));
-            self.settings
-                .shader_text
-                .set_to_next_if_unequal(Some(cfg.content));
-            // TODO: Update backend and append_env
+    /// Applies a command in the order it was sent. Returns false for `Kill`.
+    fn apply(&mut self, command: Sequenced<WorkerCommand>) -> bool {
+        match command.message {
+            WorkerCommand::SetShader(cfg) => {
+                self.shader_seq = command.seq;
+                self.settings.shader_lang.set_to_next(Some(cfg.language));
+                self.settings
+                    .shader_text
+                    .set_to_next_if_unequal(Some(cfg.content));
+                self.settings
+                    .entry_point
+                    .set_to_next_if_unequal(Some(cfg.entry_point));
+                // TODO: Update backend and append_env
+            }
+            WorkerCommand::SetHardware(hw) => self.set_hardware(hw),
+            WorkerCommand::SetOutputView(out_view) => {
+                self.settings.output_texture_view = Some(out_view)
+            }
+            WorkerCommand::SetOutputFormat(format) => self
+                .settings
+                .output_texture_format
+                .set_to_next_if_unequal(Some(format)),
+            WorkerCommand::SetPreoutSize(preout_size) => self
+                .settings
+                .preout_size
+                .set_to_next_if_unequal(Some(preout_size)),
+            WorkerCommand::SetTimeMs(time_ms) => {
+                self.settings.time_ms.set_to_next_if_unequal(Some(time_ms));
+                self.paused_time_ms = time_ms;
+                if self.clock.is_some() {
+                    self.clock = Some(FrameClock::new(time_ms));
+                }
+            }
+            WorkerCommand::SetAnimating(animating) => {
+                self.clock = match (animating, self.clock.take()) {
+                    (true, Some(clock)) => Some(clock),
+                    (true, None) => Some(FrameClock::new(self.paused_time_ms)),
+                    (false, Some(clock)) => {
+                        self.paused_time_ms = clock.now_ms();
+                        None
+                    }
+                    (false, None) => None,
+                };
+            }
+            WorkerCommand::SetTiling(tiling) => {
+                self.tiling = tiling;
+                if tiling.time_budget.is_none() {
+                    self.render_scale = 1.0;
+                }
+            }
+            WorkerCommand::SetPreoutFormat(format) => self
+                .settings
+                .preout_format
+                .set_to_next_if_unequal(Some(format.texture_format())),
+            WorkerCommand::SetTonemapping(tonemapping) => self
+                .settings
+                .tonemapping
+                .set_to_next_if_unequal(Some(tonemapping)),
+            WorkerCommand::SetRenderNotifier(notifier) => {
+                *self.events.notifier.lock().unwrap() = Some(notifier)
+            }
+            WorkerCommand::Kill => return false,
+        }
+        true
+    }
+
+    /// Switches to `hw` with nothing carried over from the previous device, so
+    /// everything is rebuilt from the current settings. Errors and loss of
+    /// the new device are reported as events instead of panicking.
+    fn set_hardware(&mut self, hw: GPUAdapterInfo) {
+        // Keeps the old device's callbacks quiet from here on.
+        self.device_lost.store(true, Ordering::SeqCst);
+        self.device_lost = Arc::new(AtomicBool::new(false));
+        self.drop_gpu_objects();
+
+        let events = self.events.clone();
+        let lost = self.device_lost.clone();
+        // Stops listening once the worker moves to another device or is
+        // dropped, the device is lost, or the client is gone.
+        self.device_listener = Some(hw.device_events.listen(move |event| {
+            if lost.load(Ordering::SeqCst) {
+                return false;
+            }
+            let sent = match event {
+                DeviceEvent::Error(e) => events.send(WorkerEvent::Error(e.clone())),
+                DeviceEvent::Lost(message) => {
+                    lost.store(true, Ordering::SeqCst);
+                    events.send(WorkerEvent::DeviceLost(message.clone()))
+                }
+            };
+            events.notify();
+            sent
+        }));
+
+        self.settings.hardware.set_to_next(Some(hw));
+    }
+
+    /// Drops every memoized object made on the device, so none of them
+    /// survive into the next one.
+    fn drop_gpu_objects(&mut self) {
+        self.tonemap_pipeline = Default::default();
+        self.blitter = Default::default();
+        self.mod_comp = Default::default();
+        self.backend = match self.backend {
+            ArbitraryWorker::ComputeWorker(_) => {
+                ArbitraryWorker::ComputeWorker(ComputeWorkerPart::default())
+            }
+            ArbitraryWorker::FragmentWorker(_) => {
+                ArbitraryWorker::FragmentWorker(FragmentWorkerPart::default())
+            }
+        };
+        self.settings.output_texture_view = None;
+    }
+
+    /// Applies every command that has arrived. Returns false once the worker should stop.
+    fn read_commands(&mut self) -> bool {
+        while let Ok(command) = self.commands.try_recv() {
+            if !self.apply(command) {
+                return false;
+            }
         }
 
-        if let Some(hw) = latest_from_receiver(&self.settings_recvrs.hardware) {
-            self.settings.hardware.set_to_next(Some(hw));
+        if self.device_lost.load(Ordering::SeqCst) && self.settings.hardware.get_value().is_some() {
+            self.drop_gpu_objects();
+            self.settings.hardware.set_to_next(None);
         }
 
-        if let Some(out_view) = latest_from_receiver(&self.settings_recvrs.output_texture_view) {
-            self.settings.output_texture_view = Some(out_view);
+        if let Some(clock) = &self.clock {
+            self.settings
+                .time_ms
+                .set_to_next_if_unequal(Some(clock.now_ms()));
         }
+        self.update_scaled_size();
+        true
+    }
 
-        if let Some(preout_size) = latest_from_receiver(&self.settings_recvrs.preout_size) {
+    fn update_scaled_size(&mut self) {
+        if let Some((width, height)) = *self.settings.preout_size.get_value() {
+            let scale = |side: u32| ((side as f32 * self.render_scale) as u32).max(1);
+            let scaled = (scale(width), scale(height));
             self.settings
-                .preout_size
-                .set_to_next_if_unequal(Some(preout_size));
+                .scaled_preout_size
+                .set_to_next_if_unequal(Some(scaled));
         }
+    }
 
-        self.settings.output_texture_format =
-            Versioned::default().next(Some(TextureFormat::Rgba8Unorm));
+    /// Lowers the resolution while frames go over the time budget, and raises
+    /// it again once they are well under it.
+    fn adjust_render_scale(&mut self, frame_time: Duration) {
+        let Some(budget) = self.tiling.time_budget else {
+            return;
+        };
+        if frame_time > budget {
+            self.render_scale = (self.render_scale * RENDER_SCALE_STEP).max(MIN_RENDER_SCALE);
+        } else if frame_time < budget / 2 {
+            self.render_scale = (self.render_scale / RENDER_SCALE_STEP).min(1.0);
+        }
     }
 
     async fn longrunning_task(mut self) {
-        loop {
-            Self::pacing_fn().await;
+        while self.read_commands() {
+            self.step().await;
 
-            if let Ok(()) = self.settings_recvrs.kill.try_recv() {
+            if !self.wait_for_command().await {
                 return;
             }
-            self.step().await
+        }
+    }
+
+    /// Sleeps until the client sends a command or, while animating, the next
+    /// frame is due. Returns false once the client is gone.
+    async fn wait_for_command(&mut self) -> bool {
+        let received = match self.clock {
+            Some(_) => match async_std::future::timeout(FRAME_INTERVAL, self.commands.recv()).await
+            {
+                Ok(received) => received,
+                Err(_) => return true,
+            },
+            None => self.commands.recv().await,
+        };
+        match received {
+            Ok(command) => self.apply(command),
+            Err(_) => false,
         }
     }
 
@@ -166,22 +427,24 @@ impl Worker {
         });
     }
 
-    #[cfg(target_arch = "wasm32")]
-    pub async fn pacing_fn() {
-        use std::time::Duration;
-
-        async_std::task::sleep(Duration::from_millis(16u64)).await;
-        //let _ = wasm_timer::Delay::new(Duration::from_millis(16u64)).await;
-    }
-
-    #[cfg(not(target_arch = "wasm32"))]
-    pub async fn pacing_fn() {}
-
     async fn step(&mut self) {
-        self.read_recvrs();
-
+        let started = Instant::now();
+        let mut stats = WorkerStats::default();
         let hardware = self.settings.hardware.my_as_ref();
 
+        if hardware.get_value().is_some()
+            && self.when_send_comp_started.check_and_update(&[
+                *self.settings.shader_text.version(),
+                *self.settings.shader_lang.version(),
+                *hardware.version(),
+            ])
+        {
+            self.events.send(WorkerEvent::CompileStarted {
+                shader_seq: self.shader_seq,
+            });
+        }
+
+        let compile_started = Instant::now();
         let module = self
             .mod_comp
             .compute(
@@ -196,7 +459,16 @@ impl Worker {
             .when_send_comp_errs
             .check_and_update(&[*module.version()])
         {
-            // TODO: Send compilation results
+            if let Some(res) = module.get_value() {
+                stats.compile_time = Some(compile_started.elapsed());
+                self.events.send(WorkerEvent::CompileFinished {
+                    shader_seq: self.shader_seq,
+                    error: match res {
+                        Ok(_) => None,
+                        Err(e) => Some(e.to_string()),
+                    },
+                });
+            }
         }
 
         let successful_module = module.map(|f| match f {
@@ -204,29 +476,63 @@ impl Worker {
             _ => None,
         });
 
+        let tonemap = self
+            .tonemap_pipeline
+            .compute(&hardware, &self.settings.output_texture_format)
+            .await
+            .my_as_ref();
         let blit = self
             .blitter
-            .compute(&hardware, &self.settings.output_texture_format)
+            .compute(&hardware, &tonemap, &self.settings.tonemapping)
             .await
             .my_as_ref();
 
-        let rerendered = self
+        let rendered = self
             .backend
             .step(
-                &self.settings.preout_size,
+                &self.settings.scaled_preout_size,
+                &self.settings.time_ms,
                 &self.settings.hardware.my_as_ref(),
                 &successful_module,
-                &Versioned::default().next(Some(&WGSL_ENTRY.to_string())),
+                &self.settings.entry_point.my_as_ref(),
+                &self.settings.preout_format,
                 &blit,
                 self.render_on_invalid,
                 &self.settings.output_texture_view.as_ref(),
+                self.tiling.tiles_per_side,
+                &mut stats,
             )
             .await;
 
+        if let Some(frame_time) = stats.frame_time {
+            stats.render_scale = Some(self.render_scale);
+            self.adjust_render_scale(frame_time);
+        }
+
+        // A shader that failed to compile draws nothing, so the output still
+        // shows the pass of an earlier one.
+        if rendered == Rendered::Pass {
+            self.drawn_seq = self.shader_seq;
+        }
+        let rerendered = rendered != Rendered::Nothing;
         if rerendered {
-            // TODO: Send render notif
             self.settings.output_texture_view = None;
-            let _ = self.backtalk_senders.render_success.send(());
+            self.events.send(WorkerEvent::RenderDone {
+                shader_seq: self.drawn_seq,
+                time_ms: self.settings.time_ms.get_value().unwrap_or(0),
+            });
+            self.events.notify();
+
+            let now = Instant::now();
+            if self.clock.is_some() {
+                stats.frame_interval = self.last_frame.map(|last| now - last);
+            }
+            self.last_frame = Some(now);
+        }
+
+        if rerendered || stats.compile_time.is_some() || stats.pipeline_time.is_some() {
+            stats.step_time = started.elapsed();
+            self.events.send(WorkerEvent::Stats(stats));
         }
     }
 }
@@ -1,13 +1,20 @@
+use std::time::Duration;
+
 use cardigan_incremental::{GeneralVersionedComp, Versioned, VersionedInputs, memoized};
 use wgpu::{
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline,
-    ComputePipelineDescriptor, PipelineLayout, ShaderModule, ShaderStages, Surface, TextureView,
-    util::TextureBlitter,
+    BindGroupLayoutEntry, Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor,
+    ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, PipelineLayout,
+    ShaderModule, ShaderStages, Surface, TextureFormat, TextureView,
 };
 
+use web_time::Instant;
+
+use crate::rendering::communication::WorkerStats;
+use crate::rendering::graphics_backend_worker::gpu_timer::{GpuTimer, gpu_timer};
 use crate::rendering::graphics_backend_worker::shared::{
-    BackendWorker, pipeline_layout, preoutput_texture_view,
+    BackendWorker, Rendered, Tile, TileProgress, TonemapBlitter, pipeline_layout,
+    preoutput_texture_view, wait_for_queue,
 };
 use crate::rendering::shader_config::GPUAdapterInfo;
 
@@ -26,7 +33,7 @@ async fn pipeline(
         module: module,
         entry_point: Some(entry_point.as_ref()),
         compilation_options: comp_opts,
-        cache: None,
+        cache: hardware.pipeline_cache.as_ref(),
     };
 
     let pipeline = hardware.deviceref.create_compute_pipeline(&descriptor);
@@ -34,55 +41,86 @@ async fn pipeline(
 }
 
 #[memoized]
-async fn bind_group_layout(hardware: &GPUAdapterInfo) -> BindGroupLayout {
+async fn bind_group_layout(hardware: &GPUAdapterInfo, format: TextureFormat) -> BindGroupLayout {
     let layout = hardware
         .deviceref
         .create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("bind group layout"),
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    access: wgpu::StorageTextureAccess::WriteOnly,
-                    format: wgpu::TextureFormat::Rgba8Unorm,
-                    view_dimension: wgpu::TextureViewDimension::D2,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
         });
     layout
 }
 
+/// Where the tile being drawn starts (`xy`) and the size of the whole frame
+/// (`zw`). Shaders add `xy` to their invocation id to find their pixel.
+#[memoized]
+async fn tile_buffer(hardware: &GPUAdapterInfo) -> Buffer {
+    hardware.deviceref.create_buffer(&BufferDescriptor {
+        label: Some("Tile Uniform Buffer"),
+        size: 16,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
 #[memoized]
 async fn bind_group(
     hardware: &GPUAdapterInfo,
     bgl: &BindGroupLayout,
     preout_view: &TextureView,
+    tile_buffer: &Buffer,
 ) -> BindGroup {
     let bg = hardware.deviceref.create_bind_group(&BindGroupDescriptor {
         label: Some("Bind group!"),
         layout: bgl,
-        entries: &[BindGroupEntry {
-            binding: 0,
-            resource: wgpu::BindingResource::TextureView(preout_view),
-        }],
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(preout_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: tile_buffer.as_entire_binding(),
+            },
+        ],
     });
     bg
 }
 
 async fn recompute_preout_fn(
     pipeline: Option<&ComputePipeline>,
-    preout_view_size: &Option<(u32, u32)>,
     bind_group: Option<&BindGroup>,
+    timer: Option<&GpuTimer>,
+    tile: Tile,
     encoder: &mut wgpu::CommandEncoder,
 ) -> Option<()> {
-    let preout_view_size = preout_view_size.as_ref()?;
     let bind_group = bind_group?;
     let pipeline = pipeline?;
 
     let compute_pass_descriptor = ComputePassDescriptor {
         label: Some("Compute Pass!"),
-        timestamp_writes: None,
+        timestamp_writes: timer.map(GpuTimer::compute_pass_writes),
     };
 
     let mut compute_pass = encoder.begin_compute_pass(&compute_pass_descriptor);
@@ -91,10 +129,7 @@ async fn recompute_preout_fn(
     // TODO: Bind more groups
     compute_pass.set_bind_group(0, bind_group, &[]);
 
-    let workgroup_counts = (
-        preout_view_size.0.div_ceil(16u32),
-        preout_view_size.1.div_ceil(16u32),
-    );
+    let workgroup_counts = (tile.width.div_ceil(16u32), tile.height.div_ceil(16u32));
 
     compute_pass.dispatch_workgroups(workgroup_counts.0, workgroup_counts.1, 1);
 
@@ -105,18 +140,23 @@ async fn render_output(
     hardware: Option<&GPUAdapterInfo>,
     bg: Option<&BindGroup>,
     pipeline: Option<&ComputePipeline>,
-    blitter: Option<&TextureBlitter>,
+    blitter: Option<&TonemapBlitter>,
     output_view: Option<&TextureView>,
     preout_view_size: &Option<(u32, u32)>,
     preout_view: Option<&TextureView>,
-    recompute_preout: bool,
-    rerender_out: bool,
-) -> Option<()> {
+    tile_buffer: Option<&Buffer>,
+    timer: Option<&GpuTimer>,
+    tile: Option<Tile>,
+    stats: &mut WorkerStats,
+) -> Option<(Duration, bool)> {
     let hardware = hardware.as_ref()?;
     let blitter = blitter.as_ref()?;
     let output_view = output_view.as_ref()?;
     let preout_view = preout_view.as_ref()?;
+    let preout_view_size = preout_view_size.as_ref()?;
+    let tile_buffer = tile_buffer?;
 
+    let encode_started = Instant::now();
     let encoder_descriptor = CommandEncoderDescriptor {
         label: Some("Command Encoder Descriptor"),
     };
@@ -124,17 +164,33 @@ async fn render_output(
         .deviceref
         .create_command_encoder(&encoder_descriptor);
 
-    if recompute_preout {
-        recompute_preout_fn(pipeline, preout_view_size, bg, &mut encoder).await;
+    let mut drew = false;
+    if let Some(tile) = tile {
+        hardware.queueref.write_buffer(
+            tile_buffer,
+            0,
+            bytemuck::bytes_of(&[tile.x, tile.y, preout_view_size.0, preout_view_size.1]),
+        );
+        drew = recompute_preout_fn(pipeline, bg, timer, tile, &mut encoder)
+            .await
+            .is_some();
     }
-
-    if rerender_out {
-        blitter.copy(&hardware.deviceref, &mut encoder, preout_view, output_view);
+    let timed = timer.filter(|_| drew);
+    if let Some(timer) = timed {
+        timer.resolve(&mut encoder);
     }
 
+    blitter.copy(&hardware.deviceref, &mut encoder, preout_view, output_view);
+
     hardware.queueref.submit([encoder.finish()]);
+    stats.encode_time = Some(encode_started.elapsed());
+    let queue_time = wait_for_queue(hardware).await;
 
-    Some(())
+    if let Some(timer) = timed {
+        stats.gpu_time = timer.read(hardware).await;
+    }
+
+    Some((queue_time, drew))
 }
 
 pub struct GPUExactSurface<'a> {
@@ -147,9 +203,16 @@ pub struct ComputeWorkerPart {
     pll: pipeline_layout,
     pl: pipeline,
     bgl: bind_group_layout,
+    tb: tile_buffer,
     bg: bind_group,
+    timer: gpu_timer,
     preout_comp: GeneralVersionedComp<4>,
     rendered_comp: VersionedInputs<2>,
+    pipeline_seen: VersionedInputs<1>,
+    target_seen: VersionedInputs<1>,
+    tiles: TileProgress,
+    /// The size of the frame being drawn, kept until it is done.
+    frame_size: Versioned<(u32, u32)>,
 }
 
 impl ComputeWorkerPart {
@@ -162,13 +225,17 @@ impl BackendWorker for ComputeWorkerPart {
     async fn step(
         &mut self,
         preout_size: &Versioned<(u32, u32)>,
+        _time_ms: &Versioned<u32>,
         hardware: &Versioned<&GPUAdapterInfo>,
         module: &Versioned<&ShaderModule>,
         entry_point: &Versioned<&String>,
-        blitter: &Versioned<&TextureBlitter>,
+        preout_format: &Versioned<TextureFormat>,
+        blitter: &Versioned<&TonemapBlitter>,
         render_output_on_invalidated: bool,
         output_view: &Option<&TextureView>,
-    ) -> bool {
+        tiles_per_side: u32,
+        stats: &mut WorkerStats,
+    ) -> Rendered {
         let uses = wgpu::TextureUsages::TEXTURE_BINDING
             | wgpu::TextureUsages::COPY_DST
             | wgpu::TextureUsages::STORAGE_BINDING;
@@ -176,59 +243,88 @@ impl BackendWorker for ComputeWorkerPart {
         let uses_vwrapped = Versioned::default();
         let uses_vwrapped = uses_vwrapped.next(Some(uses));
 
-        let bindgroup_lay = self.bgl.compute(hardware).await.my_as_ref();
+        let bindgroup_lay = self.bgl.compute(hardware, preout_format).await.my_as_ref();
         let pipeline_lay = self.pll.compute(hardware, &bindgroup_lay).await.my_as_ref();
         let preout_view = self
             .pov
-            .compute(preout_size, &uses_vwrapped, hardware)
+            .compute(preout_size, &uses_vwrapped, preout_format, hardware)
             .await
             .my_as_ref();
 
+        let tile_buf = self.tb.compute(hardware).await.my_as_ref();
+
         let bindgroup = self
             .bg
-            .compute(hardware, &bindgroup_lay, &preout_view)
+            .compute(hardware, &bindgroup_lay, &preout_view, &tile_buf)
             .await
             .my_as_ref();
 
+        let pipeline_started = Instant::now();
         let pipeline = self
             .pl
             .compute(hardware, &pipeline_lay, module, entry_point)
             .await
             .my_as_ref();
+        if self.pipeline_seen.check_and_update(&[*pipeline.version()])
+            && pipeline.get_value().is_some()
+        {
+            stats.pipeline_time = Some(pipeline_started.elapsed());
+        }
+
+        let timer = self.timer.compute(hardware).await.my_as_ref();
 
         // TODO: Refactor all this common shape.
         if render_output_on_invalidated && output_view.is_some() {
-            let recompute_preout = self.preout_comp.check_and_update(&[
+            // A new texture has none of the tiles drawn so far.
+            if self.target_seen.check_and_update(&[*preout_view.version()]) {
+                self.tiles.abandon_frame();
+            }
+            if self.preout_comp.check_and_update(&[
                 *preout_size.version(),
                 *pipeline.version(),
                 *bindgroup.version(),
                 *blitter.version(),
-            ]);
+            ]) {
+                self.tiles.request_frame();
+            }
+            if self.tiles.start_frame(tiles_per_side) {
+                self.frame_size
+                    .set_to_next_if_unequal(*preout_size.get_value());
+            }
+            let tile = (*self.frame_size.get_value()).and_then(|size| self.tiles.take(size));
 
             let rerender_out = self
                 .rendered_comp
                 .check_and_update(&[self.preout_comp.get_version(), *blitter.version()]);
 
-            if rerender_out || recompute_preout {
+            if rerender_out || tile.is_some() {
                 let res = render_output(
                     *hardware.get_value(),
                     *bindgroup.get_value(),
                     *pipeline.get_value(),
                     *blitter.get_value(),
                     *output_view,
-                    preout_size.get_value(),
+                    self.frame_size.get_value(),
                     *preout_view.get_value(),
-                    recompute_preout,
-                    rerender_out,
+                    *tile_buf.get_value(),
+                    (*timer.get_value()).and_then(|t| t.as_ref()),
+                    tile,
+                    stats,
                 )
                 .await;
 
                 match res {
-                    Some(_) => return true,
-                    None => return false,
+                    Some((queue_time, drew)) => {
+                        if tile.is_some() {
+                            self.tiles.add_gpu_time(queue_time);
+                            stats.frame_time = self.tiles.frame_time();
+                        }
+                        return if drew { Rendered::Pass } else { Rendered::Copy };
+                    }
+                    None => return Rendered::Nothing,
                 }
             }
         }
-        return false;
+        return Rendered::Nothing;
     }
 }
@@ -4,17 +4,23 @@ use wgpu::{
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
     BindGroupLayoutEntry, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline,
     ComputePipelineDescriptor, PipelineLayout, ShaderModule, ShaderStages, Surface, TextureView,
-    util::TextureBlitter,
 };
 use wgpu::{
-    BlendState, Buffer, BufferBinding, BufferUsages, Color, ColorTargetState, FragmentState,
-    MultisampleState, Operations, PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor,
-    RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, TextureFormat, VertexState,
+    Buffer, BufferBinding, BufferUsages, ColorTargetState, FragmentState, MultisampleState,
+    Operations, PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, TextureFormat, VertexState,
 };
 
+use std::time::Duration;
+
+use web_time::Instant;
+
 use crate::rendering::{DEFAULT_WGSL_VERT, WGSL_VERT_ENTRY};
+use crate::rendering::communication::WorkerStats;
+use crate::rendering::graphics_backend_worker::gpu_timer::{GpuTimer, gpu_timer};
 use crate::rendering::graphics_backend_worker::shared::{
-    BackendWorker, pipeline_layout, preoutput_texture_view,
+    BackendWorker, Rendered, Tile, TileProgress, TonemapBlitter, pipeline_layout,
+    preoutput_texture_view, wait_for_queue,
 };
 use crate::rendering::shader_config::GPUAdapterInfo;
 
@@ -38,7 +44,7 @@ async fn pipeline(
     let descriptor = RenderPipelineDescriptor {
         label: Some("Render Pipeline"),
         layout: Some(pipeline_layout),
-        cache: None,
+        cache: hardware.pipeline_cache.as_ref(),
         vertex: VertexState {
             module: vert_module,
             entry_point: Some(&vert_entry_point),
@@ -66,7 +72,8 @@ async fn pipeline(
             compilation_options: comp_opts,
             targets: &[Some(ColorTargetState {
                 format: output_format,
-                blend: Some(BlendState::REPLACE),
+                // Float32 formats can't be blended, and tiles overwrite anyway.
+                blend: None,
                 write_mask: wgpu::ColorWrites::ALL,
             })],
         }),
@@ -140,6 +147,7 @@ async fn populate_uniforms(
     hardware: &GPUAdapterInfo,
     bf: &Buffer,
     preout_view_size: (u32, u32),
+    time_ms: u32,
 ) -> () {
 
     let var_name = [preout_view_size.0, preout_view_size.1];
@@ -148,7 +156,7 @@ async fn populate_uniforms(
     hardware.queueref.write_buffer(
         bf,
         0,
-        bytemuck::bytes_of(&[preout_view_size.0, preout_view_size.1]),
+        bytemuck::bytes_of(&[preout_view_size.0, preout_view_size.1, time_ms, 0]),
     );
 }
 
@@ -156,6 +164,8 @@ async fn recompute_preout_fn(
     pipeline: Option<&RenderPipeline>,
     preout_view: Option<&TextureView>,
     bind_group: Option<&BindGroup>,
+    timer: Option<&GpuTimer>,
+    tile: Tile,
     encoder: &mut wgpu::CommandEncoder,
 ) -> Option<()> {
     let preout_view = preout_view?;
@@ -168,17 +178,20 @@ async fn recompute_preout_fn(
             view: preout_view,
             resolve_target: None,
             ops: Operations {
-                load: wgpu::LoadOp::Clear(Color::BLUE),
+                // Tiles drawn earlier stay, and so does the last frame until
+                // every tile is redrawn.
+                load: wgpu::LoadOp::Load,
                 store: wgpu::StoreOp::Store,
             },
         })],
         depth_stencil_attachment: None,
-        timestamp_writes: None,
+        timestamp_writes: timer.map(GpuTimer::render_pass_writes),
         occlusion_query_set: None,
     };
 
     let mut render_pass = encoder.begin_render_pass(&render_pass_descriptor);
     render_pass.set_pipeline(pipeline);
+    render_pass.set_scissor_rect(tile.x, tile.y, tile.width, tile.height);
 
     // TODO: Bind more groups
     render_pass.set_bind_group(0, bind_group, &[]);
@@ -192,20 +205,22 @@ async fn render_output(
     hardware: Option<&GPUAdapterInfo>,
     bg: Option<&BindGroup>,
     pipeline: Option<&RenderPipeline>,
-    blitter: Option<&TextureBlitter>,
+    blitter: Option<&TonemapBlitter>,
     output_view: Option<&TextureView>,
     preout_view_size: &Option<(u32, u32)>,
     preout_view: Option<&TextureView>,
     uniform_values: Option<()>,
-    recompute_preout: bool,
-    rerender_out: bool,
-) -> Option<()> {
+    timer: Option<&GpuTimer>,
+    tile: Option<Tile>,
+    stats: &mut WorkerStats,
+) -> Option<(Duration, bool)> {
     let hardware = hardware.as_ref()?;
     let blitter = blitter.as_ref()?;
     let output_view = output_view.as_ref()?;
     let preout_view = preout_view?;
     let _ = uniform_values?;
 
+    let encode_started = Instant::now();
     let encoder_descriptor = CommandEncoderDescriptor {
         label: Some("Command Encoder Descriptor"),
     };
@@ -213,17 +228,29 @@ async fn render_output(
         .deviceref
         .create_command_encoder(&encoder_descriptor);
 
-    if recompute_preout {
-        recompute_preout_fn(pipeline, Some(preout_view), bg, &mut encoder).await;
+    // Only a pass that was actually recorded has timestamps to read.
+    let mut drew = false;
+    if let Some(tile) = tile {
+        drew = recompute_preout_fn(pipeline, Some(preout_view), bg, timer, tile, &mut encoder)
+            .await
+            .is_some();
     }
-
-    if rerender_out {
-        blitter.copy(&hardware.deviceref, &mut encoder, preout_view, output_view);
+    let timed = timer.filter(|_| drew);
+    if let Some(timer) = timed {
+        timer.resolve(&mut encoder);
     }
 
+    blitter.copy(&hardware.deviceref, &mut encoder, preout_view, output_view);
+
     hardware.queueref.submit([encoder.finish()]);
+    stats.encode_time = Some(encode_started.elapsed());
+    let queue_time = wait_for_queue(hardware).await;
 
-    Some(())
+    if let Some(timer) = timed {
+        stats.gpu_time = timer.read(hardware).await;
+    }
+
+    Some((queue_time, drew))
 }
 
 #[derive(Default)]
@@ -236,21 +263,33 @@ pub struct FragmentWorkerPart {
     bf: unif_buffer,
     uv: populate_uniforms,
     bg: bind_group,
+    timer: gpu_timer,
     preout_comp: GeneralVersionedComp<5>,
     rendered_comp: VersionedInputs<2>,
+    pipeline_seen: VersionedInputs<1>,
+    target_seen: VersionedInputs<1>,
+    tiles: TileProgress,
+    /// The size and time of the frame being drawn, which the uniforms hold
+    /// until it is done.
+    frame_size: Versioned<(u32, u32)>,
+    frame_time_ms: Versioned<u32>,
 }
 
 impl BackendWorker for FragmentWorkerPart {
     async fn step(
         &mut self,
         preout_size: &Versioned<(u32, u32)>,
+        time_ms: &Versioned<u32>,
         hardware: &Versioned<&GPUAdapterInfo>,
         module: &Versioned<&ShaderModule>,
         entry_point: &Versioned<&String>,
-        blitter: &Versioned<&TextureBlitter>,
+        preout_format: &Versioned<TextureFormat>,
+        blitter: &Versioned<&TonemapBlitter>,
         render_output_on_invalidated: bool,
         output_view: &Option<&TextureView>,
-    ) -> bool {
+        tiles_per_side: u32,
+        stats: &mut WorkerStats,
+    ) -> Rendered {
         let uses = wgpu::TextureUsages::TEXTURE_BINDING
             | wgpu::TextureUsages::COPY_DST
             | wgpu::TextureUsages::RENDER_ATTACHMENT;
@@ -271,14 +310,11 @@ impl BackendWorker for FragmentWorkerPart {
             }
         }
 
-        let out_fmt = Versioned::default();
-        let out_fmt = out_fmt.next(Some(TextureFormat::Rgba8Unorm));
-
         let bindgroup_lay = self.bgl.compute(hardware).await.my_as_ref();
         let pipeline_lay = self.pll.compute(hardware, &bindgroup_lay).await.my_as_ref();
         let preout_view = self
             .pov
-            .compute(preout_size, &uses_vwrapped, hardware)
+            .compute(preout_size, &uses_vwrapped, preout_format, hardware)
             .await
             .my_as_ref();
 
@@ -290,6 +326,7 @@ impl BackendWorker for FragmentWorkerPart {
             .await
             .my_as_ref();
 
+        let pipeline_started = Instant::now();
         let pipeline = self
             .pl
             .compute(
@@ -297,55 +334,87 @@ impl BackendWorker for FragmentWorkerPart {
                 &pipeline_lay,
                 &self.vert.my_as_ref(),
                 module,
-                &out_fmt,
+                preout_format,
                 &vert_ep.my_as_ref(),
                 entry_point,
             )
             .await
             .my_as_ref();
+        if self.pipeline_seen.check_and_update(&[*pipeline.version()])
+            && pipeline.get_value().is_some()
+        {
+            stats.pipeline_time = Some(pipeline_started.elapsed());
+        }
+
+        let timer = self.timer.compute(hardware).await.my_as_ref();
 
         let safe_pipeline = pipeline.map(|f| match f {
             Some(Ok(p)) => Some(p),
         _ => None,
         });
 
-        let unif_vals = self.uv.compute(hardware, &bf, preout_size).await;
-
         // TODO: Refactor all this common shape, and fix (small) versioning bug.
         if render_output_on_invalidated && output_view.is_some() {
-            let recompute_preout = self.preout_comp.check_and_update(&[
+            // A new texture has none of the tiles drawn so far.
+            if self.target_seen.check_and_update(&[*preout_view.version()]) {
+                self.tiles.abandon_frame();
+            }
+            if self.preout_comp.check_and_update(&[
                 *preout_size.version(),
+                *time_ms.version(),
                 *pipeline.version(),
                 *bindgroup.version(),
                 *blitter.version(),
-                *unif_vals.version(),
-            ]);
+            ]) {
+                self.tiles.request_frame();
+            }
+            // Every tile of a frame has to show the same moment at the same size.
+            if self.tiles.start_frame(tiles_per_side) {
+                self.frame_size
+                    .set_to_next_if_unequal(*preout_size.get_value());
+                self.frame_time_ms
+                    .set_to_next_if_unequal(*time_ms.get_value());
+            }
+            let unif_vals = self
+                .uv
+                .compute(hardware, &bf, &self.frame_size, &self.frame_time_ms)
+                .await;
+
+            // One tile per step, each shown as soon as it is drawn.
+            let tile = (*self.frame_size.get_value()).and_then(|size| self.tiles.take(size));
 
             let rerender_out = self
                 .rendered_comp
                 .check_and_update(&[self.preout_comp.get_version(), *blitter.version()]);
 
-            if rerender_out || recompute_preout {
+            if rerender_out || tile.is_some() {
                 let res = render_output(
                     *hardware.get_value(),
                     *bindgroup.get_value(),
                     *safe_pipeline.get_value(),
                     *blitter.get_value(),
                     *output_view,
-                    preout_size.get_value(),
+                    self.frame_size.get_value(),
                     *preout_view.get_value(),
                     *unif_vals.get_value(),
-                    recompute_preout,
-                    rerender_out,
+                    (*timer.get_value()).and_then(|t| t.as_ref()),
+                    tile,
+                    stats,
                 )
                 .await;
 
                 match res {
-                    Some(_) => return true,
-                    None => return false,
+                    Some((queue_time, drew)) => {
+                        if tile.is_some() {
+                            self.tiles.add_gpu_time(queue_time);
+                            stats.frame_time = self.tiles.frame_time();
+                        }
+                        return if drew { Rendered::Pass } else { Rendered::Copy };
+                    }
+                    None => return Rendered::Nothing,
                 }
             }
         }
-        return false;
+        return Rendered::Nothing;
     }
 }
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use cardigan_incremental::memoized;
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, ComputePassTimestampWrites, QuerySet,
+    QuerySetDescriptor, QueryType, RenderPassTimestampWrites,
+};
+
+use crate::rendering::shader_config::GPUAdapterInfo;
+
+const TIMESTAMP_BYTES: u64 = 2 * wgpu::QUERY_SIZE as u64;
+
+/// Measures how long one pass takes on the GPU, with a timestamp at either end.
+pub struct GpuTimer {
+    query_set: QuerySet,
+    resolved: Buffer,
+    readback: Buffer,
+}
+
+/// `None` when the device was created without `Features::TIMESTAMP_QUERY`.
+#[memoized]
+pub async fn gpu_timer(hardware: &GPUAdapterInfo) -> Option<GpuTimer> {
+    let device = &hardware.deviceref;
+    if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+        return None;
+    }
+
+    let query_set = device.create_query_set(&QuerySetDescriptor {
+        label: Some("Pass Timestamps"),
+        ty: QueryType::Timestamp,
+        count: 2,
+    });
+    let resolved = device.create_buffer(&BufferDescriptor {
+        label: Some("Resolved Timestamps"),
+        size: TIMESTAMP_BYTES,
+        usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let readback = device.create_buffer(&BufferDescriptor {
+        label: Some("Timestamp Readback"),
+        size: TIMESTAMP_BYTES,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    Some(GpuTimer {
+        query_set,
+        resolved,
+        readback,
+    })
+}
+
+impl GpuTimer {
+    pub fn render_pass_writes(&self) -> RenderPassTimestampWrites<'_> {
+        RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        }
+    }
+
+    pub fn compute_pass_writes(&self) -> ComputePassTimestampWrites<'_> {
+        ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        }
+    }
+
+    /// Records copying the timestamps somewhere [`GpuTimer::read`] can map them.
+    /// Goes after the timed pass, in the same encoder.
+    pub fn resolve(&self, encoder: &mut CommandEncoder) {
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolved, 0);
+        encoder.copy_buffer_to_buffer(&self.resolved, 0, &self.readback, 0, TIMESTAMP_BYTES);
+    }
+
+    /// Waits for the submitted pass to finish and returns its GPU time.
+    pub async fn read(&self, hardware: &GPUAdapterInfo) -> Option<Duration> {
+        let slice = self.readback.slice(..);
+        let (send, recv) = async_std::channel::bounded(1);
+        slice.map_async(wgpu::MapMode::Read, move |res| {
+            let _ = send.try_send(res);
+        });
+        // The browser maps buffers on its own; natively someone has to poll.
+        #[cfg(not(target_arch = "wasm32"))]
+        hardware.deviceref.poll(wgpu::PollType::Wait).ok()?;
+        recv.recv().await.ok()?.ok()?;
+
+        let ticks = {
+            let data = slice.get_mapped_range();
+            let stamp = |i: usize| u64::from_le_bytes(data[i * 8..i * 8 + 8].try_into().unwrap());
+            stamp(1).saturating_sub(stamp(0))
+        };
+        self.readback.unmap();
+
+        let nanos = ticks as f64 * hardware.queueref.get_timestamp_period() as f64;
+        Some(Duration::from_nanos(nanos as u64))
+    }
+}
@@ -1,19 +1,30 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, time::Duration};
 
 use cardigan_incremental::{Versioned, memoized};
+use web_time::Instant;
 use wgpu::{
-    BindGroupLayout, Device, Extent3d, PipelineLayout, PipelineLayoutDescriptor, Queue,
-    ShaderModule, ShaderModuleDescriptor, Texture, TextureDescriptor, TextureFormat, TextureView,
-    TextureViewDescriptor,
-    util::{TextureBlitter, TextureBlitterBuilder},
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, Buffer, ColorTargetState, CommandEncoder, Device, Extent3d,
+    FragmentState, MultisampleState, Operations, PipelineLayout, PipelineLayoutDescriptor,
+    PrimitiveState, Queue, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, Sampler, SamplerDescriptor, ShaderModule, ShaderModuleDescriptor,
+    ShaderStages, Texture, TextureDescriptor, TextureFormat, TextureView, TextureViewDescriptor,
+    VertexState,
+    util::{BufferInitDescriptor, DeviceExt},
 };
 
-use crate::rendering::shader_config::{GPUAdapterInfo, ShaderLanguage};
+use crate::rendering::{
+    communication::{ToneMapper, Tonemapping, WorkerStats},
+    shader_config::{GPUAdapterInfo, ShaderLanguage},
+};
+
+const TONEMAP_WGSL: &str = include_str!("../../tonemap.wgsl");
 
 #[memoized]
 async fn preoutput_texture_view(
     preout_size: (u32, u32),
     uses: wgpu::TextureUsages,
+    format: TextureFormat,
     hardware: &GPUAdapterInfo,
 ) -> TextureView {
     let descriptor = TextureDescriptor {
@@ -27,7 +38,7 @@ async fn preoutput_texture_view(
         sample_count: 1,
 
         dimension: wgpu::TextureDimension::D2,
-        format: TextureFormat::Rgba8Unorm,
+        format,
         usage: uses,
         //wgpu::TextureUsages::TEXTURE_BINDING
         //  | wgpu::TextureUsages::COPY_DST
@@ -43,9 +54,175 @@ async fn preoutput_texture_view(
     tex_view
 }
 
+/// The parts of a [`TonemapBlitter`] that only depend on the output format.
+#[derive(Clone)]
+pub struct TonemapPipeline {
+    pipeline: RenderPipeline,
+    layout: BindGroupLayout,
+    sampler: Sampler,
+}
+
 #[memoized]
-async fn blitter(hardware: &GPUAdapterInfo, output_format: TextureFormat) -> TextureBlitter {
-    TextureBlitterBuilder::new(&hardware.deviceref, output_format).build()
+async fn tonemap_pipeline(
+    hardware: &GPUAdapterInfo,
+    output_format: TextureFormat,
+) -> TonemapPipeline {
+    let device = &hardware.deviceref;
+    let module = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("Tonemap Module"),
+        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(TONEMAP_WGSL)),
+    });
+    // 32-bit float textures can't be filtered everywhere, so frames are
+    // sampled without filtering whatever their format.
+    let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Tonemap Bind Group Layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    });
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Tonemap Pipeline Layout"),
+        bind_group_layouts: &[&layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Tonemap Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: &module,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[],
+        },
+        primitive: PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState {
+            module: &module,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(ColorTargetState {
+                format: output_format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+        cache: hardware.pipeline_cache.as_ref(),
+    });
+    let sampler = device.create_sampler(&SamplerDescriptor {
+        label: Some("Tonemap Sampler"),
+        ..Default::default()
+    });
+    TonemapPipeline {
+        pipeline,
+        layout,
+        sampler,
+    }
+}
+
+/// Copies the pre-output texture into the output view, tonemapping on the way.
+/// Takes the place of wgpu's `TextureBlitter`, which can't read float textures
+/// that aren't filterable.
+pub struct TonemapBlitter {
+    pipeline: TonemapPipeline,
+    params: Buffer,
+}
+
+impl TonemapBlitter {
+    pub fn copy(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        source: &TextureView,
+        target: &TextureView,
+    ) {
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Tonemap Bind Group"),
+            layout: &self.pipeline.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.pipeline.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: self.params.as_entire_binding(),
+                },
+            ],
+        });
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.pipeline.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+#[memoized]
+async fn blitter(
+    hardware: &GPUAdapterInfo,
+    pipeline: &TonemapPipeline,
+    tonemapping: Tonemapping,
+) -> TonemapBlitter {
+    let mapper: u32 = match tonemapping.mapper {
+        ToneMapper::Clamp => 0,
+        ToneMapper::Reinhard => 1,
+        ToneMapper::Aces => 2,
+        ToneMapper::None => 3,
+    };
+    let params = hardware
+        .deviceref
+        .create_buffer_init(&BufferInitDescriptor {
+            label: Some("Tonemap Params"),
+            contents: bytemuck::bytes_of(&[mapper, tonemapping.exposure.to_bits(), 0, 0]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+    TonemapBlitter {
+        pipeline: pipeline.clone(),
+        params,
+    }
 }
 
 #[memoized]
@@ -60,17 +237,130 @@ async fn pipeline_layout(hardware: &GPUAdapterInfo, bgl: &BindGroupLayout) -> Pi
     pipeline_layout
 }
 
+/// A rectangle of the pre-output texture, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Which tiles of the frame being drawn are still to come, and how long the
+/// ones so far took.
+#[derive(Default)]
+pub struct TileProgress {
+    per_side: u32,
+    next: u32,
+    gpu_time: Duration,
+    /// Something changed, so another frame is due once this one is done.
+    frame_requested: bool,
+}
+
+impl TileProgress {
+    /// Asks for a new frame. One that is partly drawn gets finished first,
+    /// so a frame that changes every step, like an animation, still completes.
+    pub fn request_frame(&mut self) {
+        self.frame_requested = true;
+    }
+
+    /// Drops a partly drawn frame, for when the texture it went to is gone.
+    pub fn abandon_frame(&mut self) {
+        self.next = self.per_side * self.per_side;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next >= self.per_side * self.per_side
+    }
+
+    /// Starts the requested frame, split into `per_side` by `per_side` tiles,
+    /// once the last one is done. Returns whether it did; whatever the frame
+    /// depends on should be taken up only then, so all its tiles agree.
+    pub fn start_frame(&mut self, per_side: u32) -> bool {
+        if !(self.is_done() && self.frame_requested) {
+            return false;
+        }
+        self.frame_requested = false;
+        self.per_side = per_side.max(1);
+        self.next = 0;
+        self.gpu_time = Duration::ZERO;
+        true
+    }
+
+    /// The next tile of the current frame, of `size`, that has any pixels in it.
+    pub fn take(&mut self, size: (u32, u32)) -> Option<Tile> {
+        while !self.is_done() {
+            let (col, row) = (self.next % self.per_side, self.next / self.per_side);
+            self.next += 1;
+            let x = size.0 * col / self.per_side;
+            let y = size.1 * row / self.per_side;
+            let tile = Tile {
+                x,
+                y,
+                width: size.0 * (col + 1) / self.per_side - x,
+                height: size.1 * (row + 1) / self.per_side - y,
+            };
+            if tile.width > 0 && tile.height > 0 {
+                return Some(tile);
+            }
+        }
+        None
+    }
+
+    pub fn add_gpu_time(&mut self, time: Duration) {
+        self.gpu_time += time;
+    }
+
+    /// The GPU time of the whole frame, once its last tile is drawn.
+    pub fn frame_time(&self) -> Option<Duration> {
+        self.is_done().then_some(self.gpu_time)
+    }
+}
+
+/// Waits for everything submitted so far to finish on the GPU and returns
+/// how long that took. Keeps the worker from queueing up more work than the
+/// GPU gets through.
+pub async fn wait_for_queue(hardware: &GPUAdapterInfo) -> Duration {
+    let started = Instant::now();
+    let (send, recv) = async_std::channel::bounded(1);
+    hardware.queueref.on_submitted_work_done(move || {
+        let _ = send.try_send(());
+    });
+    // The browser reports this on its own; natively someone has to poll, and
+    // a lost device never calls back.
+    #[cfg(not(target_arch = "wasm32"))]
+    if hardware.deviceref.poll(wgpu::PollType::Wait).is_err() {
+        return started.elapsed();
+    }
+    let _ = recv.recv().await;
+    started.elapsed()
+}
+
+/// What a step put in the output view.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Rendered {
+    Nothing,
+    /// The last frame again, e.g. with new tonemapping. No pass was recorded.
+    Copy,
+    /// A pass drawn with the current pipeline.
+    Pass,
+}
+
 pub trait BackendWorker {
     async fn step(
         &mut self,
         preout_size: &Versioned<(u32, u32)>,
+        time_ms: &Versioned<u32>,
         hardware: &Versioned<&GPUAdapterInfo>,
         module: &Versioned<&ShaderModule>,
         entry_point: &Versioned<&String>,
-        blitter: &Versioned<&TextureBlitter>,
+        preout_format: &Versioned<TextureFormat>,
+        blitter: &Versioned<&TonemapBlitter>,
         render_output_on_invalidated: bool,
         output_view: &Option<&TextureView>,
-    ) -> bool;
+        tiles_per_side: u32,
+        stats: &mut WorkerStats,
+    ) -> Rendered;
 }
 
 #[memoized]
@@ -84,7 +374,12 @@ async fn module_comp(
     let module = device.create_shader_module(ShaderModuleDescriptor {
         label: Some("Compute Module"),
         source: match lang {
-            //ShaderLanguage::Glsl => wgpu::ShaderSource::Glsl {shader: (Cow::Owned(shader_text.clone())), stage: Sh, },
+            // Only fragment backends are driven by the worker so far.
+            ShaderLanguage::Glsl => wgpu::ShaderSource::Glsl {
+                shader: Cow::Owned(shader_text.clone()),
+                stage: wgpu::naga::ShaderStage::Fragment,
+                defines: &[],
+            },
             ShaderLanguage::Wgsl => wgpu::ShaderSource::Wgsl(Cow::Owned(shader_text.clone())),
         },
     });
@@ -103,3 +398,101 @@ async fn module_comp(
 }
 
 pub type ModuleCompResult = Result<ShaderModule, wgpu::Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started(per_side: u32) -> TileProgress {
+        let mut progress = TileProgress::default();
+        progress.request_frame();
+        assert!(progress.start_frame(per_side));
+        progress
+    }
+
+    fn all_tiles(progress: &mut TileProgress, size: (u32, u32)) -> Vec<Tile> {
+        std::iter::from_fn(|| progress.take(size)).collect()
+    }
+
+    #[test]
+    fn tiles_cover_sizes_that_do_not_divide_evenly() {
+        let tiles = all_tiles(&mut started(3), (10, 7));
+        assert_eq!(tiles.len(), 9);
+        assert_eq!(
+            tiles.iter().map(|t| t.width).take(3).collect::<Vec<_>>(),
+            [3, 3, 4]
+        );
+        assert_eq!(
+            tiles
+                .iter()
+                .step_by(3)
+                .map(|t| t.height)
+                .collect::<Vec<_>>(),
+            [2, 2, 3]
+        );
+        // Every pixel is in exactly one tile.
+        for (x, y) in (0..10).flat_map(|x| (0..7).map(move |y| (x, y))) {
+            let covering = tiles
+                .iter()
+                .filter(|t| (t.x..t.x + t.width).contains(&x) && (t.y..t.y + t.height).contains(&y))
+                .count();
+            assert_eq!(covering, 1, "pixel {x},{y}");
+        }
+    }
+
+    #[test]
+    fn empty_tiles_are_skipped() {
+        let mut progress = started(4);
+        let tiles = all_tiles(&mut progress, (2, 1));
+        assert_eq!(
+            tiles,
+            [
+                Tile {
+                    x: 0,
+                    y: 0,
+                    width: 1,
+                    height: 1
+                },
+                Tile {
+                    x: 1,
+                    y: 0,
+                    width: 1,
+                    height: 1
+                },
+            ]
+        );
+        assert!(progress.is_done());
+    }
+
+    #[test]
+    fn a_requested_frame_waits_for_the_current_one() {
+        let mut progress = started(2);
+        progress.take((8, 8)).unwrap();
+        progress.add_gpu_time(Duration::from_millis(3));
+
+        progress.request_frame();
+        assert!(!progress.start_frame(3));
+        assert_eq!(progress.frame_time(), None);
+
+        assert_eq!(all_tiles(&mut progress, (8, 8)).len(), 3);
+        progress.add_gpu_time(Duration::from_millis(4));
+        assert_eq!(progress.frame_time(), Some(Duration::from_millis(7)));
+
+        assert!(progress.start_frame(3));
+        assert_eq!(progress.frame_time(), None);
+        assert_eq!(all_tiles(&mut progress, (8, 8)).len(), 9);
+        // Nothing more was requested.
+        assert!(!progress.start_frame(3));
+    }
+
+    #[test]
+    fn abandoning_a_frame_lets_the_next_one_start() {
+        let mut progress = started(4);
+        progress.take((16, 16)).unwrap();
+        progress.request_frame();
+        progress.abandon_frame();
+        assert!(progress.take((16, 16)).is_none());
+        assert!(progress.start_frame(1));
+        assert_eq!(all_tiles(&mut progress, (16, 16)).len(), 1);
+    }
+}
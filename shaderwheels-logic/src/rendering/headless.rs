@@ -0,0 +1,349 @@
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use wgpu::{Device, Extent3d, Queue, TextureDescriptor};
+
+use crate::rendering::{
+    communication::{PreoutFormat, Tonemapping},
+    graphics_backend_client::{CompileState, GraphicsClient},
+    shader_config::{DeviceEvents, GPUAdapterInfo, ShaderConfig},
+};
+
+/// Largest thumbnail edge, to keep one request from exhausting the adapter.
+pub const MAX_SIZE: u32 = 2048;
+const RENDER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    NoAdapter(wgpu::RequestAdapterError),
+    NoDevice(wgpu::RequestDeviceError),
+    /// The size asked for, and the largest side allowed.
+    BadSize((u32, u32), u32),
+    /// The worker never produced a frame, which mostly means the shader does not compile.
+    NotRendered,
+    DeviceLost(String),
+    Readback(String),
+}
+
+impl Display for HeadlessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeadlessError::NoAdapter(e) => write!(f, "no graphics adapter: {e}"),
+            HeadlessError::NoDevice(e) => write!(f, "could not open the graphics device: {e}"),
+            HeadlessError::BadSize((w, h), max) => {
+                write!(f, "size {w}x{h} is outside 1x1 to {max}x{max}")
+            }
+            HeadlessError::NotRendered => {
+                write!(f, "the shader did not render; it probably does not compile")
+            }
+            HeadlessError::DeviceLost(e) => write!(f, "the graphics device was lost: {e}"),
+            HeadlessError::Readback(e) => write!(f, "could not read the frame back: {e}"),
+        }
+    }
+}
+
+/// An RGBA8 image, tightly packed row by row.
+pub struct RenderedFrame {
+    pub size: (u32, u32),
+    pub rgba: Vec<u8>,
+}
+
+/// An RGBA image of 32-bit floats as the shader drew it, neither tonemapped
+/// nor clamped, row by row.
+pub struct HdrFrame {
+    pub size: (u32, u32),
+    pub rgba: Vec<f32>,
+}
+
+/// Renders shaders off screen through the same worker the editor uses.
+pub struct HeadlessRenderer {
+    device: Device,
+    queue: Queue,
+    device_events: DeviceEvents,
+    adapter_info: wgpu::AdapterInfo,
+}
+
+impl HeadlessRenderer {
+    /// Prefers a software adapter, so results do not depend on the machine's GPU,
+    /// and falls back to whatever adapter there is.
+    pub async fn new() -> Result<Self, HeadlessError> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::from_env_or_default());
+        let mut options = wgpu::RequestAdapterOptions {
+            force_fallback_adapter: true,
+            ..Default::default()
+        };
+        let adapter = match instance.request_adapter(&options).await {
+            Ok(adapter) => adapter,
+            Err(e) => {
+                log::warn!("No software adapter ({e}), using a hardware one");
+                options.force_fallback_adapter = false;
+                instance
+                    .request_adapter(&options)
+                    .await
+                    .map_err(HeadlessError::NoAdapter)?
+            }
+        };
+
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("Headless Device"),
+                required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+                required_limits: wgpu::Limits::downlevel_defaults(),
+                ..Default::default()
+            })
+            .await
+            .map_err(HeadlessError::NoDevice)?;
+
+        Ok(Self {
+            device_events: DeviceEvents::watch(&device),
+            device,
+            queue,
+            adapter_info: adapter.get_info(),
+        })
+    }
+
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    /// Renders one frame of `cfg` at `size`, with the shader's clock at `time_ms`.
+    /// Blocks until the frame is back on the CPU.
+    pub fn render(
+        &self,
+        cfg: ShaderConfig,
+        size: (u32, u32),
+        time_ms: u32,
+    ) -> Result<RenderedFrame, HeadlessError> {
+        check_size(size, MAX_SIZE)?;
+        let hardware = GPUAdapterInfo {
+            deviceref: self.device.clone(),
+            queueref: self.queue.clone(),
+            pipeline_cache: None,
+            device_events: self.device_events.clone(),
+        };
+        let rgba = render_with(
+            hardware,
+            cfg,
+            size,
+            time_ms,
+            PreoutFormat::Rgba8Unorm,
+            Tonemapping::default(),
+        )?;
+        Ok(RenderedFrame { size, rgba })
+    }
+}
+
+/// Renders one frame of `cfg` on `hardware`, which can be a device that is
+/// already in use, such as the editor's. The frame is drawn and read back in
+/// 32-bit floats, and nothing is tonemapped, so colors above 1 survive.
+pub fn render_hdr(
+    hardware: GPUAdapterInfo,
+    cfg: ShaderConfig,
+    size: (u32, u32),
+    time_ms: u32,
+) -> Result<HdrFrame, HeadlessError> {
+    check_size(size, hardware.deviceref.limits().max_texture_dimension_2d)?;
+    let bytes = render_with(
+        hardware,
+        cfg,
+        size,
+        time_ms,
+        PreoutFormat::Rgba32Float,
+        Tonemapping::NONE,
+    )?;
+    let rgba = bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+    Ok(HdrFrame { size, rgba })
+}
+
+fn check_size(size: (u32, u32), max: u32) -> Result<(), HeadlessError> {
+    if size.0 == 0 || size.1 == 0 || size.0 > max || size.1 > max {
+        return Err(HeadlessError::BadSize(size, max));
+    }
+    Ok(())
+}
+
+/// Bytes per row of a readback, padded the way texture copies need.
+fn padded_row_len(width: u32, texel_size: u32) -> u64 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64;
+    (width as u64 * texel_size as u64).div_ceil(align) * align
+}
+
+/// A float frame can need a readback buffer larger than the device allows even
+/// when each side is within the texture limit. The largest side reported is
+/// the one of the largest square that fits.
+fn check_readback_size(
+    limits: &wgpu::Limits,
+    size: (u32, u32),
+    format: wgpu::TextureFormat,
+) -> Result<(), HeadlessError> {
+    let texel_size = format.block_copy_size(None).unwrap_or(4);
+    let fits = |w: u32, h: u32| padded_row_len(w, texel_size) * h as u64 <= limits.max_buffer_size;
+    if fits(size.0, size.1) {
+        return Ok(());
+    }
+    let mut side = ((limits.max_buffer_size / texel_size as u64) as f64).sqrt() as u32;
+    while side > 1 && !fits(side, side) {
+        side -= 1;
+    }
+    Err(HeadlessError::BadSize(
+        size,
+        side.min(limits.max_texture_dimension_2d),
+    ))
+}
+
+/// Renders through a worker of its own into an output texture in `format`,
+/// and returns the texture's bytes, tightly packed row by row.
+fn render_with(
+    hardware: GPUAdapterInfo,
+    cfg: ShaderConfig,
+    size: (u32, u32),
+    time_ms: u32,
+    format: PreoutFormat,
+    tonemapping: Tonemapping,
+) -> Result<Vec<u8>, HeadlessError> {
+    let (device, queue) = (hardware.deviceref.clone(), hardware.queueref.clone());
+    check_readback_size(&device.limits(), size, format.texture_format())?;
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("Headless Output"),
+        size: Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: format.texture_format(),
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+
+    // Each render gets its own worker, which the client stops when dropped.
+    let mut client = GraphicsClient::new(cfg);
+    client.set_hardware(hardware);
+    client.set_preout_format(format);
+    client.set_tonemapping(tonemapping);
+    client.set_preout_size(size);
+    client.set_time_ms(time_ms);
+    client.set_output_format(format.texture_format());
+    client.set_output_view(texture.create_view(&wgpu::TextureViewDescriptor::default()));
+
+    let deadline = Instant::now() + RENDER_TIMEOUT;
+    while !client.get_should_swap() {
+        if let Some(reason) = client.device_lost() {
+            return Err(HeadlessError::DeviceLost(reason.to_string()));
+        }
+        let failed = matches!(
+            client.compile_state(),
+            CompileState::Finished { error: Some(_), .. }
+        );
+        if failed || Instant::now() > deadline {
+            return Err(HeadlessError::NotRendered);
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    read_back(&device, &queue, &texture, size)
+}
+
+fn read_back(
+    device: &Device,
+    queue: &Queue,
+    texture: &wgpu::Texture,
+    size: (u32, u32),
+) -> Result<Vec<u8>, HeadlessError> {
+    let (width, height) = size;
+    let texel_size = texture.format().block_copy_size(None).unwrap_or(4);
+    let unpadded_row = width as u64 * texel_size as u64;
+    let padded_row = padded_row_len(width, texel_size);
+
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Headless Readback"),
+        size: padded_row * height as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Headless Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &readback,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                // A row is at most the largest texture side times 16 bytes.
+                bytes_per_row: Some(padded_row as u32),
+                rows_per_image: None,
+            },
+        },
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit([encoder.finish()]);
+
+    let slice = readback.slice(..);
+    let (send, recv) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |res| {
+        let _ = send.send(res);
+    });
+    device
+        .poll(wgpu::PollType::Wait)
+        .map_err(|e| HeadlessError::Readback(e.to_string()))?;
+    recv.recv()
+        .map_err(|e| HeadlessError::Readback(e.to_string()))?
+        .map_err(|e| HeadlessError::Readback(e.to_string()))?;
+
+    let mut bytes = Vec::with_capacity((unpadded_row * height as u64) as usize);
+    {
+        let mapped = slice.get_mapped_range();
+        for row in mapped.chunks(padded_row as usize) {
+            bytes.extend_from_slice(&row[..unpadded_row as usize]);
+        }
+    }
+    readback.unmap();
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_lengths_do_not_overflow() {
+        assert_eq!(padded_row_len(1, 4), 256);
+        assert_eq!(padded_row_len(64, 4), 256);
+        assert_eq!(padded_row_len(65, 16), 1280);
+        assert_eq!(padded_row_len(16384, 16) * 16384, 1 << 32);
+    }
+
+    #[test]
+    fn refuses_float_frames_larger_than_a_buffer() {
+        // What desktop GPUs commonly report.
+        let limits = wgpu::Limits {
+            max_texture_dimension_2d: 16384,
+            max_buffer_size: 256 << 20,
+            ..wgpu::Limits::default()
+        };
+        let float = wgpu::TextureFormat::Rgba32Float;
+
+        let res = check_readback_size(&limits, (16384, 16384), float);
+        let Err(HeadlessError::BadSize(_, max)) = res else {
+            panic!("a 16384x16384 float frame was accepted");
+        };
+        assert_eq!(max, 4096);
+        check_readback_size(&limits, (max, max), float).unwrap();
+        // The same frame in 8 bits fits in a quarter of the space.
+        check_readback_size(&limits, (8192, 8192), wgpu::TextureFormat::Rgba8Unorm).unwrap();
+    }
+}
@@ -1,23 +1,66 @@
-use wgpu::{Device, Queue};
+use std::sync::{Arc, Mutex, Weak};
 
-#[derive(Clone, Copy)]
+use serde::{Deserialize, Serialize};
+use wgpu::{Device, DeviceLostReason, PipelineCache, Queue};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
 pub enum ShaderLanguage {
+    #[default]
     Wgsl,
-    //Glsl,
+    Glsl,
 }
 
-#[derive(Clone)]
+impl ShaderLanguage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShaderLanguage::Wgsl => "wgsl",
+            ShaderLanguage::Glsl => "glsl",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "wgsl" => Some(ShaderLanguage::Wgsl),
+            "glsl" => Some(ShaderLanguage::Glsl),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
 pub enum ShaderBackend {
     FullCompute,
+    // Should be switched to general once support exists
+    #[default]
     FullFragment,
     General,
 }
 
+impl ShaderBackend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShaderBackend::FullCompute => "full-compute",
+            ShaderBackend::FullFragment => "full-fragment",
+            ShaderBackend::General => "general",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "full-compute" => Some(ShaderBackend::FullCompute),
+            "full-fragment" => Some(ShaderBackend::FullFragment),
+            "general" => Some(ShaderBackend::General),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ShaderConfig {
     pub content: String,
     pub language: ShaderLanguage,
     pub backend: ShaderBackend,
+    pub entry_point: String,
 }
 
 impl Default for ShaderConfig {
@@ -25,10 +68,10 @@ impl Default for ShaderConfig {
         Self {
             //content: crate::rendering::DEFAULT_WGSL_COMPUTE.to_string(),
             content: crate::rendering::DEFAULT_WGSL_FRAG.to_string(),
-            language: ShaderLanguage::Wgsl,
-            // Should be switched to general once support exists
+            language: ShaderLanguage::default(),
             //backend: ShaderBackend::FullCompute,
-            backend: ShaderBackend::FullFragment,
+            backend: ShaderBackend::default(),
+            entry_point: crate::rendering::WGSL_ENTRY.to_string(),
         }
     }
 }
@@ -36,4 +79,130 @@ impl Default for ShaderConfig {
 pub struct GPUAdapterInfo {
     pub deviceref: Device,
     pub queueref: Queue,
+    /// Shared by every pipeline the worker builds, where the backend supports it.
+    pub pipeline_cache: Option<PipelineCache>,
+    /// Must come from [`DeviceEvents::watch`] on `deviceref`.
+    pub device_events: DeviceEvents,
+}
+
+#[derive(Clone, Debug)]
+pub enum DeviceEvent {
+    Error(String),
+    Lost(String),
+}
+
+type DeviceListener = Box<dyn FnMut(&DeviceEvent) -> bool + Send>;
+
+#[derive(Default)]
+struct Listeners {
+    next_id: u64,
+    listeners: Vec<(u64, DeviceListener)>,
+}
+
+/// A device's uncaptured errors and loss, passed on to every worker rendering
+/// on it. wgpu keeps a single callback of each kind per device, so they are
+/// registered once here rather than by each worker.
+#[derive(Clone, Default)]
+pub struct DeviceEvents {
+    listeners: Arc<Mutex<Listeners>>,
+}
+
+/// Removes its listener from [`DeviceEvents`] when dropped.
+#[must_use = "the listener is removed as soon as this is dropped"]
+pub struct DeviceListenerGuard {
+    listeners: Weak<Mutex<Listeners>>,
+    id: u64,
+}
+
+impl Drop for DeviceListenerGuard {
+    fn drop(&mut self) {
+        if let Some(listeners) = self.listeners.upgrade()
+            && let Ok(mut listeners) = listeners.lock()
+        {
+            listeners.listeners.retain(|(id, _)| *id != self.id);
+        }
+    }
+}
+
+impl DeviceEvents {
+    /// Takes over `device`'s callbacks. Call it once per device.
+    pub fn watch(device: &Device) -> Self {
+        let events = Self::default();
+        let on_error = events.clone();
+        device.on_uncaptured_error(Box::new(move |e: wgpu::Error| {
+            on_error.send(DeviceEvent::Error(e.to_string()))
+        }));
+        let on_lost = events.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            let message = match reason {
+                DeviceLostReason::Unknown => message,
+                DeviceLostReason::Destroyed => format!("destroyed: {message}"),
+            };
+            on_lost.send(DeviceEvent::Lost(message));
+        });
+        events
+    }
+
+    /// Calls `listener` with every event from here on, until it returns false
+    /// or the returned guard is dropped.
+    pub fn listen(
+        &self,
+        listener: impl FnMut(&DeviceEvent) -> bool + Send + 'static,
+    ) -> DeviceListenerGuard {
+        let mut listeners = self.listeners.lock().unwrap();
+        let id = listeners.next_id;
+        listeners.next_id += 1;
+        listeners.listeners.push((id, Box::new(listener)));
+        DeviceListenerGuard {
+            listeners: Arc::downgrade(&self.listeners),
+            id,
+        }
+    }
+
+    fn send(&self, event: DeviceEvent) {
+        self.listeners
+            .lock()
+            .unwrap()
+            .listeners
+            .retain_mut(|(_, listener)| listener(&event));
+    }
+
+    #[cfg(test)]
+    fn listener_count(&self) -> usize {
+        self.listeners.lock().unwrap().listeners.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropping_the_guard_removes_the_listener() {
+        let events = DeviceEvents::default();
+        let kept = events.listen(|_| true);
+        let dropped = events.listen(|_| true);
+        assert_eq!(events.listener_count(), 2);
+
+        drop(dropped);
+        assert_eq!(events.listener_count(), 1);
+        drop(kept);
+        assert_eq!(events.listener_count(), 0);
+    }
+
+    #[test]
+    fn listeners_can_stop_themselves() {
+        let events = DeviceEvents::default();
+        let seen = Arc::new(Mutex::new(0));
+        let counter = seen.clone();
+        let _guard = events.listen(move |_| {
+            *counter.lock().unwrap() += 1;
+            false
+        });
+
+        events.send(DeviceEvent::Error("first".to_string()));
+        events.send(DeviceEvent::Error("second".to_string()));
+        assert_eq!(*seen.lock().unwrap(), 1);
+        assert_eq!(events.listener_count(), 0);
+    }
 }
@@ -0,0 +1,192 @@
+use std::{fmt::Display, io::Read, net::SocketAddr};
+
+use serde::Deserialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::rendering::{
+    self,
+    headless::{HeadlessError, HeadlessRenderer, RenderedFrame},
+    shader_config::{ShaderBackend, ShaderConfig, ShaderLanguage},
+};
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8787";
+const DEFAULT_SIZE: u32 = 256;
+const MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+/// The render-relevant part of a `.shwl` project. Other fields are ignored, so
+/// any project version that has these works.
+#[derive(Deserialize)]
+struct ProjectDocument {
+    source: String,
+    #[serde(default)]
+    language: ShaderLanguage,
+    #[serde(default)]
+    backend: ShaderBackend,
+    #[serde(default)]
+    entry_point: Option<String>,
+}
+
+/// Reads a project the way the editor saves it, or bare WGSL source as very old
+/// projects were stored.
+pub fn project_to_config(body: &str) -> ShaderConfig {
+    match serde_json::from_str::<ProjectDocument>(body) {
+        Ok(doc) => ShaderConfig {
+            content: doc.source,
+            language: doc.language,
+            backend: doc.backend,
+            entry_point: doc
+                .entry_point
+                .unwrap_or_else(|| rendering::WGSL_ENTRY.to_string()),
+        },
+        Err(_) => ShaderConfig {
+            content: body.to_string(),
+            ..Default::default()
+        },
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThumbnailParams {
+    pub size: (u32, u32),
+    pub time_ms: u32,
+}
+
+impl ThumbnailParams {
+    /// Parses `width`, `height` and `time` (in seconds) from a query string.
+    /// A lone `width` or `height` gives a square.
+    pub fn from_query(query: &str) -> Result<Self, String> {
+        let mut width = None;
+        let mut height = None;
+        let mut time_ms = 0;
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let number = || format!("`{key}` must be a number, not `{value}`");
+            match key {
+                "width" => width = Some(value.parse::<u32>().map_err(|_| number())?),
+                "height" => height = Some(value.parse::<u32>().map_err(|_| number())?),
+                "time" => {
+                    let secs = value.parse::<f64>().map_err(|_| number())?;
+                    if !(0.0..=u32::MAX as f64 / 1000.0).contains(&secs) {
+                        return Err(format!("`time` is out of range: {secs}"));
+                    }
+                    time_ms = (secs * 1000.0).round() as u32;
+                }
+                _ => return Err(format!("unknown parameter `{key}`")),
+            }
+        }
+        let size = match (width, height) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(side), None) | (None, Some(side)) => (side, side),
+            (None, None) => (DEFAULT_SIZE, DEFAULT_SIZE),
+        };
+        Ok(Self { size, time_ms })
+    }
+}
+
+pub fn encode_png(frame: &RenderedFrame) -> Result<Vec<u8>, png::EncodingError> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, frame.size.0, frame.size.1);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&frame.rgba)?;
+    writer.finish()?;
+    Ok(bytes)
+}
+
+#[derive(Debug)]
+pub enum ServerError {
+    Bind(Box<dyn std::error::Error + Send + Sync>),
+    Renderer(HeadlessError),
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerError::Bind(e) => write!(f, "could not listen: {e}"),
+            ServerError::Renderer(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Serves `POST /thumbnail?width=..&height=..&time=..` with a project as the
+/// body, answering with a PNG. `GET /health` reports the adapter in use.
+pub struct ThumbnailServer {
+    server: Server,
+    renderer: HeadlessRenderer,
+}
+
+impl ThumbnailServer {
+    /// Port 0 picks a free port, see [`ThumbnailServer::local_addr`].
+    pub fn bind(addr: &str, renderer: HeadlessRenderer) -> Result<Self, ServerError> {
+        let server = Server::http(addr).map_err(ServerError::Bind)?;
+        Ok(Self { server, renderer })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Handles requests one at a time until the process ends; renders share
+    /// one device, so there is nothing to gain from doing them in parallel.
+    pub fn run(&self) {
+        for mut request in self.server.incoming_requests() {
+            let response = self.handle(&mut request);
+            if let Err(e) = request.respond(response) {
+                log::warn!("Could not send a response: {e}");
+            }
+        }
+    }
+
+    fn handle(&self, request: &mut Request) -> Response<std::io::Cursor<Vec<u8>>> {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+
+        match (request.method(), path) {
+            (Method::Get, "/health") => {
+                let info = self.renderer.adapter_info();
+                text_response(200, format!("ok: {} ({:?})", info.name, info.backend))
+            }
+            (Method::Post, "/thumbnail") => self.thumbnail(request, query),
+            (_, "/health" | "/thumbnail") => text_response(405, "method not allowed".to_string()),
+            _ => text_response(404, "not found".to_string()),
+        }
+    }
+
+    fn thumbnail(&self, request: &mut Request, query: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+        let params = match ThumbnailParams::from_query(query) {
+            Ok(params) => params,
+            Err(e) => return text_response(400, e),
+        };
+
+        let mut body = String::new();
+        let read = request
+            .as_reader()
+            .take(MAX_BODY_BYTES + 1)
+            .read_to_string(&mut body);
+        if let Err(e) = read {
+            return text_response(400, format!("body is not UTF-8 text: {e}"));
+        }
+        if body.len() as u64 > MAX_BODY_BYTES {
+            return text_response(413, "project is too large".to_string());
+        }
+
+        let cfg = project_to_config(&body);
+        let frame = match self.renderer.render(cfg, params.size, params.time_ms) {
+            Ok(frame) => frame,
+            Err(e @ HeadlessError::BadSize(..)) => return text_response(400, e.to_string()),
+            Err(e @ HeadlessError::NotRendered) => return text_response(422, e.to_string()),
+            Err(e) => return text_response(500, e.to_string()),
+        };
+        match encode_png(&frame) {
+            // Both halves of the header are plain ASCII.
+            Ok(png) => Response::from_data(png)
+                .with_header(Header::from_bytes("Content-Type", "image/png").unwrap()),
+            Err(e) => text_response(500, format!("could not encode the PNG: {e}")),
+        }
+    }
+}
+
+fn text_response(status: u16, text: String) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(text).with_status_code(status)
+}
@@ -0,0 +1,56 @@
+// Copies a frame to the output, scaled by the exposure and brought into the
+// 0 to 1 range by a tone curve.
+
+struct Params {
+    // 0 clamps, 1 is Reinhard, 2 is ACES and anything else leaves colors as they are.
+    mapper: u32,
+    exposure: f32,
+}
+
+@group(0) @binding(0) var frame: texture_2d<f32>;
+@group(0) @binding(1) var frame_sampler: sampler;
+@group(0) @binding(2) var<uniform> params: Params;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle that covers the whole output.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let mapped = (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+    return clamp(mapped, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = textureSampleLevel(frame, frame_sampler, in.uv, 0.0);
+    let color = texel.rgb * exp2(params.exposure);
+    var mapped: vec3<f32>;
+    switch params.mapper {
+        case 0u: {
+            mapped = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        case 1u: {
+            let positive = max(color, vec3<f32>(0.0));
+            mapped = positive / (positive + vec3<f32>(1.0));
+        }
+        case 2u: {
+            mapped = aces(max(color, vec3<f32>(0.0)));
+        }
+        default: {
+            mapped = color;
+        }
+    }
+    return vec4<f32>(mapped, texel.a);
+}
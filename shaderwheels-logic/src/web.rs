@@ -1,19 +1,32 @@
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
+
 use openidconnect::{
-    AuthenticationFlow, AuthorizationCode, ClientId, CsrfToken, EmptyAdditionalClaims,
-    EmptyAdditionalProviderMetadata, IssuerUrl, Nonce, OAuth2TokenResponse, ProviderMetadata,
-    RedirectUrl, Scope,
+    AccessTokenHash, AuthenticationFlow, AuthorizationCode, ClientId, CsrfToken,
+    EmptyAdditionalProviderMetadata, EndSessionUrl, EndpointMaybeSet, EndpointNotSet, EndpointSet,
+    IssuerUrl, LogoutProviderMetadata, LogoutRequest, Nonce, OAuth2TokenResponse,
+    PkceCodeChallenge, PkceCodeVerifier, PostLogoutRedirectUrl, ProviderMetadata, RedirectUrl,
+    RefreshToken, Scope, TokenResponse,
     core::{
         CoreAuthDisplay, CoreClaimName, CoreClaimType, CoreClient, CoreClientAuthMethod,
-        CoreGenderClaim, CoreGrantType, CoreJsonWebKey, CoreJweContentEncryptionAlgorithm,
+        CoreGrantType, CoreIdToken, CoreJsonWebKey, CoreJweContentEncryptionAlgorithm,
         CoreJweKeyManagementAlgorithm, CoreResponseMode, CoreResponseType,
-        CoreSubjectIdentifierType,
+        CoreSubjectIdentifierType, CoreUserInfoClaims,
     },
     reqwest,
+    url::Url,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+pub mod config;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod loopback;
+pub mod shader_api;
 
 pub type CognitoProviderMetadata = ProviderMetadata<
-    EmptyAdditionalProviderMetadata,
+    LogoutProviderMetadata<EmptyAdditionalProviderMetadata>,
     CoreAuthDisplay,
     CoreClientAuthMethod,
     CoreClaimName,
@@ -27,84 +40,466 @@ pub type CognitoProviderMetadata = ProviderMetadata<
     CoreSubjectIdentifierType,
 >;
 
-pub const SHADER_API_ENDPOINT: &str = include_str!("api-endpoint.txt");
+/// What `CoreClient::from_provider_metadata` hands back: the auth URL is always
+/// known, the token and user info URLs only if the provider advertised them.
+type DiscoveredClient = CoreClient<
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointMaybeSet,
+    EndpointMaybeSet,
+>;
 
-#[derive(Deserialize, Clone)]
+/// Login provider settings, validated from a [`config::ConfigLayer`].
+#[derive(Clone)]
 pub struct MyOICDCfgFile {
     client_id: String,
     issuer: String,
     redirect_uri: String,
     post_logout_redirect_uri: String,
     scope: String,
+    /// Port for the native loopback redirect. 0 picks any free port.
+    loopback_port: u16,
+}
+
+impl MyOICDCfgFile {
+    pub fn loopback_port(&self) -> u16 {
+        self.loopback_port
+    }
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Config(String),
+    Discovery(String),
+    Redirect(String),
+    Provider(String),
+    StateMismatch,
+    NotLoggedIn,
+    NoRefreshToken,
+    TokenExchange(String),
+    MissingIdToken,
+    IdToken(String),
+    Cancelled,
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::Config(e) => write!(f, "invalid login configuration: {e}"),
+            AuthError::Discovery(e) => write!(f, "could not reach the login provider: {e}"),
+            AuthError::Redirect(e) => write!(f, "bad redirect from the login provider: {e}"),
+            AuthError::Provider(e) => write!(f, "the login provider refused: {e}"),
+            AuthError::StateMismatch => write!(f, "login response did not match the request"),
+            AuthError::NotLoggedIn => write!(f, "not logged in"),
+            AuthError::NoRefreshToken => write!(f, "the session cannot be renewed"),
+            AuthError::TokenExchange(e) => write!(f, "could not get tokens: {e}"),
+            AuthError::MissingIdToken => write!(f, "the login provider sent no ID token"),
+            AuthError::IdToken(e) => write!(f, "ID token failed verification: {e}"),
+            AuthError::Cancelled => write!(f, "the login was cancelled"),
+        }
+    }
 }
 
-pub struct OICDCtx {
-    issuer_url: IssuerUrl,
-    client_id: ClientId,
+fn config_err(e: impl Display) -> AuthError {
+    AuthError::Config(e.to_string())
 }
 
+/// Seconds since the Unix epoch. `std::time::SystemTime` panics on the web.
+pub fn unix_now() -> u64 {
+    web_time::SystemTime::now()
+        .duration_since(web_time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// The query parameters the provider redirects back with.
+#[derive(Default, Debug)]
 pub struct AuthReturn {
-    code: Option<String>,
-    state: Option<String>,
-}
-
-pub async fn doauth() {
-    let cfg = include_str!("private-config.json");
-    let ctx: MyOICDCfgFile = serde_json::from_str::<MyOICDCfgFile>(cfg).unwrap();
-
-    let issuer_url = IssuerUrl::new(ctx.issuer).unwrap();
-    let client_id = ClientId::new(ctx.client_id);
-    let redirect_url = RedirectUrl::new(ctx.redirect_uri).unwrap();
-
-    let http_client = reqwest::ClientBuilder::new().build().unwrap();
-    let provider_meta = CognitoProviderMetadata::discover_async(issuer_url, &http_client)
-        .await
-        .unwrap();
-
-    let client = CoreClient::from_provider_metadata(provider_meta, client_id, None)
-        .set_redirect_uri(redirect_url);
-
-    let (authorize_url, csrf_state, nonce) = client
-        .authorize_url(
-            AuthenticationFlow::<CoreResponseType>::AuthorizationCode,
-            CsrfToken::new_random,
-            Nonce::new_random,
-        )
-        // This example is requesting access to the "calendar" features and the user's profile.
-        .add_scope(Scope::new("email".to_string()))
-        .url();
-
-    println!("Auth: {authorize_url}");
-
-    let authret: Result<AuthReturn, std::io::Error> = Ok(AuthReturn {
-        code: None,
-        state: None,
-    }); //use_query::<AuthReturn>();
-    if authret.is_err() || authret.as_ref().unwrap().code.is_none() {
-        return;
-    }
-    let authreturn = authret.unwrap();
-    let code = AuthorizationCode::new(authreturn.code.unwrap());
-    let state = CsrfToken::new(authreturn.state.unwrap());
-
-    let token_resp = client
-        .exchange_code(code)
-        .unwrap()
-        .request_async(&http_client)
-        .await
-        .unwrap();
-    println!("Scopes: {:?}", token_resp.scopes());
-
-    // Should verify in here
-
-    let uinf: openidconnect::UserInfoClaims<EmptyAdditionalClaims, CoreGenderClaim> = client
-        .user_info(token_resp.access_token().clone(), None)
-        .unwrap()
-        .request_async(&http_client)
-        .await
-        .unwrap();
-
-    //token_resp.
-
-    println!("Userinfo: {:?}", uinf);
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+impl AuthReturn {
+    pub fn from_redirect_url(url: &str) -> Result<Self, AuthError> {
+        let url = Url::parse(url).map_err(|e| AuthError::Redirect(e.to_string()))?;
+        let mut ret = AuthReturn::default();
+        for (key, value) in url.query_pairs() {
+            let slot = match key.as_ref() {
+                "code" => &mut ret.code,
+                "state" => &mut ret.state,
+                "error" => &mut ret.error,
+                "error_description" => &mut ret.error_description,
+                _ => continue,
+            };
+            *slot = Some(value.into_owned());
+        }
+        Ok(ret)
+    }
+
+    /// Whether the URL was a redirect from the provider at all.
+    pub fn is_auth_response(&self) -> bool {
+        self.code.is_some() || self.error.is_some()
+    }
+}
+
+/// Everything needed to finish a login once the browser comes back. On the web
+/// this has to survive the page navigating away, so it is serializable.
+#[derive(Serialize, Deserialize)]
+pub struct PendingLogin {
+    pub authorize_url: String,
+    redirect_uri: String,
+    csrf_state: String,
+    nonce: String,
+    pkce_verifier: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UserInfo {
+    pub subject: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+impl UserInfo {
+    pub fn display_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.email.as_deref())
+            .unwrap_or(&self.subject)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Session {
+    pub user: UserInfo,
+    pub access_token: String,
+    pub id_token: String,
+    pub refresh_token: Option<String>,
+    /// When the access token runs out, in seconds since the Unix epoch.
+    pub expires_at: Option<u64>,
+}
+
+/// How long before expiry the access token gets refreshed, so requests that
+/// are already on their way do not arrive with a dead token.
+const REFRESH_MARGIN_SECS: u64 = 60;
+
+impl Session {
+    /// When the access token should be refreshed, if it expires and can be renewed.
+    pub fn refresh_at(&self) -> Option<u64> {
+        self.refresh_token.as_ref()?;
+        self.expires_at
+            .map(|expires_at| expires_at.saturating_sub(REFRESH_MARGIN_SECS))
+    }
+}
+
+#[derive(Default)]
+struct SessionSlot {
+    session: Option<Session>,
+    generation: u64,
+}
+
+/// The session shared between the UI and background requests, so that either
+/// side can swap in refreshed tokens.
+#[derive(Clone, Default)]
+pub struct SessionHandle {
+    slot: Arc<Mutex<SessionSlot>>,
+}
+
+impl SessionHandle {
+    pub fn new(session: Option<Session>) -> Self {
+        Self {
+            slot: Arc::new(Mutex::new(SessionSlot {
+                session,
+                generation: 0,
+            })),
+        }
+    }
+
+    pub fn get(&self) -> Option<Session> {
+        self.slot.lock().unwrap().session.clone()
+    }
+
+    pub fn set(&self, session: Option<Session>) {
+        let mut slot = self.slot.lock().unwrap();
+        slot.session = session;
+        slot.generation += 1;
+    }
+
+    pub fn is_logged_in(&self) -> bool {
+        self.slot.lock().unwrap().session.is_some()
+    }
+
+    pub fn access_token(&self) -> Option<String> {
+        let slot = self.slot.lock().unwrap();
+        slot.session.as_ref().map(|s| s.access_token.clone())
+    }
+
+    pub fn refresh_at(&self) -> Option<u64> {
+        self.slot.lock().unwrap().session.as_ref()?.refresh_at()
+    }
+
+    /// Bumped on every change, so the owner can tell when to persist the session.
+    pub fn generation(&self) -> u64 {
+        self.slot.lock().unwrap().generation
+    }
+
+    /// Trades the refresh token for new tokens and stores them.
+    pub async fn refresh(&self) -> Result<(), AuthError> {
+        let session = self.get().ok_or(AuthError::NotLoggedIn)?;
+        let provider = OidcProvider::discover(config::current().auth.clone()).await?;
+        let fresh = provider.refresh(&session).await?;
+
+        let mut slot = self.slot.lock().unwrap();
+        // Logging out while the refresh was in flight must not bring the session back.
+        let unchanged = slot
+            .session
+            .as_ref()
+            .is_some_and(|current| current.refresh_token == session.refresh_token);
+        if unchanged {
+            slot.session = Some(fresh);
+            slot.generation += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for SessionHandle {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SessionHandle {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Option::<Session>::deserialize(deserializer).map(Self::new)
+    }
+}
+
+pub struct OidcProvider {
+    cfg: MyOICDCfgFile,
+    client: DiscoveredClient,
+    end_session_url: Option<EndSessionUrl>,
+    http: reqwest::Client,
+}
+
+impl OidcProvider {
+    pub async fn discover(cfg: MyOICDCfgFile) -> Result<Self, AuthError> {
+        let issuer_url = IssuerUrl::new(cfg.issuer.clone()).map_err(config_err)?;
+        let http = reqwest::ClientBuilder::new()
+            .build()
+            .map_err(|e| AuthError::Discovery(e.to_string()))?;
+        let provider_meta = CognitoProviderMetadata::discover_async(issuer_url, &http)
+            .await
+            .map_err(|e| AuthError::Discovery(e.to_string()))?;
+
+        let end_session_url = provider_meta
+            .additional_metadata()
+            .end_session_endpoint
+            .clone();
+        let client = CoreClient::from_provider_metadata(
+            provider_meta,
+            ClientId::new(cfg.client_id.clone()),
+            None,
+        );
+        Ok(Self {
+            cfg,
+            client,
+            end_session_url,
+            http,
+        })
+    }
+
+    pub fn config(&self) -> &MyOICDCfgFile {
+        &self.cfg
+    }
+
+    fn scopes(&self) -> impl Iterator<Item = Scope> + '_ {
+        // `openid` is always requested by the client itself.
+        self.cfg
+            .scope
+            .split_whitespace()
+            .filter(|s| *s != "openid")
+            .map(|s| Scope::new(s.to_string()))
+    }
+
+    /// Builds the authorize URL for a PKCE authorization-code login. `redirect_uri`
+    /// replaces the configured one, which the native loopback listener needs.
+    pub fn begin_login(&self, redirect_uri: Option<String>) -> Result<PendingLogin, AuthError> {
+        let redirect_uri = redirect_uri.unwrap_or_else(|| self.cfg.redirect_uri.clone());
+        let client = self
+            .client
+            .clone()
+            .set_redirect_uri(RedirectUrl::new(redirect_uri.clone()).map_err(config_err)?);
+
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (authorize_url, csrf_state, nonce) = client
+            .authorize_url(
+                AuthenticationFlow::<CoreResponseType>::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .add_scopes(self.scopes())
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+
+        Ok(PendingLogin {
+            authorize_url: authorize_url.to_string(),
+            redirect_uri,
+            csrf_state: csrf_state.secret().clone(),
+            nonce: nonce.secret().clone(),
+            pkce_verifier: pkce_verifier.secret().clone(),
+        })
+    }
+
+    /// Exchanges the code for tokens and verifies the ID token's signature, nonce
+    /// and access token hash before trusting anything in it.
+    pub async fn finish_login(
+        &self,
+        pending: PendingLogin,
+        ret: AuthReturn,
+    ) -> Result<Session, AuthError> {
+        if let Some(error) = ret.error {
+            return Err(AuthError::Provider(ret.error_description.unwrap_or(error)));
+        }
+        if ret.state.as_deref() != Some(pending.csrf_state.as_str()) {
+            return Err(AuthError::StateMismatch);
+        }
+        let code = ret
+            .code
+            .ok_or_else(|| AuthError::Redirect("no authorization code".to_string()))?;
+
+        let client = self
+            .client
+            .clone()
+            .set_redirect_uri(RedirectUrl::new(pending.redirect_uri).map_err(config_err)?);
+
+        let token_resp = client
+            .exchange_code(AuthorizationCode::new(code))
+            .map_err(|e| AuthError::TokenExchange(e.to_string()))?
+            .set_pkce_verifier(PkceCodeVerifier::new(pending.pkce_verifier))
+            .request_async(&self.http)
+            .await
+            .map_err(|e| AuthError::TokenExchange(e.to_string()))?;
+
+        let id_token = token_resp.id_token().ok_or(AuthError::MissingIdToken)?;
+        let verifier = client.id_token_verifier();
+        let claims = id_token
+            .claims(&verifier, &Nonce::new(pending.nonce))
+            .map_err(|e| AuthError::IdToken(e.to_string()))?;
+
+        if let Some(expected_hash) = claims.access_token_hash() {
+            let signing_alg = id_token
+                .signing_alg()
+                .map_err(|e| AuthError::IdToken(e.to_string()))?;
+            let signing_key = id_token
+                .signing_key(&verifier)
+                .map_err(|e| AuthError::IdToken(e.to_string()))?;
+            let actual_hash =
+                AccessTokenHash::from_token(token_resp.access_token(), signing_alg, signing_key)
+                    .map_err(|e| AuthError::IdToken(e.to_string()))?;
+            if actual_hash != *expected_hash {
+                return Err(AuthError::IdToken("access token hash mismatch".to_string()));
+            }
+        }
+
+        let mut user = UserInfo {
+            subject: claims.subject().as_str().to_string(),
+            name: claims
+                .name()
+                .and_then(|n| n.get(None))
+                .map(|n| n.as_str().to_string()),
+            email: claims.email().map(|e| e.as_str().to_string()),
+        };
+
+        // Auth0 keeps most profile claims out of the ID token unless asked, so
+        // top up from the user info endpoint. Failing here is not fatal.
+        match client.user_info(token_resp.access_token().clone(), None) {
+            Ok(request) => {
+                let info: Result<CoreUserInfoClaims, _> = request.request_async(&self.http).await;
+                match info {
+                    Ok(info) => {
+                        user.name = user.name.or_else(|| {
+                            info.name()
+                                .and_then(|n| n.get(None))
+                                .map(|n| n.as_str().to_string())
+                        });
+                        user.email = user
+                            .email
+                            .or_else(|| info.email().map(|e| e.as_str().to_string()));
+                    }
+                    Err(e) => log::warn!("Could not fetch user info: {e}"),
+                }
+            }
+            Err(e) => log::info!("Provider has no user info endpoint: {e}"),
+        }
+
+        Ok(Session {
+            user,
+            access_token: token_resp.access_token().secret().clone(),
+            id_token: id_token.to_string(),
+            refresh_token: token_resp.refresh_token().map(|t| t.secret().clone()),
+            expires_at: token_resp.expires_in().map(|d| unix_now() + d.as_secs()),
+        })
+    }
+
+    pub async fn refresh(&self, session: &Session) -> Result<Session, AuthError> {
+        let refresh_token = session
+            .refresh_token
+            .clone()
+            .ok_or(AuthError::NoRefreshToken)?;
+
+        let token_resp = self
+            .client
+            .exchange_refresh_token(&RefreshToken::new(refresh_token))
+            .map_err(|e| AuthError::TokenExchange(e.to_string()))?
+            .request_async(&self.http)
+            .await
+            .map_err(|e| AuthError::TokenExchange(e.to_string()))?;
+
+        // A refreshed ID token carries no nonce, but its signature is still checked.
+        let id_token = match token_resp.id_token() {
+            Some(id_token) => {
+                id_token
+                    .claims(&self.client.id_token_verifier(), |_: Option<&Nonce>| {
+                        Ok::<(), String>(())
+                    })
+                    .map_err(|e| AuthError::IdToken(e.to_string()))?;
+                id_token.to_string()
+            }
+            None => session.id_token.clone(),
+        };
+
+        Ok(Session {
+            user: session.user.clone(),
+            access_token: token_resp.access_token().secret().clone(),
+            id_token,
+            // Providers that rotate refresh tokens send a new one, others keep the old.
+            refresh_token: token_resp
+                .refresh_token()
+                .map(|t| t.secret().clone())
+                .or_else(|| session.refresh_token.clone()),
+            expires_at: token_resp.expires_in().map(|d| unix_now() + d.as_secs()),
+        })
+    }
+
+    /// The provider's end-session URL for `session`. With `return_to_app` the
+    /// browser is sent back to `post_logout_redirect_uri` afterwards.
+    pub fn logout_url(&self, session: &Session, return_to_app: bool) -> Option<String> {
+        let mut request = LogoutRequest::from(self.end_session_url.clone()?)
+            .set_client_id(ClientId::new(self.cfg.client_id.clone()));
+        if let Ok(id_token) = session.id_token.parse::<CoreIdToken>() {
+            request = request.set_id_token_hint(&id_token);
+        }
+        if return_to_app {
+            match PostLogoutRedirectUrl::new(self.cfg.post_logout_redirect_uri.clone()) {
+                Ok(url) => request = request.set_post_logout_redirect_uri(url),
+                Err(e) => log::warn!("Ignoring invalid post_logout_redirect_uri: {e}"),
+            }
+        }
+        Some(request.http_get_url().to_string())
+    }
 }
@@ -0,0 +1,509 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use openidconnect::url::Url;
+use serde::Deserialize;
+
+use super::MyOICDCfgFile;
+
+const BUILTIN_AUTH: &str = include_str!("../private-config.json");
+const BUILTIN_API_ENDPOINT: &str = include_str!("../api-endpoint.txt");
+const BUILTIN_SHARE_BASE_URL: &str = "https://shader.robinlh.dev/";
+
+/// Environment variable naming a config file, like `--config`.
+pub const CONFIG_PATH_ENV: &str = "SHADERWHEELS_CONFIG";
+
+/// Field, environment variable and command line flag for each setting.
+const KEYS: &[(&str, &str, &str)] = &[
+    (
+        "api_endpoint",
+        "SHADERWHEELS_API_ENDPOINT",
+        "--api-endpoint",
+    ),
+    ("issuer", "SHADERWHEELS_OIDC_ISSUER", "--oidc-issuer"),
+    (
+        "client_id",
+        "SHADERWHEELS_OIDC_CLIENT_ID",
+        "--oidc-client-id",
+    ),
+    (
+        "redirect_uri",
+        "SHADERWHEELS_OIDC_REDIRECT_URI",
+        "--oidc-redirect-uri",
+    ),
+    (
+        "post_logout_redirect_uri",
+        "SHADERWHEELS_OIDC_POST_LOGOUT_REDIRECT_URI",
+        "--oidc-post-logout-redirect-uri",
+    ),
+    ("scope", "SHADERWHEELS_OIDC_SCOPE", "--oidc-scope"),
+    (
+        "loopback_port",
+        "SHADERWHEELS_OIDC_LOOPBACK_PORT",
+        "--oidc-loopback-port",
+    ),
+];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse {
+        source: String,
+        message: String,
+    },
+    Fetch(String),
+    UnknownFlag(String),
+    MissingValue(String),
+    Missing(&'static str),
+    Invalid {
+        field: &'static str,
+        message: String,
+    },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read { path, error } => {
+                write!(f, "could not read config file {}: {error}", path.display())
+            }
+            ConfigError::Parse { source, message } => write!(f, "{source}: {message}"),
+            ConfigError::Fetch(e) => write!(f, "could not fetch config.json: {e}"),
+            ConfigError::UnknownFlag(flag) => {
+                write!(f, "unknown flag {flag}, expected --config or one of:")?;
+                for (_, _, flag) in KEYS {
+                    write!(f, " {flag}")?;
+                }
+                Ok(())
+            }
+            ConfigError::MissingValue(flag) => write!(f, "{flag} needs a value"),
+            ConfigError::Missing(field) => write!(f, "{field} is not configured"),
+            ConfigError::Invalid { field, message } => write!(f, "{field}: {message}"),
+        }
+    }
+}
+
+/// One source of settings. Everything is optional, so that a file, the
+/// environment and flags can each override just what they set.
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigLayer {
+    pub api_endpoint: Option<String>,
+    pub client_id: Option<String>,
+    pub issuer: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub post_logout_redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub loopback_port: Option<u16>,
+    pub share_base_url: Option<String>,
+}
+
+impl ConfigLayer {
+    /// The settings compiled into the binary.
+    pub fn builtin() -> Self {
+        let mut layer = Self::from_json(BUILTIN_AUTH, "built-in private-config.json")
+            .expect("built-in private-config.json is malformed");
+        let api_endpoint = BUILTIN_API_ENDPOINT.trim();
+        if !api_endpoint.is_empty() {
+            layer.api_endpoint = Some(api_endpoint.to_string());
+        }
+        layer.share_base_url = Some(BUILTIN_SHARE_BASE_URL.to_string());
+        layer
+    }
+
+    pub fn from_json(text: &str, source: &str) -> Result<Self, ConfigError> {
+        serde_json::from_str(text).map_err(|e| ConfigError::Parse {
+            source: source.to_string(),
+            message: e.to_string(),
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|error| ConfigError::Read {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Self::from_json(&text, &path.display().to_string())
+    }
+
+    /// Reads the `SHADERWHEELS_*` variables through `var`.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut layer = Self::default();
+        for (field, env, _) in KEYS {
+            if let Some(value) = var(env) {
+                layer.set(field, value, env)?;
+            }
+        }
+        Ok(layer)
+    }
+
+    fn set(&mut self, field: &str, value: String, source: &str) -> Result<(), ConfigError> {
+        let slot = match field {
+            "api_endpoint" => &mut self.api_endpoint,
+            "client_id" => &mut self.client_id,
+            "issuer" => &mut self.issuer,
+            "redirect_uri" => &mut self.redirect_uri,
+            "post_logout_redirect_uri" => &mut self.post_logout_redirect_uri,
+            "scope" => &mut self.scope,
+            "share_base_url" => &mut self.share_base_url,
+            "loopback_port" => {
+                let port = value.parse().map_err(|_| ConfigError::Invalid {
+                    field: "loopback_port",
+                    message: format!("{value:?} from {source} is not a port number"),
+                })?;
+                self.loopback_port = Some(port);
+                return Ok(());
+            }
+            _ => unreachable!("{field} is missing from ConfigLayer::set"),
+        };
+        *slot = Some(value);
+        Ok(())
+    }
+
+    /// Settings from `top` win over the ones in `self`.
+    pub fn overlay(self, top: ConfigLayer) -> ConfigLayer {
+        ConfigLayer {
+            api_endpoint: top.api_endpoint.or(self.api_endpoint),
+            client_id: top.client_id.or(self.client_id),
+            issuer: top.issuer.or(self.issuer),
+            redirect_uri: top.redirect_uri.or(self.redirect_uri),
+            post_logout_redirect_uri: top
+                .post_logout_redirect_uri
+                .or(self.post_logout_redirect_uri),
+            scope: top.scope.or(self.scope),
+            loopback_port: top.loopback_port.or(self.loopback_port),
+            share_base_url: top.share_base_url.or(self.share_base_url),
+        }
+    }
+
+    pub fn validate(self) -> Result<AppConfig, ConfigError> {
+        let api_endpoint = self
+            .api_endpoint
+            .filter(|url| !url.trim().is_empty())
+            .map(|url| parse_http_url("api_endpoint", &url))
+            .transpose()?;
+
+        let issuer = required("issuer", self.issuer)?;
+        parse_http_url("issuer", &issuer)?;
+        let redirect_uri = required("redirect_uri", self.redirect_uri)?;
+        parse_http_url("redirect_uri", &redirect_uri)?;
+        let post_logout_redirect_uri =
+            required("post_logout_redirect_uri", self.post_logout_redirect_uri)?;
+        parse_http_url("post_logout_redirect_uri", &post_logout_redirect_uri)?;
+
+        let share_base_url = parse_http_url(
+            "share_base_url",
+            &required("share_base_url", self.share_base_url)?,
+        )?;
+
+        let scope = self.scope.unwrap_or_else(|| "openid".to_string());
+        if !scope.split_whitespace().any(|s| s == "openid") {
+            return Err(ConfigError::Invalid {
+                field: "scope",
+                message: format!("{scope:?} has to include \"openid\""),
+            });
+        }
+
+        Ok(AppConfig {
+            api_endpoint,
+            share_base_url,
+            auth: MyOICDCfgFile {
+                client_id: required("client_id", self.client_id)?,
+                issuer,
+                redirect_uri,
+                post_logout_redirect_uri,
+                scope,
+                loopback_port: self.loopback_port.unwrap_or(0),
+            },
+        })
+    }
+}
+
+fn required(field: &'static str, value: Option<String>) -> Result<String, ConfigError> {
+    value
+        .filter(|v| !v.trim().is_empty())
+        .ok_or(ConfigError::Missing(field))
+}
+
+fn parse_http_url(field: &'static str, value: &str) -> Result<Url, ConfigError> {
+    let url = Url::parse(value).map_err(|e| ConfigError::Invalid {
+        field,
+        message: format!("{value:?} is not a URL: {e}"),
+    })?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ConfigError::Invalid {
+            field,
+            message: format!("{value:?} is not an http(s) URL"),
+        });
+    }
+    Ok(url)
+}
+
+/// Validated settings for the shader API and the login provider.
+#[derive(Clone)]
+pub struct AppConfig {
+    pub api_endpoint: Option<Url>,
+    /// The web build that share links made by the native app point at.
+    pub share_base_url: Url,
+    pub auth: MyOICDCfgFile,
+}
+
+pub struct CliArgs {
+    pub config_path: Option<PathBuf>,
+    pub layer: ConfigLayer,
+}
+
+/// Accepts `--flag value` and `--flag=value` for every setting, plus `--config <path>`.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<CliArgs, ConfigError> {
+    let mut cli = CliArgs {
+        config_path: None,
+        layer: ConfigLayer::default(),
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| ConfigError::MissingValue(flag.clone()))
+        };
+
+        if flag == "--config" {
+            cli.config_path = Some(PathBuf::from(value()?));
+            continue;
+        }
+        match KEYS.iter().find(|(_, _, f)| *f == flag) {
+            Some((field, _, _)) => {
+                let value = value()?;
+                cli.layer.set(field, value, &flag)?;
+            }
+            None => return Err(ConfigError::UnknownFlag(flag)),
+        }
+    }
+    Ok(cli)
+}
+
+/// Built-in defaults, overridden by the config file, then `SHADERWHEELS_*`
+/// variables, then flags. `default_path` is read if it exists and neither
+/// `--config` nor `SHADERWHEELS_CONFIG` name another file.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_native(
+    args: impl IntoIterator<Item = String>,
+    default_path: Option<PathBuf>,
+) -> Result<AppConfig, ConfigError> {
+    let cli = parse_args(args)?;
+    let env = |key: &str| std::env::var(key).ok();
+
+    let explicit_path = cli
+        .config_path
+        .or_else(|| env(CONFIG_PATH_ENV).map(PathBuf::from));
+    let file = match (explicit_path, default_path) {
+        (Some(path), _) => ConfigLayer::from_file(&path)?,
+        (None, Some(path)) if path.exists() => ConfigLayer::from_file(&path)?,
+        _ => ConfigLayer::default(),
+    };
+
+    ConfigLayer::builtin()
+        .overlay(file)
+        .overlay(ConfigLayer::from_env(env)?)
+        .overlay(cli.layer)
+        .validate()
+}
+
+/// Fetches `config.json` from next to the page. Deployments without one get the
+/// built-in defaults. Any other failure is an error rather than a silent switch
+/// to the built-in provider and API.
+pub async fn load_web(page_url: &str) -> Result<AppConfig, ConfigError> {
+    let layer = match fetch_config_json(page_url).await? {
+        Some((url, text)) => ConfigLayer::from_json(&text, url.as_str())?,
+        None => ConfigLayer::default(),
+    };
+
+    ConfigLayer::builtin().overlay(layer).validate()
+}
+
+/// The URL and text of `config.json`, or `None` if the page has none.
+async fn fetch_config_json(page_url: &str) -> Result<Option<(Url, String)>, ConfigError> {
+    let url = Url::parse(page_url)
+        .and_then(|page| page.join("config.json"))
+        .map_err(|e| ConfigError::Fetch(e.to_string()))?;
+
+    let resp = reqwest::Client::new()
+        .get(url.clone())
+        .send()
+        .await
+        .map_err(|e| ConfigError::Fetch(e.to_string()))?;
+
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        log::info!("No config.json at {url}, using the built-in configuration");
+        return Ok(None);
+    }
+    if !resp.status().is_success() {
+        return Err(ConfigError::Fetch(format!(
+            "{url} returned {}",
+            resp.status()
+        )));
+    }
+    let text = resp
+        .text()
+        .await
+        .map_err(|e| ConfigError::Fetch(e.to_string()))?;
+    Ok(Some((url, text)))
+}
+
+static CURRENT: OnceLock<AppConfig> = OnceLock::new();
+
+/// Makes `config` the one returned by [`current`]. Only the first call counts.
+pub fn install(config: AppConfig) {
+    if CURRENT.set(config).is_err() {
+        log::warn!("The runtime configuration was already installed");
+    }
+}
+
+/// The configuration loaded at startup, or the built-in one if none was installed.
+pub fn current() -> &'static AppConfig {
+    CURRENT.get_or_init(|| {
+        ConfigLayer::builtin()
+            .validate()
+            .expect("the built-in configuration is invalid")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn complete() -> ConfigLayer {
+        ConfigLayer {
+            api_endpoint: Some("https://api.example.com/".to_string()),
+            client_id: Some("client".to_string()),
+            issuer: Some("https://login.example.com/".to_string()),
+            redirect_uri: Some("https://app.example.com/".to_string()),
+            post_logout_redirect_uri: Some("https://app.example.com/".to_string()),
+            scope: None,
+            loopback_port: None,
+            share_base_url: Some("https://app.example.com/".to_string()),
+        }
+    }
+
+    #[test]
+    fn parses_both_flag_forms() {
+        let cli = parse_args(args(&[
+            "--config",
+            "staging.json",
+            "--oidc-client-id=abc",
+            "--oidc-loopback-port",
+            "8123",
+            "--api-endpoint=https://api.example.com/?a=b",
+        ]))
+        .unwrap();
+        assert_eq!(cli.config_path, Some(PathBuf::from("staging.json")));
+        assert_eq!(cli.layer.client_id.as_deref(), Some("abc"));
+        assert_eq!(cli.layer.loopback_port, Some(8123));
+        // Only the first `=` splits the flag from its value.
+        assert_eq!(
+            cli.layer.api_endpoint.as_deref(),
+            Some("https://api.example.com/?a=b")
+        );
+    }
+
+    #[test]
+    fn rejects_bad_flags() {
+        assert!(matches!(
+            parse_args(args(&["--colour", "red"])),
+            Err(ConfigError::UnknownFlag(flag)) if flag == "--colour"
+        ));
+        assert!(matches!(
+            parse_args(args(&["--oidc-issuer"])),
+            Err(ConfigError::MissingValue(flag)) if flag == "--oidc-issuer"
+        ));
+        assert!(matches!(
+            parse_args(args(&["--oidc-loopback-port=http"])),
+            Err(ConfigError::Invalid {
+                field: "loopback_port",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn overlay_keeps_what_the_top_leaves_unset() {
+        let top = ConfigLayer {
+            client_id: Some("top".to_string()),
+            loopback_port: Some(9000),
+            ..Default::default()
+        };
+        let merged = complete().overlay(top);
+        assert_eq!(merged.client_id.as_deref(), Some("top"));
+        assert_eq!(merged.loopback_port, Some(9000));
+        assert_eq!(merged.issuer.as_deref(), Some("https://login.example.com/"));
+    }
+
+    #[test]
+    fn validate_fills_in_defaults() {
+        let cfg = ConfigLayer {
+            api_endpoint: Some(" ".to_string()),
+            ..complete()
+        }
+        .validate()
+        .unwrap();
+        assert_eq!(cfg.api_endpoint, None);
+        assert_eq!(cfg.auth.scope, "openid");
+        assert_eq!(cfg.auth.loopback_port, 0);
+    }
+
+    #[test]
+    fn validate_rejects_incomplete_or_bad_settings() {
+        let missing = ConfigLayer {
+            client_id: None,
+            ..complete()
+        };
+        assert!(matches!(
+            missing.validate(),
+            Err(ConfigError::Missing("client_id"))
+        ));
+
+        let not_http = ConfigLayer {
+            issuer: Some("ftp://login.example.com/".to_string()),
+            ..complete()
+        };
+        assert!(matches!(
+            not_http.validate(),
+            Err(ConfigError::Invalid {
+                field: "issuer",
+                ..
+            })
+        ));
+
+        let no_openid = ConfigLayer {
+            scope: Some("profile email".to_string()),
+            ..complete()
+        };
+        assert!(matches!(
+            no_openid.validate(),
+            Err(ConfigError::Invalid { field: "scope", .. })
+        ));
+    }
+
+    #[test]
+    fn files_with_unknown_keys_are_rejected() {
+        assert!(matches!(
+            ConfigLayer::from_json(r#"{"client_ID": "x"}"#, "config.json"),
+            Err(ConfigError::Parse { .. })
+        ));
+    }
+}
@@ -0,0 +1,184 @@
+use std::{future::Future, pin::pin, task::Poll, time::Duration};
+
+use async_std::{
+    channel::{Receiver, Sender},
+    io::{ReadExt, WriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use super::{AuthError, AuthReturn};
+
+const DONE_PAGE: &str = "<!DOCTYPE html><html><body>\
+    <p>Login finished. You can close this tab and return to ShaderWheels.</p>\
+    </body></html>";
+
+/// How long a connection may take to send its request line. Browsers open
+/// connections ahead of time and may never send anything on them.
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_REQUEST_LINE: usize = 8192;
+
+fn io_err(e: std::io::Error) -> AuthError {
+    AuthError::Redirect(e.to_string())
+}
+
+/// Ends the wait for the redirect when dropped, which frees the listener's port.
+pub struct CancelLogin(#[allow(dead_code)] Sender<()>);
+
+/// Finishes once the matching [`CancelLogin`] is dropped.
+pub struct LoginCancelled(Receiver<()>);
+
+pub fn cancel_pair() -> (CancelLogin, LoginCancelled) {
+    let (sender, receiver) = async_std::channel::bounded(1);
+    (CancelLogin(sender), LoginCancelled(receiver))
+}
+
+/// Receives the provider's redirect on native, where there is no page to return to.
+pub struct LoopbackListener {
+    listener: TcpListener,
+    redirect_uri: String,
+}
+
+impl LoopbackListener {
+    pub async fn bind(port: u16) -> Result<Self, AuthError> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .await
+            .map_err(io_err)?;
+        let port = listener.local_addr().map_err(io_err)?.port();
+        Ok(Self {
+            listener,
+            redirect_uri: format!("http://127.0.0.1:{port}/"),
+        })
+    }
+
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    /// Waits until the browser lands on the redirect URI, or until the login is
+    /// cancelled. Other requests the browser makes in the meantime (favicons and
+    /// the like) get a 404.
+    pub async fn wait_for_redirect(
+        self,
+        cancelled: LoginCancelled,
+    ) -> Result<AuthReturn, AuthError> {
+        let mut redirect = pin!(self.accept_redirect());
+        let mut cancelled = pin!(cancelled.0.recv());
+        std::future::poll_fn(|cx| {
+            if cancelled.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Err(AuthError::Cancelled));
+            }
+            redirect.as_mut().poll(cx)
+        })
+        .await
+    }
+
+    async fn accept_redirect(&self) -> Result<AuthReturn, AuthError> {
+        loop {
+            let (mut stream, _) = self.listener.accept().await.map_err(io_err)?;
+
+            // A connection that is idle or broken is not the redirect, so it is
+            // dropped and the next one is awaited.
+            let request_line =
+                match async_std::io::timeout(READ_TIMEOUT, read_request_line(&mut stream)).await {
+                    Ok(line) => line,
+                    Err(e) => {
+                        log::debug!("Dropping a loopback connection: {e}");
+                        continue;
+                    }
+                };
+
+            // The request line looks like `GET /?code=...&state=... HTTP/1.1`.
+            let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+            let ret = AuthReturn::from_redirect_url(&format!(
+                "{}{}",
+                self.redirect_uri.trim_end_matches('/'),
+                path
+            ))?;
+
+            if ret.is_auth_response() {
+                respond(&mut stream, "200 OK", DONE_PAGE).await;
+                return Ok(ret);
+            }
+            respond(&mut stream, "404 Not Found", "").await;
+        }
+    }
+}
+
+/// Reads until the end of the first line, however the request is split up.
+async fn read_request_line(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        if let Some(end) = request.windows(2).position(|w| w == b"\r\n") {
+            return Ok(String::from_utf8_lossy(&request[..end]).into_owned());
+        }
+        if request.len() > MAX_REQUEST_LINE {
+            return Err(std::io::Error::other("request line too long"));
+        }
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        request.extend_from_slice(&buf[..len]);
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    // The browser going away early does not matter, the redirect was already read.
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+    };
+
+    use super::*;
+
+    #[test]
+    fn idle_connections_do_not_hold_up_the_redirect() {
+        async_std::task::block_on(async {
+            let listener = LoopbackListener::bind(0).await.unwrap();
+            let addr = listener.listener.local_addr().unwrap();
+            let (_cancel, cancelled) = cancel_pair();
+
+            let browser = std::thread::spawn(move || {
+                let _preconnect = TcpStream::connect(addr).unwrap();
+                let mut stream = TcpStream::connect(addr).unwrap();
+                stream.write_all(b"GET /?code=abc").unwrap();
+                std::thread::sleep(Duration::from_millis(50));
+                stream
+                    .write_all(b"&state=xyz HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                    .unwrap();
+                let mut reply = String::new();
+                stream.read_to_string(&mut reply).unwrap();
+                reply
+            });
+
+            let ret = listener.wait_for_redirect(cancelled).await.unwrap();
+            assert_eq!(ret.code.as_deref(), Some("abc"));
+            assert_eq!(ret.state.as_deref(), Some("xyz"));
+            assert!(browser.join().unwrap().starts_with("HTTP/1.1 200 OK"));
+        });
+    }
+
+    #[test]
+    fn cancelling_frees_the_port() {
+        async_std::task::block_on(async {
+            let listener = LoopbackListener::bind(0).await.unwrap();
+            let port = listener.listener.local_addr().unwrap().port();
+            let (cancel, cancelled) = cancel_pair();
+
+            let waiting = async_std::task::spawn(listener.wait_for_redirect(cancelled));
+            drop(cancel);
+            assert!(matches!(waiting.await, Err(AuthError::Cancelled)));
+            LoopbackListener::bind(port).await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,774 @@
+use std::{fmt::Display, time::Duration};
+
+use reqwest::{IntoUrl, Method, RequestBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use super::{SessionHandle, config};
+
+pub type ShaderId = u64;
+
+/// Opaque tag for one saved version of a remote shader.
+pub type RevisionId = String;
+
+/// A shader as stored by the API. `project` holds a serialized `.shwl` document,
+/// so the API does not need to know about every project field.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RemoteShader {
+    pub id: ShaderId,
+    pub name: String,
+    pub project: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Falls back to the response's `ETag` when the body has none, if that is
+    /// a strong one.
+    #[serde(default)]
+    pub revision: Option<RevisionId>,
+    /// The shader this one was forked from.
+    #[serde(default)]
+    pub parent: Option<ShaderId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShaderUpload {
+    pub name: String,
+    pub project: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<ShaderId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RevisionSummary {
+    pub revision: RevisionId,
+    /// Seconds since the Unix epoch.
+    #[serde(default)]
+    pub created_at: Option<u64>,
+    #[serde(default)]
+    pub author: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShaderSummary {
+    pub id: ShaderId,
+    pub name: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Absolute, or relative to the API endpoint.
+    #[serde(default)]
+    pub thumbnail_url: Option<String>,
+    /// Seconds since the Unix epoch.
+    #[serde(default)]
+    pub updated_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Newest,
+    Popular,
+    Name,
+}
+
+impl SortOrder {
+    pub const ALL: [SortOrder; 3] = [SortOrder::Newest, SortOrder::Popular, SortOrder::Name];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SortOrder::Newest => "Newest",
+            SortOrder::Popular => "Most Popular",
+            SortOrder::Name => "Name",
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ListQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<u32>,
+    /// Matches names and descriptions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortOrder>,
+    /// Only shaders everyone can see, rather than the caller's own.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub public: bool,
+}
+
+#[derive(Debug)]
+pub enum ShaderApiError {
+    Network(reqwest::Error),
+    Unauthorized,
+    NotFound,
+    /// The shader changed on the server since the revision the save was based on.
+    Conflict,
+    Status { status: StatusCode, body: String },
+    Decode(reqwest::Error),
+    NotConfigured,
+}
+
+impl Display for ShaderApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShaderApiError::Network(e) => write!(f, "could not reach the shader API: {e}"),
+            ShaderApiError::Unauthorized => write!(f, "not logged in, or the session has expired"),
+            ShaderApiError::NotFound => write!(f, "shader does not exist"),
+            ShaderApiError::Conflict => write!(f, "shader was changed by someone else"),
+            ShaderApiError::Status { status, body } => {
+                write!(f, "shader API returned {status}: {body}")
+            }
+            ShaderApiError::Decode(e) => write!(f, "unexpected response from the shader API: {e}"),
+            ShaderApiError::NotConfigured => write!(f, "no shader API endpoint is configured"),
+        }
+    }
+}
+
+impl ShaderApiError {
+    /// Errors that may go away by themselves if the request is sent again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ShaderApiError::Network(_) => true,
+            ShaderApiError::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Typed client for the shader REST API. Cloning is cheap and shares the
+/// underlying connection pool.
+#[derive(Clone)]
+pub struct ShaderApiClient {
+    http: reqwest::Client,
+    base_url: Option<String>,
+    session: SessionHandle,
+    retry: RetryPolicy,
+}
+
+impl Default for ShaderApiClient {
+    fn default() -> Self {
+        Self::new(
+            config::current()
+                .api_endpoint
+                .as_ref()
+                .map(|url| url.as_str()),
+        )
+    }
+}
+
+impl ShaderApiClient {
+    pub fn new(base_url: Option<&str>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.map(|url| url.trim().trim_end_matches('/').to_string()),
+            session: SessionHandle::default(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Requests are sent with this session's access token, refreshing it once
+    /// if the API rejects it.
+    pub fn set_session(&mut self, session: SessionHandle) {
+        self.session = session;
+    }
+
+    pub fn base_url(&self) -> Option<&str> {
+        self.base_url.as_deref()
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.request_url(
+            method,
+            format!("{}{}", self.base_url().unwrap_or_default(), path),
+        )
+    }
+
+    fn request_url(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        let req = self
+            .http
+            .request(method, url)
+            .header(reqwest::header::ACCEPT, "application/json");
+        match self.session.access_token() {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        }
+    }
+
+    /// Sends the request built by `build`. An expired token gets one refresh and
+    /// one more try; a 401 means the request was not acted on, so even a POST is safe.
+    async fn send(
+        &self,
+        idempotent: bool,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<Response, ShaderApiError> {
+        if self.base_url.is_none() {
+            return Err(ShaderApiError::NotConfigured);
+        }
+        match self.send_with_retries(idempotent, &build).await {
+            Err(ShaderApiError::Unauthorized) if self.session.is_logged_in() => {
+                log::info!("Access token was rejected, refreshing the session");
+                if let Err(e) = self.session.refresh().await {
+                    log::warn!("Could not refresh the session: {e}");
+                    return Err(ShaderApiError::Unauthorized);
+                }
+                self.send_with_retries(idempotent, &build).await
+            }
+            res => res,
+        }
+    }
+
+    /// Sends the request built by `build`, rebuilding it for each retry. Only
+    /// idempotent requests are retried, so a flaky connection cannot create duplicates.
+    async fn send_with_retries(
+        &self,
+        idempotent: bool,
+        build: &impl Fn() -> RequestBuilder,
+    ) -> Result<Response, ShaderApiError> {
+        let mut backoff = self.retry.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            let res = Self::send_once(build()).await;
+            match res {
+                Err(e) if idempotent && e.is_transient() && attempt < self.retry.max_attempts => {
+                    log::info!("Shader API request failed ({e}), retrying in {backoff:?}");
+                    async_std::task::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn send_once(req: RequestBuilder) -> Result<Response, ShaderApiError> {
+        let resp = req.send().await.map_err(ShaderApiError::Network)?;
+        match resp.status() {
+            status if status.is_success() => Ok(resp),
+            StatusCode::UNAUTHORIZED => Err(ShaderApiError::Unauthorized),
+            StatusCode::NOT_FOUND => Err(ShaderApiError::NotFound),
+            StatusCode::PRECONDITION_FAILED | StatusCode::CONFLICT => Err(ShaderApiError::Conflict),
+            status => Err(ShaderApiError::Status {
+                status,
+                body: resp.text().await.unwrap_or_default(),
+            }),
+        }
+    }
+
+    async fn decode<T: DeserializeOwned>(resp: Response) -> Result<T, ShaderApiError> {
+        resp.json().await.map_err(ShaderApiError::Decode)
+    }
+
+    async fn decode_shader(resp: Response) -> Result<RemoteShader, ShaderApiError> {
+        // A weak ETag can't be sent back in If-Match, so only a strong one
+        // stands in for the revision.
+        let etag = resp
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.starts_with("W/"))
+            .map(|v| v.trim_matches('"').to_string());
+        let mut shader: RemoteShader = Self::decode(resp).await?;
+        if shader.revision.is_none() {
+            shader.revision = etag;
+        }
+        Ok(shader)
+    }
+
+    pub async fn create(&self, shader: &ShaderUpload) -> Result<RemoteShader, ShaderApiError> {
+        let resp = self
+            .send(false, || {
+                self.request(Method::POST, "/shaders").json(shader)
+            })
+            .await?;
+        Self::decode_shader(resp).await
+    }
+
+    pub async fn read(&self, id: ShaderId) -> Result<RemoteShader, ShaderApiError> {
+        let resp = self
+            .send(true, || {
+                self.request(Method::GET, &format!("/shaders/{id}"))
+            })
+            .await?;
+        Self::decode_shader(resp).await
+    }
+
+    /// Overwrites a shader. With `base_revision` set the server refuses with
+    /// [`ShaderApiError::Conflict`] if someone saved a newer revision meanwhile.
+    /// A retried request can hit that after the first attempt went through, so
+    /// callers should check whether the "newer" revision is just their own.
+    pub async fn update(
+        &self,
+        id: ShaderId,
+        shader: &ShaderUpload,
+        base_revision: Option<&str>,
+    ) -> Result<RemoteShader, ShaderApiError> {
+        let resp = self
+            .send(true, || {
+                let req = self
+                    .request(Method::PUT, &format!("/shaders/{id}"))
+                    .json(shader);
+                match base_revision {
+                    Some(rev) => req.header(reqwest::header::IF_MATCH, format!("\"{rev}\"")),
+                    None => req,
+                }
+            })
+            .await?;
+        Self::decode_shader(resp).await
+    }
+
+    /// Saved versions of a shader, newest first.
+    pub async fn revisions(&self, id: ShaderId) -> Result<Vec<RevisionSummary>, ShaderApiError> {
+        let resp = self
+            .send(true, || {
+                self.request(Method::GET, &format!("/shaders/{id}/revisions"))
+            })
+            .await?;
+        Self::decode(resp).await
+    }
+
+    pub async fn read_revision(
+        &self,
+        id: ShaderId,
+        revision: &str,
+    ) -> Result<RemoteShader, ShaderApiError> {
+        let url = self.revision_url(id, revision)?;
+        let resp = self
+            .send(true, || self.request_url(Method::GET, url.clone()))
+            .await?;
+        let mut shader: RemoteShader = Self::decode(resp).await?;
+        shader.revision.get_or_insert_with(|| revision.to_string());
+        Ok(shader)
+    }
+
+    /// Revisions come from the server, so they are escaped into a single path
+    /// segment rather than trusted not to contain `/`, `?` or `#`.
+    fn revision_url(&self, id: ShaderId, revision: &str) -> Result<Url, ShaderApiError> {
+        let base = self.base_url().ok_or(ShaderApiError::NotConfigured)?;
+        let mut url = Url::parse(&format!("{base}/shaders/{id}/revisions"))
+            .map_err(|_| ShaderApiError::NotConfigured)?;
+        url.path_segments_mut()
+            .map_err(|_| ShaderApiError::NotConfigured)?
+            .push(revision);
+        Ok(url)
+    }
+
+    pub async fn delete(&self, id: ShaderId) -> Result<(), ShaderApiError> {
+        self.send(true, || {
+            self.request(Method::DELETE, &format!("/shaders/{id}"))
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list(&self, query: &ListQuery) -> Result<Vec<ShaderSummary>, ShaderApiError> {
+        let resp = self
+            .send(true, || self.request(Method::GET, "/shaders").query(query))
+            .await?;
+        Self::decode(resp).await
+    }
+
+    /// Downloads a shader's thumbnail, if it has one.
+    pub async fn thumbnail(
+        &self,
+        summary: &ShaderSummary,
+    ) -> Result<Option<Vec<u8>>, ShaderApiError> {
+        let Some(url) = &summary.thumbnail_url else {
+            return Ok(None);
+        };
+        // Thumbnails on another host (a CDN, say) must not get our access token.
+        let resp = if url.starts_with("http://") || url.starts_with("https://") {
+            self.send(true, || self.http.get(url)).await?
+        } else {
+            let path = format!("/{}", url.trim_start_matches('/'));
+            self.send(true, || self.request(Method::GET, &path)).await?
+        };
+        let bytes = resp.bytes().await.map_err(ShaderApiError::Decode)?;
+        Ok(Some(bytes.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::web::{Session, UserInfo, config::ConfigLayer};
+
+    struct Request {
+        method: String,
+        path: String,
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    impl Request {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    enum Reply {
+        Json(u16, Vec<(&'static str, String)>, String),
+        Status(u16),
+        /// Closes the connection without answering, like a timed out request.
+        Hangup,
+    }
+
+    /// A local HTTP server that answers each request with `handler` and keeps
+    /// every request it saw.
+    struct MockServer {
+        url: String,
+        requests: Arc<Mutex<Vec<Request>>>,
+    }
+
+    impl MockServer {
+        fn start(handler: impl Fn(&Request) -> Reply + Send + 'static) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let seen = requests.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(mut stream) = stream else { continue };
+                    let Some(request) = read_request(&mut stream) else {
+                        continue;
+                    };
+                    let reply = handler(&request);
+                    seen.lock().unwrap().push(request);
+                    let (status, headers, body) = match reply {
+                        Reply::Json(status, headers, body) => (status, headers, body),
+                        Reply::Status(status) => (status, Vec::new(), String::new()),
+                        Reply::Hangup => continue,
+                    };
+                    let mut head = format!(
+                        "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+                        body.len()
+                    );
+                    for (name, value) in headers {
+                        head += &format!("{name}: {value}\r\n");
+                    }
+                    let _ = stream.write_all(format!("{head}\r\n{body}").as_bytes());
+                }
+            });
+            Self { url, requests }
+        }
+
+        fn client(&self) -> ShaderApiClient {
+            ShaderApiClient::new(Some(&self.url)).with_retry_policy(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(1),
+            })
+        }
+
+        fn hits(&self, path: &str) -> usize {
+            let requests = self.requests.lock().unwrap();
+            requests.iter().filter(|r| r.path == path).count()
+        }
+    }
+
+    fn read_request(stream: &mut std::net::TcpStream) -> Option<Request> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next()?.to_string();
+        let path = parts.next()?.to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).ok()?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':')?;
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+        let len = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .map_or(0, |(_, value)| value.parse().unwrap_or(0));
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).ok()?;
+        Some(Request {
+            method,
+            path,
+            headers,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+    }
+
+    fn shader_json(id: ShaderId, revision: Option<&str>) -> String {
+        serde_json::json!({
+            "id": id,
+            "name": "Waves",
+            "project": "{}",
+            "revision": revision,
+        })
+        .to_string()
+    }
+
+    fn upload() -> ShaderUpload {
+        ShaderUpload {
+            name: "Waves".to_string(),
+            project: "{}".to_string(),
+            parent: None,
+        }
+    }
+
+    /// reqwest needs a tokio runtime natively.
+    fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(fut)
+    }
+
+    #[test]
+    fn create_read_update_delete_and_list() {
+        let server = MockServer::start(|req| match (req.method.as_str(), req.path.as_str()) {
+            ("POST", "/shaders") => Reply::Json(201, Vec::new(), shader_json(7, Some("r1"))),
+            ("GET", "/shaders/7") => {
+                Reply::Json(200, vec![("ETag", "\"r2\"".into())], shader_json(7, None))
+            }
+            ("PUT", "/shaders/7") => Reply::Json(200, Vec::new(), shader_json(7, Some("r3"))),
+            ("DELETE", "/shaders/7") => Reply::Status(204),
+            ("GET", path) if path.starts_with("/shaders?") => Reply::Json(
+                200,
+                Vec::new(),
+                r#"[{"id": 7, "name": "Waves", "tags": ["water"]}]"#.to_string(),
+            ),
+            _ => Reply::Status(404),
+        });
+        let client = server.client();
+
+        let created = block_on(client.create(&upload())).unwrap();
+        assert_eq!(created.id, 7);
+        assert_eq!(created.revision.as_deref(), Some("r1"));
+
+        let read = block_on(client.read(7)).unwrap();
+        assert_eq!(read.revision.as_deref(), Some("r2"));
+
+        let updated = block_on(client.update(7, &upload(), Some("r2"))).unwrap();
+        assert_eq!(updated.revision.as_deref(), Some("r3"));
+
+        block_on(client.delete(7)).unwrap();
+
+        let query = ListQuery {
+            search: Some("waves".to_string()),
+            public: true,
+            ..Default::default()
+        };
+        let listed = block_on(client.list(&query)).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].tags, ["water"]);
+
+        let requests = server.requests.lock().unwrap();
+        let post = &requests[0];
+        assert_eq!(post.header("content-type"), Some("application/json"));
+        let sent: ShaderUpload = serde_json::from_str(&post.body).unwrap();
+        assert_eq!(sent.name, "Waves");
+        assert_eq!(requests[2].header("if-match"), Some("\"r2\""));
+        assert_eq!(requests[4].path, "/shaders?search=waves&public=true");
+    }
+
+    #[test]
+    fn weak_etags_are_not_revisions() {
+        let server = MockServer::start(|_| {
+            Reply::Json(200, vec![("ETag", "W/\"r2\"".into())], shader_json(7, None))
+        });
+        let read = block_on(server.client().read(7)).unwrap();
+        assert_eq!(read.revision, None);
+    }
+
+    #[test]
+    fn revisions_stay_one_path_segment() {
+        let server =
+            MockServer::start(|_| Reply::Json(200, Vec::new(), shader_json(7, Some("a/b?c#d"))));
+        let read = block_on(server.client().read_revision(7, "a/b?c#d")).unwrap();
+        assert_eq!(read.revision.as_deref(), Some("a/b?c#d"));
+        assert_eq!(server.hits("/shaders/7/revisions/a%2Fb%3Fc%23d"), 1);
+    }
+
+    #[test]
+    fn missing_shader_is_not_found() {
+        let server = MockServer::start(|_| Reply::Status(404));
+        let res = block_on(server.client().read(1));
+        assert!(matches!(res, Err(ShaderApiError::NotFound)));
+        assert_eq!(server.hits("/shaders/1"), 1);
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let attempts = Mutex::new(0);
+        let server = MockServer::start(move |_| {
+            let mut attempts = attempts.lock().unwrap();
+            *attempts += 1;
+            match *attempts {
+                1 | 2 => Reply::Status(503),
+                _ => Reply::Json(200, Vec::new(), shader_json(1, Some("r1"))),
+            }
+        });
+        block_on(server.client().read(1)).unwrap();
+        assert_eq!(server.hits("/shaders/1"), 3);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_attempt() {
+        let server = MockServer::start(|_| Reply::Status(500));
+        let res = block_on(server.client().read(1));
+        assert!(matches!(
+            res,
+            Err(ShaderApiError::Status { status, .. }) if status == StatusCode::INTERNAL_SERVER_ERROR
+        ));
+        assert_eq!(server.hits("/shaders/1"), 3);
+    }
+
+    #[test]
+    fn retries_dropped_connections() {
+        let attempts = Mutex::new(0);
+        let server = MockServer::start(move |_| {
+            let mut attempts = attempts.lock().unwrap();
+            *attempts += 1;
+            match *attempts {
+                1 => Reply::Hangup,
+                _ => Reply::Json(200, Vec::new(), shader_json(1, Some("r1"))),
+            }
+        });
+        block_on(server.client().read(1)).unwrap();
+        assert_eq!(server.hits("/shaders/1"), 2);
+    }
+
+    #[test]
+    fn does_not_retry_create() {
+        let server = MockServer::start(|_| Reply::Status(503));
+        let res = block_on(server.client().create(&upload()));
+        assert!(matches!(res, Err(ShaderApiError::Status { .. })));
+        assert_eq!(server.hits("/shaders"), 1);
+    }
+
+    #[test]
+    fn stale_revisions_conflict() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/shaders/1" => Reply::Status(412),
+            _ => Reply::Status(409),
+        });
+        let client = server.client();
+        let res = block_on(client.update(1, &upload(), Some("r1")));
+        assert!(matches!(res, Err(ShaderApiError::Conflict)));
+        let res = block_on(client.update(2, &upload(), None));
+        assert!(matches!(res, Err(ShaderApiError::Conflict)));
+        // A conflict will not go away by trying again.
+        assert_eq!(server.hits("/shaders/1"), 1);
+    }
+
+    /// The only test that installs a configuration, since that can happen once
+    /// per process. The server doubles as the login provider.
+    #[test]
+    fn refreshes_the_session_once_on_unauthorized() {
+        let issuer = Arc::new(Mutex::new(String::new()));
+        let server = MockServer::start({
+            let issuer = issuer.clone();
+            move |req| {
+                let issuer = issuer.lock().unwrap().clone();
+                match req.path.as_str() {
+                    "/.well-known/openid-configuration" => Reply::Json(
+                        200,
+                        Vec::new(),
+                        serde_json::json!({
+                            "issuer": issuer,
+                            "authorization_endpoint": format!("{issuer}/authorize"),
+                            "token_endpoint": format!("{issuer}/token"),
+                            "jwks_uri": format!("{issuer}/jwks"),
+                            "response_types_supported": ["code"],
+                            "subject_types_supported": ["public"],
+                            "id_token_signing_alg_values_supported": ["RS256"],
+                        })
+                        .to_string(),
+                    ),
+                    "/jwks" => Reply::Json(200, Vec::new(), r#"{"keys": []}"#.to_string()),
+                    "/token" => Reply::Json(
+                        200,
+                        Vec::new(),
+                        r#"{"access_token": "fresh", "token_type": "Bearer"}"#.to_string(),
+                    ),
+                    "/shaders/1" if req.header("authorization") == Some("Bearer fresh") => {
+                        Reply::Json(200, Vec::new(), shader_json(1, Some("r1")))
+                    }
+                    _ => Reply::Status(401),
+                }
+            }
+        });
+        *issuer.lock().unwrap() = server.url.clone();
+        config::install(
+            ConfigLayer::builtin()
+                .overlay(ConfigLayer {
+                    issuer: Some(server.url.clone()),
+                    ..Default::default()
+                })
+                .validate()
+                .unwrap(),
+        );
+
+        let session = SessionHandle::new(Some(Session {
+            user: UserInfo {
+                subject: "tester".to_string(),
+                name: None,
+                email: None,
+            },
+            access_token: "stale".to_string(),
+            id_token: String::new(),
+            refresh_token: Some("refresh".to_string()),
+            expires_at: None,
+        }));
+        let mut client = server.client();
+        client.set_session(session.clone());
+
+        block_on(client.read(1)).unwrap();
+        assert_eq!(server.hits("/shaders/1"), 2);
+        assert_eq!(server.hits("/token"), 1);
+        assert_eq!(session.access_token().as_deref(), Some("fresh"));
+
+        // A token the API still refuses after the refresh is not refreshed again.
+        let res = block_on(client.read(2));
+        assert!(matches!(res, Err(ShaderApiError::Unauthorized)));
+        assert_eq!(server.hits("/shaders/2"), 2);
+        assert_eq!(server.hits("/token"), 2);
+    }
+}
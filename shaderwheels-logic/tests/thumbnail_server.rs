@@ -0,0 +1,100 @@
+//! Runs the thumbnail server on a free port and talks HTTP to it.
+
+#![cfg(feature = "thumbnail-server")]
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::OnceLock,
+};
+
+use shaderwheels_logic::{
+    rendering::{DEFAULT_WGSL_FRAG, headless::HeadlessRenderer},
+    thumbnail_server::ThumbnailServer,
+};
+
+/// One server for every test, or `None` on machines without any adapter.
+fn server_addr() -> Option<SocketAddr> {
+    static ADDR: OnceLock<Option<SocketAddr>> = OnceLock::new();
+    *ADDR.get_or_init(|| {
+        let renderer = match pollster::block_on(HeadlessRenderer::new()) {
+            Ok(renderer) => renderer,
+            Err(e) => {
+                eprintln!("Skipping the thumbnail server tests: {e}");
+                return None;
+            }
+        };
+        let server = ThumbnailServer::bind("127.0.0.1:0", renderer).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+        Some(addr)
+    })
+}
+
+struct Reply {
+    status: u16,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+fn post(addr: SocketAddr, path: &str, body: &str) -> Reply {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\nHost: {addr}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+
+    let split = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8_lossy(&raw[..split]).into_owned();
+    let mut lines = head.lines();
+    let status = lines.next().unwrap().split_whitespace().nth(1).unwrap();
+    let content_type = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        .map(|(_, value)| value.trim().to_string());
+    Reply {
+        status: status.parse().unwrap(),
+        content_type,
+        body: raw[split + 4..].to_vec(),
+    }
+}
+
+fn project(source: &str) -> String {
+    serde_json::json!({ "source": source, "language": "Wgsl" }).to_string()
+}
+
+#[test]
+fn renders_a_project_as_png() {
+    let Some(addr) = server_addr() else { return };
+    let reply = post(
+        addr,
+        "/thumbnail?width=32&height=16&time=1.5",
+        &project(DEFAULT_WGSL_FRAG),
+    );
+    assert_eq!(reply.status, 200, "{}", String::from_utf8_lossy(&reply.body));
+    assert_eq!(reply.content_type.as_deref(), Some("image/png"));
+
+    let decoder = png::Decoder::new(std::io::Cursor::new(reply.body));
+    let info = decoder.read_info().unwrap().info().clone();
+    assert_eq!((info.width, info.height), (32, 16));
+}
+
+#[test]
+fn rejects_bad_parameters() {
+    let Some(addr) = server_addr() else { return };
+    let body = project(DEFAULT_WGSL_FRAG);
+    assert_eq!(post(addr, "/thumbnail?width=wide", &body).status, 400);
+    assert_eq!(post(addr, "/thumbnail?depth=3", &body).status, 400);
+    assert_eq!(post(addr, "/thumbnail?width=0", &body).status, 400);
+}
+
+#[test]
+fn rejects_shaders_that_do_not_compile() {
+    let Some(addr) = server_addr() else { return };
+    let reply = post(addr, "/thumbnail", &project("fn main( {"));
+    assert_eq!(reply.status, 422);
+}
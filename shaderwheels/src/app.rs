@@ -1,20 +1,69 @@
-use egui::{KeyboardShortcut, Modifiers, ViewportCommand, Widget};
+use egui::{ViewportCommand, Widget};
 use egui_tiles::Tree;
+use shaderwheels_logic::web::shader_api::{RemoteShader, ShaderApiError, ShaderId};
 
 mod tiles_tree_stuff;
 
+pub const APP_NAME: &str = "ShaderWheels";
+
+pub use egui_shaderwheels_logic::wgpu_options;
+
+#[cfg(target_arch = "wasm32")]
 use crate::app::{
+    browser_library::{BrowserLibraryWindow, LibraryAction},
+    shader_content_manager::BrowserShaderEntry,
+};
+use crate::app::{
+    account::{Account, AccountAction, AccountEvent},
+    compare_pane::ComparePane,
     egui_shaderwheels_logic::RenderCtx,
-    shader_content_manager::{ShaderInfo, ShaderStorageConnection, ShaderStorageConnectionManager},
-    tiles_tree_stuff::{create_basic_tree, ShaderWheelsPane, TreeBehavior},
+    executor::{Task, spawn_task},
+    file_menu::{FileAction, RecentFiles, UnsavedChangesChoice},
+    gallery::{Gallery, RemoteOpenMode},
+    gpu_pane::GpuPane,
+    merge_dialog::{ConflictChoice, ConflictDialog},
+    previews::{Previews, ViewAction},
+    render_settings::RenderSettings,
+    revision_history::{RevisionAction, RevisionHistory},
+    shader_content_manager::{
+        ConcreteOrUndecidedLocation, SaveError, ShaderDBEntry, ShaderInfo,
+        ShaderStorageConnection, ShaderStorageConnectionManager, ShaderStorageLocation,
+        ShaderStorageTypePreference,
+    },
+    shadertoy_import::{ImportedShader, ShadertoyImporter},
+    share_link::{ShareDialogChoice, SharedShader},
+    tiles_tree_stuff::{create_basic_tree, show_pane, PaneType, ShaderWheelsPane, TreeBehavior},
 };
 
+mod account;
+#[cfg(target_arch = "wasm32")]
+mod browser_library;
+#[cfg(target_arch = "wasm32")]
+mod browser_storage;
 mod cfg_pane;
+mod compare_pane;
 mod editor_gui;
 mod egui_shaderwheels_logic;
 mod eguice_syntax;
 mod error_viewer;
+mod executor;
+mod file_menu;
+mod gallery;
+mod gpu_pane;
+#[cfg(not(target_arch = "wasm32"))]
+mod hdr_export;
+mod merge_dialog;
+mod outbox;
+mod pipeline_cache;
+mod png_export;
+mod previews;
+mod project_file;
+mod render_settings;
+mod revision_history;
 mod shader_content_manager;
+mod shadertoy_import;
+mod share_link;
+mod stats_pane;
 
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
@@ -32,6 +81,98 @@ pub struct App {
 
     #[serde(skip)]
     compile_on_change: bool,
+
+    recent_files: RecentFiles,
+
+    #[serde(skip)]
+    pending_action: Option<FileAction>,
+
+    /// An action that arrived from outside the UI, such as a share link.
+    #[serde(skip)]
+    incoming_action: Option<FileAction>,
+
+    #[serde(skip)]
+    allow_close: bool,
+
+    #[serde(skip)]
+    status: Option<StatusMessage>,
+
+    account: Account,
+
+    #[serde(skip)]
+    long_share_link: Option<String>,
+
+    #[serde(skip)]
+    share_upload: Option<Task<Result<RemoteShader, ShaderApiError>>>,
+
+    #[serde(skip)]
+    remote_open: Option<(RemoteOpenMode, Task<Result<RemoteShader, ShaderApiError>>)>,
+
+    #[serde(skip)]
+    gallery: Gallery,
+
+    #[serde(skip)]
+    gpu_pane: GpuPane,
+
+    #[serde(skip)]
+    previews: Previews,
+
+    #[serde(skip)]
+    compare_pane: ComparePane,
+
+    render_settings: RenderSettings,
+
+    /// Set for shaders opened from the gallery for viewing only.
+    #[serde(skip)]
+    read_only: bool,
+
+    #[serde(skip)]
+    conflict: Option<ConflictDialog>,
+
+    #[serde(skip)]
+    revision_history: RevisionHistory,
+
+    #[serde(skip)]
+    shadertoy_import: ShadertoyImporter,
+
+    /// The path an HDR export is being written to.
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip)]
+    hdr_export: Option<Task<Result<std::path::PathBuf, String>>>,
+
+    #[cfg(target_arch = "wasm32")]
+    #[serde(skip)]
+    browser_library: BrowserLibraryWindow,
+}
+
+struct StatusMessage {
+    text: String,
+    is_error: bool,
+}
+
+impl StatusMessage {
+    fn info(text: String) -> Self {
+        Self {
+            text,
+            is_error: false,
+        }
+    }
+
+    fn error(text: String) -> Self {
+        Self {
+            text,
+            is_error: true,
+        }
+    }
+
+    fn ui(&self, ui: &mut egui::Ui) {
+        let text = egui::RichText::new(&self.text);
+        if self.is_error {
+            ui.label(text.color(ui.visuals().error_fg_color));
+        } else {
+            ui.label(text);
+        }
+    }
 }
 
 impl Default for App {
@@ -42,6 +183,28 @@ impl Default for App {
             compile_on_change: true,
             tree: create_basic_tree(),
             storage_manager: ShaderStorageConnectionManager::default(),
+            recent_files: RecentFiles::default(),
+            pending_action: None,
+            incoming_action: None,
+            allow_close: false,
+            status: None,
+            account: Account::default(),
+            long_share_link: None,
+            share_upload: None,
+            remote_open: None,
+            gallery: Gallery::default(),
+            gpu_pane: GpuPane::default(),
+            previews: Previews::default(),
+            compare_pane: ComparePane::default(),
+            render_settings: RenderSettings::default(),
+            read_only: false,
+            conflict: None,
+            revision_history: RevisionHistory::default(),
+            shadertoy_import: ShadertoyImporter::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            hdr_export: None,
+            #[cfg(target_arch = "wasm32")]
+            browser_library: BrowserLibraryWindow::default(),
         }
     }
 }
@@ -55,21 +218,720 @@ impl App {
         // Load previous app state (if any).
         // Note that you must enable the `persistence` feature for this to work.
 
-        let state: App = if let Some(storage) = cc.storage {
+        let mut state: App = if let Some(storage) = cc.storage {
             eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default()
         } else {
             Default::default()
         };
 
+        #[cfg(not(target_arch = "wasm32"))]
+        state.account.restore();
+        #[cfg(target_arch = "wasm32")]
+        state.account.resume_login(&cc.egui_ctx);
+        state
+            .storage_manager
+            .api
+            .set_session(state.account.session.clone());
+        state.storage_manager.outbox.restore();
+
         let mut rctx = egui_shaderwheels_logic::onetime_hardware_setup(cc);
 
         //rctx.dep_graph
         rctx.client
-            .set_shader_text(state.current_shader_inf.contents.clone());
+            .set_shader_config(state.current_shader_inf.to_shader_config());
         //rctx.dep_graph.set_entry_point("main".to_string());
 
         Self { inf: rctx, ..state }
     }
+
+    /// Opens the shader in a share link's fragment as an unsaved shader.
+    pub fn open_share_fragment(&mut self, ctx: &egui::Context, fragment: &str) {
+        let Some(shared) = share_link::parse_fragment(fragment) else {
+            return;
+        };
+        #[cfg(target_arch = "wasm32")]
+        share_link::clear_location_fragment();
+
+        match shared {
+            Ok(SharedShader::Inline(info)) => {
+                self.incoming_action = Some(FileAction::OpenShared(info))
+            }
+            Ok(SharedShader::Remote(id)) => self.open_remote(ctx, id, RemoteOpenMode::Copy),
+            Err(e) => {
+                self.status = Some(StatusMessage::error(format!(
+                    "Could not open share link: {e}"
+                )))
+            }
+        }
+    }
+
+    fn open_remote(&mut self, ctx: &egui::Context, id: ShaderId, mode: RemoteOpenMode) {
+        let api = self.storage_manager.api.clone();
+        self.remote_open = Some((mode, spawn_task(ctx, async move { api.read(id).await })));
+    }
+
+    fn open_remote_shader(&mut self, entry: ShaderDBEntry, info: ShaderInfo, mode: RemoteOpenMode) {
+        match mode {
+            RemoteOpenMode::ReadOnly => {
+                let location =
+                    ConcreteOrUndecidedLocation::Concrete(ShaderStorageLocation::RemoteDB(entry));
+                self.open_shader(ShaderStorageConnection::new(location, info.clone()), info);
+                self.read_only = true;
+            }
+            RemoteOpenMode::Fork => self.fork_shader(info, Some(entry.id)),
+            RemoteOpenMode::Copy => self.open_unsaved_shader(info),
+        }
+    }
+
+    /// Opens a copy of `info` that becomes a new database entry once saved,
+    /// linked to `parent` if it came from one.
+    fn fork_shader(&mut self, mut info: ShaderInfo, parent: Option<ShaderId>) {
+        info.name = format!("{} (fork)", info.name);
+        let preference = match parent {
+            Some(parent) => ShaderStorageTypePreference::Fork(parent),
+            None => ShaderStorageTypePreference::DB,
+        };
+        let connection = ShaderStorageConnection::new(
+            ConcreteOrUndecidedLocation::Undecided(preference),
+            ShaderInfo::default(),
+        );
+        self.open_shader(connection, info);
+    }
+
+    fn open_shader(&mut self, connection: ShaderStorageConnection, info: ShaderInfo) {
+        self.read_only = false;
+        self.storage_manager.replace_connection(connection);
+        self.current_shader_inf = info;
+        self.inf
+            .client
+            .set_shader_config(self.current_shader_inf.to_shader_config());
+    }
+
+    /// Replaces the shader being edited with one that has no storage location yet.
+    fn open_unsaved_shader(&mut self, info: ShaderInfo) {
+        self.open_shader(ShaderStorageConnection::default(), info);
+    }
+
+    /// Opens the shader in an exported PNG, paused at the frame that was exported.
+    fn open_png_export(&mut self, meta: png_export::ExportMetadata) {
+        self.open_unsaved_shader(meta.info);
+        let client = &mut self.inf.client;
+        if let Some(format) = meta.preout_format {
+            client.set_preout_format(format);
+        }
+        if let Some(tonemapping) = meta.tonemapping {
+            client.set_tonemapping(tonemapping);
+        }
+        if let Some(time_ms) = meta.time_ms {
+            client.set_animating(false);
+            client.set_time_ms(time_ms);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn open_path(&mut self, path: std::path::PathBuf) {
+        let info = match project_file::load_project_from_path(&path) {
+            Ok(info) => info,
+            Err(e) => {
+                log::error!("Failed to open {}: {}", path.display(), e);
+                self.recent_files.remove(&path);
+                return;
+            }
+        };
+
+        // Bare shader sources are opened as new projects, so saving never overwrites them.
+        let is_project = path
+            .extension()
+            .is_some_and(|ext| ext == project_file::PROJECT_EXTENSION);
+        let location = if is_project {
+            ConcreteOrUndecidedLocation::Concrete(ShaderStorageLocation::File(
+                shader_content_manager::ShaderFileLocation { path: path.clone() },
+            ))
+        } else {
+            ConcreteOrUndecidedLocation::default()
+        };
+
+        self.open_shader(ShaderStorageConnection::new(location, info.clone()), info);
+        self.recent_files.push(path);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn open_browser_shader(&mut self, entry: BrowserShaderEntry) {
+        match browser_storage::load(&entry) {
+            Ok(info) => {
+                let location =
+                    ConcreteOrUndecidedLocation::Concrete(ShaderStorageLocation::Browser(entry));
+                self.open_shader(ShaderStorageConnection::new(location, info.clone()), info);
+                self.browser_library.open = false;
+            }
+            Err(e) => self.status = Some(StatusMessage::error(format!("Open failed: {e}"))),
+        }
+    }
+
+    /// Opens the first imported pass. Several passes are stored first, so none
+    /// of them is lost when the next one is opened.
+    fn open_imported(&mut self, shaders: Vec<ImportedShader>) {
+        let count = shaders.len();
+        // Said in the status as well, since nothing else shows that the passes
+        // render without their channels.
+        let partially = if shaders.iter().any(|s| s.partial) {
+            "Partially imported"
+        } else {
+            "Imported"
+        };
+        let passes: Vec<ShaderInfo> = shaders.into_iter().flat_map(|s| s.passes).collect();
+        let warnings = match self.shadertoy_import.warning_count() {
+            0 => String::new(),
+            n => format!(", {n} warnings"),
+        };
+        match passes.len() {
+            0 => {
+                self.status = Some(StatusMessage::error(format!(
+                    "Nothing could be imported{warnings}"
+                )))
+            }
+            1 => {
+                self.open_unsaved_shader(passes.into_iter().next().unwrap());
+                self.status = Some(StatusMessage::info(format!(
+                    "{partially} from Shadertoy{warnings}"
+                )));
+            }
+            n => {
+                if self.store_imported(passes) {
+                    self.status = Some(StatusMessage::info(format!(
+                        "{partially} {n} passes of {count} shaders, as separate projects{warnings}"
+                    )));
+                }
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn store_imported(&mut self, passes: Vec<ShaderInfo>) -> bool {
+        let Some(dir) = rfd::FileDialog::new()
+            .set_title("Folder for the imported projects")
+            .pick_folder()
+        else {
+            self.status = Some(StatusMessage::info("Import cancelled".to_string()));
+            return false;
+        };
+
+        let mut first = None;
+        for info in &passes {
+            let path = project_file::unused_project_path(&dir, &info.name);
+            if let Err(e) = std::fs::write(&path, project_file::serialize_project(info)) {
+                self.status = Some(StatusMessage::error(format!(
+                    "Could not write {}: {e}",
+                    path.display()
+                )));
+                return false;
+            }
+            first.get_or_insert(path);
+        }
+        if let Some(path) = first {
+            self.open_path(path);
+        }
+        true
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn store_imported(&mut self, passes: Vec<ShaderInfo>) -> bool {
+        let mut first = None;
+        for info in &passes {
+            let stored = browser_storage::new_entry()
+                .and_then(|entry| browser_storage::save(&entry, info).map(|_| entry));
+            match stored {
+                Ok(entry) => {
+                    first.get_or_insert(entry);
+                }
+                Err(e) => {
+                    self.status = Some(StatusMessage::error(format!("Import failed: {e}")));
+                    return false;
+                }
+            }
+        }
+        if let Some(entry) = first {
+            self.open_browser_shader(entry);
+        }
+        true
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn rename_browser_shader(&mut self, entry: BrowserShaderEntry, name: String) {
+        if let Err(e) = browser_storage::rename(&entry, &name) {
+            self.status = Some(StatusMessage::error(format!("Rename failed: {e}")));
+            return;
+        }
+
+        let is_current = matches!(
+            self.storage_manager.connection.get_location(),
+            ConcreteOrUndecidedLocation::Concrete(ShaderStorageLocation::Browser(current))
+                if *current == entry
+        );
+        if is_current {
+            self.current_shader_inf.name = name.clone();
+            self.storage_manager.connection.rename_in_location(&name);
+        }
+    }
+
+    fn has_unsaved_changes(&self) -> bool {
+        self.storage_manager
+            .connection
+            .saving_needed(&self.current_shader_inf)
+    }
+
+    fn request_file_action(
+        &mut self,
+        action: FileAction,
+        ctx: &egui::Context,
+        frame: &eframe::Frame,
+    ) {
+        if action.discards_current() && self.has_unsaved_changes() {
+            self.pending_action = Some(action);
+        } else {
+            self.perform_file_action(action, ctx, frame);
+        }
+    }
+
+    fn perform_file_action(
+        &mut self,
+        action: FileAction,
+        ctx: &egui::Context,
+        frame: &eframe::Frame,
+    ) {
+        match action {
+            FileAction::New(template) => self.open_unsaved_shader(template.to_info()),
+            FileAction::Save if self.read_only => {
+                self.status = Some(StatusMessage::info(
+                    "This shader is read-only, fork it or use Save As".to_string(),
+                ))
+            }
+            FileAction::Save => self
+                .storage_manager
+                .start_save(ctx, &self.current_shader_inf),
+            FileAction::SaveAs => self
+                .storage_manager
+                .start_save_as(ctx, &self.current_shader_inf),
+            FileAction::OpenShared(info) => self.open_unsaved_shader(info),
+            FileAction::OpenRemote(entry, info, mode) => self.open_remote_shader(entry, info, mode),
+            FileAction::OpenImported(shaders) => self.open_imported(shaders),
+            FileAction::OpenPngExport(meta) => self.open_png_export(meta),
+            FileAction::ImportShadertoy => self.shadertoy_import.pick_files(ctx),
+            FileAction::ShowRevisionHistory => match self.storage_manager.connection.remote_id() {
+                Some(id) => self
+                    .revision_history
+                    .open(ctx, &self.storage_manager.api, id),
+                None => {
+                    self.status = Some(StatusMessage::info(
+                        "Only shaders saved to the database have revisions".to_string(),
+                    ))
+                }
+            },
+            FileAction::CopyShareLink => {
+                let link =
+                    share_link::inline_link(&share_link::base_url(), &self.current_shader_inf);
+                if link.len() <= share_link::WARN_LINK_LEN {
+                    self.copy_share_link(ctx, link);
+                } else {
+                    self.long_share_link = Some(link);
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            FileAction::Open => {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("Shaders", project_file::OPENABLE_EXTENSIONS)
+                    .pick_file()
+                {
+                    self.open_path(path);
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            FileAction::OpenRecent(path) => self.open_path(path),
+            #[cfg(not(target_arch = "wasm32"))]
+            FileAction::ExportPng => {
+                if let Some(renderstate) = frame.wgpu_render_state() {
+                    self.export_png(renderstate);
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            FileAction::ExportHdr => {
+                if let Some(renderstate) = frame.wgpu_render_state() {
+                    self.export_hdr(ctx, renderstate);
+                }
+            }
+            FileAction::Quit => {
+                self.allow_close = true;
+                ctx.send_viewport_cmd(ViewportCommand::Close);
+            }
+            #[cfg(target_arch = "wasm32")]
+            FileAction::ShowSavedShaders => self.browser_library.open = true,
+            #[cfg(target_arch = "wasm32")]
+            FileAction::OpenBrowserShader(entry) => self.open_browser_shader(entry),
+            #[cfg(target_arch = "wasm32")]
+            _ => log::info!("This file action is not available on the web"),
+            #[cfg(not(target_arch = "wasm32"))]
+            FileAction::ShowSavedShaders | FileAction::OpenBrowserShader(_) => {
+                log::info!("Browser storage is only available on the web")
+            }
+        }
+    }
+
+    fn update_unsaved_changes_prompt(&mut self, ctx: &egui::Context, frame: &eframe::Frame) {
+        let Some(action) = self.pending_action.clone() else {
+            return;
+        };
+
+        // Either the changes were saved from the prompt, or they no longer differ.
+        if !self.has_unsaved_changes() {
+            self.pending_action = None;
+            self.perform_file_action(action, ctx, frame);
+            return;
+        }
+
+        let choice = file_menu::unsaved_changes_prompt(
+            ctx,
+            &self.current_shader_inf.name,
+            self.storage_manager.connection.currently_saving,
+        );
+        match choice {
+            Some(UnsavedChangesChoice::Save) => self
+                .storage_manager
+                .start_save(ctx, &self.current_shader_inf),
+            Some(UnsavedChangesChoice::Discard) => {
+                self.pending_action = None;
+                self.perform_file_action(action, ctx, frame);
+            }
+            Some(UnsavedChangesChoice::Cancel) => self.pending_action = None,
+            None => {}
+        }
+    }
+
+    fn copy_share_link(&mut self, ctx: &egui::Context, link: String) {
+        ctx.copy_text(link);
+        self.status = Some(StatusMessage::info(
+            "Share link copied to the clipboard".to_string(),
+        ));
+    }
+
+    fn update_remote_open(&mut self) {
+        let Some((mode, task)) = &self.remote_open else {
+            return;
+        };
+        let Some(res) = task.try_take() else {
+            return;
+        };
+        let mode = *mode;
+        self.remote_open = None;
+
+        let opened = res.map_err(|e| e.to_string()).and_then(|remote| {
+            let entry = ShaderDBEntry {
+                id: remote.id,
+                revision: remote.revision,
+            };
+            project_file::deserialize_project(&remote.project, &remote.name)
+                .map(|info| (entry, info))
+                .map_err(|e| e.to_string())
+        });
+        match opened {
+            Ok((entry, info)) => {
+                self.incoming_action = Some(FileAction::OpenRemote(entry, info, mode))
+            }
+            Err(e) => {
+                self.status = Some(StatusMessage::error(format!("Could not open shader: {e}")))
+            }
+        }
+    }
+
+    fn update_sharing(&mut self, ctx: &egui::Context) {
+        if let Some(res) = self.share_upload.as_ref().and_then(|t| t.try_take()) {
+            self.share_upload = None;
+            match res {
+                Ok(remote) => {
+                    self.long_share_link = None;
+                    let link = share_link::remote_link(&share_link::base_url(), remote.id);
+                    self.copy_share_link(ctx, link);
+                }
+                Err(e) => self.status = Some(StatusMessage::error(format!("Upload failed: {e}"))),
+            }
+        }
+
+        let Some(link) = &self.long_share_link else {
+            return;
+        };
+        match share_link::long_link_prompt(ctx, link.len(), self.share_upload.is_some()) {
+            Some(ShareDialogChoice::CopyAnyway) => {
+                if let Some(link) = self.long_share_link.take() {
+                    self.copy_share_link(ctx, link);
+                }
+            }
+            Some(ShareDialogChoice::Upload) => {
+                let api = self.storage_manager.api.clone();
+                let upload = self.current_shader_inf.to_upload();
+                self.share_upload = Some(spawn_task(ctx, async move { api.create(&upload).await }));
+            }
+            Some(ShareDialogChoice::Cancel) => self.long_share_link = None,
+            None => {}
+        }
+    }
+
+    fn open_conflict(&mut self, latest: RemoteShader) {
+        match project_file::deserialize_project(&latest.project, &latest.name) {
+            Ok(theirs) => {
+                self.conflict = Some(ConflictDialog::new(
+                    &self.current_shader_inf,
+                    theirs,
+                    latest.revision,
+                    latest.owner,
+                ))
+            }
+            Err(e) => {
+                self.status = Some(StatusMessage::error(format!(
+                    "Save conflicted with a version that could not be read: {e}"
+                )))
+            }
+        }
+    }
+
+    fn update_conflict(&mut self, ctx: &egui::Context) {
+        let Some(choice) = self.conflict.as_mut().and_then(|dialog| dialog.ui(ctx)) else {
+            return;
+        };
+        let Some(dialog) = self.conflict.take() else {
+            return;
+        };
+
+        // Resolving it bases the next save on their revision, so it goes through.
+        match choice {
+            ConflictChoice::KeepMine => {
+                self.storage_manager
+                    .adopt_remote_version(dialog.theirs, dialog.revision);
+                self.storage_manager
+                    .start_save(ctx, &self.current_shader_inf);
+            }
+            ConflictChoice::TakeTheirs => {
+                self.storage_manager
+                    .adopt_remote_version(dialog.theirs.clone(), dialog.revision);
+                self.current_shader_inf = dialog.theirs;
+                self.inf
+                    .client
+                    .set_shader_config(self.current_shader_inf.to_shader_config());
+            }
+            ConflictChoice::SaveMerged(merged) => {
+                self.storage_manager
+                    .adopt_remote_version(dialog.theirs, dialog.revision);
+                self.replace_contents(merged);
+                self.storage_manager
+                    .start_save(ctx, &self.current_shader_inf);
+            }
+            ConflictChoice::DecideLater => {
+                self.status = Some(StatusMessage::info(
+                    "Not saved; saving again will bring the conflict back".to_string(),
+                ))
+            }
+        }
+    }
+
+    fn update_revision_history(&mut self, ctx: &egui::Context, frame: &eframe::Frame) {
+        let (remote, preview) = match self.revision_history.ui(ctx, &self.storage_manager.api) {
+            Some(RevisionAction::Restore(remote)) => (remote, false),
+            Some(RevisionAction::Preview(remote)) => (remote, true),
+            None => return,
+        };
+        match project_file::deserialize_project(&remote.project, &remote.name) {
+            Ok(info) if preview => {
+                let title = format!("{} @ {}", info.name, remote.revision.unwrap_or_default());
+                self.open_preview(title, &info, ctx, frame);
+            }
+            Ok(info) => {
+                // Restoring is an ordinary edit, so saving it makes a new revision.
+                self.replace_contents(info.contents);
+                self.status = Some(StatusMessage::info(format!(
+                    "Restored revision {}; save to keep it",
+                    remote.revision.unwrap_or_default()
+                )));
+            }
+            Err(e) => {
+                self.status = Some(StatusMessage::error(format!(
+                    "Could not read that revision: {e}"
+                )))
+            }
+        }
+    }
+
+    /// Opens a preview pane with its own worker, rendering a copy of `info`.
+    fn open_preview(
+        &mut self,
+        title: String,
+        info: &ShaderInfo,
+        ctx: &egui::Context,
+        frame: &eframe::Frame,
+    ) {
+        let renderstate = frame.wgpu_render_state().unwrap();
+        let rctx = self
+            .inf
+            .new_preview(renderstate, ctx, info.to_shader_config());
+        let id = self.previews.open(title, rctx);
+        show_pane(&mut self.tree, PaneType::Preview(id));
+    }
+
+    /// Swaps the shader source without touching where it is stored.
+    fn replace_contents(&mut self, contents: String) {
+        self.current_shader_inf.contents = contents;
+        self.inf
+            .client
+            .set_shader_config(self.current_shader_inf.to_shader_config());
+    }
+
+    fn perform_account_action(&mut self, action: AccountAction, ctx: &egui::Context) {
+        match action {
+            AccountAction::LogIn => self.account.start_login(ctx),
+            AccountAction::CancelLogin => self.account.cancel_login(),
+            AccountAction::LogOut => {
+                self.account.logout(ctx);
+                self.status = Some(StatusMessage::info("Logged out".to_string()));
+            }
+        }
+    }
+
+    /// Opens dropped files through the same prompt as the file menu, so unsaved
+    /// edits are not thrown away without asking.
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped = ctx.input(|i| i.raw.dropped_files.clone());
+        let mut action = None;
+        let mut shadertoy_files = Vec::new();
+        for file in dropped {
+            if file.name.ends_with(".png") {
+                match png_export::metadata_from_dropped_file(&file) {
+                    Some(meta) => action = Some(FileAction::OpenPngExport(meta)),
+                    None => log::info!("Dropped file {} has no shader metadata", file.name),
+                }
+                continue;
+            }
+
+            let bytes = match (&file.bytes, &file.path) {
+                (Some(bytes), _) => Ok(bytes.to_vec()),
+                (None, Some(path)) => std::fs::read(path),
+                (None, None) => continue,
+            };
+            if file.name.ends_with(".json") {
+                match bytes {
+                    Ok(bytes) => shadertoy_files.push((file.name.clone(), bytes)),
+                    Err(e) => log::warn!("Could not read dropped file {}: {}", file.name, e),
+                }
+                continue;
+            }
+            let name = file
+                .path
+                .clone()
+                .unwrap_or_else(|| file.name.clone().into());
+            match bytes
+                .map_err(project_file::ProjectFileError::Io)
+                .and_then(|bytes| project_file::project_from_file(&name, &bytes))
+            {
+                Ok(info) => action = Some(FileAction::OpenShared(info)),
+                Err(e) => log::warn!("Could not open dropped file {}: {}", file.name, e),
+            }
+        }
+
+        if !shadertoy_files.is_empty() {
+            let shaders = self.shadertoy_import.import_files(shadertoy_files);
+            action = Some(FileAction::OpenImported(shaders));
+        }
+        if action.is_some() {
+            self.incoming_action = action;
+            ctx.request_repaint();
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_png(&self, renderstate: &eframe::egui_wgpu::RenderState) {
+        let Some(frame) = egui_shaderwheels_logic::capture_present_buffer(renderstate, &self.inf)
+        else {
+            log::warn!("Nothing has been rendered yet, so there is nothing to export");
+            return;
+        };
+
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("PNG", &["png"])
+            .set_file_name(format!("{}.png", self.current_shader_inf.name))
+            .save_file()
+        else {
+            return;
+        };
+
+        let meta = png_export::ExportMetadata {
+            info: self.current_shader_inf.clone(),
+            resolution: frame.size,
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_secs()),
+            time_ms: self.inf.client.presented_frame().map(|f| f.time_ms),
+            preout_format: Some(self.inf.client.preout_format()),
+            tonemapping: Some(self.inf.client.tonemapping()),
+        };
+
+        let written = png_export::encode_png(&frame, &meta)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| std::fs::write(&path, bytes).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            log::error!("Failed to export {}: {}", path.display(), e);
+        }
+    }
+
+    /// Renders the presented frame again in 32-bit floats, without tonemapping,
+    /// and saves it as OpenEXR or Radiance HDR.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_hdr(&mut self, ctx: &egui::Context, renderstate: &eframe::egui_wgpu::RenderState) {
+        if self.hdr_export.is_some() {
+            self.status = Some(StatusMessage::info(
+                "An HDR export is still being written".to_string(),
+            ));
+            return;
+        }
+        let (Some(present), Some(presented)) =
+            (&self.inf.present_buffer, self.inf.client.presented_frame())
+        else {
+            log::warn!("Nothing has been rendered yet, so there is nothing to export");
+            return;
+        };
+
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("OpenEXR", &["exr"])
+            .add_filter("Radiance HDR", &["hdr"])
+            .set_file_name(format!("{}.exr", self.current_shader_inf.name))
+            .save_file()
+        else {
+            return;
+        };
+
+        // Rendering waits for the frame to come back from the GPU, which should
+        // not freeze the window.
+        let hardware = self.inf.hardware(renderstate);
+        let cfg = self.current_shader_inf.to_shader_config();
+        let (size, time_ms) = (present.size, presented.time_ms);
+        self.hdr_export = Some(spawn_task(ctx, async move {
+            shaderwheels_logic::rendering::headless::render_hdr(hardware, cfg, size, time_ms)
+                .map_err(|e| e.to_string())
+                .and_then(|frame| hdr_export::write_hdr(frame, &path).map_err(|e| e.to_string()))
+                .map(|_| path)
+        }));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn update_hdr_export(&mut self) {
+        let Some(res) = self.hdr_export.as_ref().and_then(|t| t.try_take()) else {
+            return;
+        };
+        self.hdr_export = None;
+        self.status = Some(match res {
+            Ok(path) => StatusMessage::info(format!("Exported {}", path.display())),
+            Err(e) => {
+                log::error!("HDR export failed: {e}");
+                StatusMessage::error(format!("HDR export failed: {e}"))
+            }
+        });
+    }
 }
 
 struct EguiCodeEditorWrapperWidget<'a> {
@@ -94,6 +956,7 @@ impl eframe::App for App {
     /// Called by the framework to save state before shutdown.
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
         eframe::set_value(storage, eframe::APP_KEY, self);
+        self.inf.save_pipeline_caches();
     }
 
     /// Called each time the UI needs repainting, which may be many times per second.
@@ -101,56 +964,144 @@ impl eframe::App for App {
         // Put your widgets into a `SidePanel`, `TopBottomPanel`, `CentralPanel`, `Window` or `Area`.
         // For inspiration and more examples, go to https://emilk.github.io/egui
 
+        self.update_remote_open();
+        let mut file_action =
+            file_menu::consume_file_shortcuts(ctx).or_else(|| self.incoming_action.take());
+
+        if ctx.input(|i| i.viewport().close_requested())
+            && !self.allow_close
+            && self.has_unsaved_changes()
+        {
+            ctx.send_viewport_cmd(ViewportCommand::CancelClose);
+            file_action = Some(FileAction::Quit);
+        }
+
+        match self.storage_manager.update(ctx) {
+            Some(Ok(())) => {
+                // Only Save As gets here for a read-only shader, and the copy is the user's own.
+                self.read_only = false;
+                if let ConcreteOrUndecidedLocation::Concrete(loc) =
+                    self.storage_manager.connection.get_location()
+                {
+                    self.status = Some(StatusMessage::info(format!("Saved to {loc}")));
+                    if let ShaderStorageLocation::File(file) = loc {
+                        self.recent_files.push(file.path.clone());
+                    }
+                }
+            }
+            Some(Err(SaveError::NoLocation)) => {
+                self.status = Some(StatusMessage::info("Save cancelled".to_string()))
+            }
+            Some(Err(SaveError::Conflict(latest))) => self.open_conflict(*latest),
+            Some(Err(e)) => self.status = Some(StatusMessage::error(format!("Save failed: {e}"))),
+            None => {}
+        }
+
+        match self.account.update(ctx) {
+            Some(AccountEvent::LoggedIn(name)) => {
+                self.status = Some(StatusMessage::info(format!("Logged in as {name}")))
+            }
+            Some(AccountEvent::LoginFailed(e)) => {
+                self.status = Some(StatusMessage::error(format!("Login failed: {e}")))
+            }
+            Some(AccountEvent::RefreshFailed(e)) => {
+                self.status = Some(StatusMessage::error(format!("Could not renew login: {e}")))
+            }
+            None => {}
+        }
+
         if !_frame.is_web() {
             let changed = self
                 .storage_manager
                 .connection
                 .saving_needed(&self.current_shader_inf);
+            let sync = match self.storage_manager.connection.remote_id() {
+                Some(id) => format!(" ({})", self.storage_manager.outbox.status_of(id).label()),
+                None => String::new(),
+            };
             let viewport_title = if changed {
                 self.current_shader_inf.name.clone() + "*"
             } else {
                 self.current_shader_inf.name.clone()
-            } + " - ShaderWheels";
+            } + &sync
+                + " - ShaderWheels";
             ctx.send_viewport_cmd(ViewportCommand::Title(viewport_title));
         }
 
-        let mut saved = false;
-
-        ctx.input_mut(|i| {
-            if i.consume_shortcut(&KeyboardShortcut::new(Modifiers::CTRL, egui::Key::S)) {
-                saved = true;
-            }
-        });
-
-        /*
-        if saved {
-            // Saving should happen here!
-            self.storage_manager.start_save(&self.current_shader_inf);
-        }*/
-
-        self.storage_manager.update();
+        self.handle_dropped_files(ctx);
 
         _frame.wgpu_render_state().unwrap();
 
+        let mut account_action = None;
+        let mut view_action = None;
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             // The top panel is often a good place for a menu bar:
 
             egui::MenuBar::new().ui(ui, |ui| {
-                // NOTE: no File->Quit on web pages!
-                let is_web = cfg!(target_arch = "wasm32");
-                if !is_web {
-                    ui.menu_button("File", |ui| {
-                        if ui.button("Quit").clicked() {
-                            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
-                        }
-                    });
-                    ui.add_space(16.0);
-                }
+                ui.menu_button("File", |ui| {
+                    if let Some(action) = file_menu::add_file_menu(ui, &self.recent_files) {
+                        file_action = Some(action);
+                    }
+                });
+                ui.menu_button("View", |ui| {
+                    view_action = previews::add_view_menu(ui);
+                });
+                ui.add_space(16.0);
 
                 egui::widgets::global_theme_preference_buttons(ui);
+
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    account_action = self.account.ui(ui);
+                    self.storage_manager.outbox.ui(ui);
+                    if let Some(status) = &self.status {
+                        ui.separator();
+                        status.ui(ui);
+                    }
+                });
             });
         });
 
+        #[cfg(target_arch = "wasm32")]
+        if let Some(action) = self.browser_library.show(ctx) {
+            match action {
+                LibraryAction::Open(entry) => {
+                    file_action = Some(FileAction::OpenBrowserShader(entry))
+                }
+                LibraryAction::Rename(entry, name) => self.rename_browser_shader(entry, name),
+                LibraryAction::Delete(entry) => {
+                    if let Err(e) = browser_storage::delete(&entry) {
+                        self.status = Some(StatusMessage::error(format!("Delete failed: {e}")));
+                    }
+                }
+            }
+        }
+
+        if let Some(action) = account_action {
+            self.perform_account_action(action, ctx);
+        }
+
+        match view_action {
+            Some(ViewAction::PreviewCurrent) => {
+                let info = self.current_shader_inf.clone();
+                self.open_preview(info.name.clone(), &info, ctx, _frame);
+            }
+            Some(ViewAction::Compare) => show_pane(&mut self.tree, PaneType::Compare),
+            None => {}
+        }
+
+        if let Some(action) = file_action {
+            self.request_file_action(action, ctx, _frame);
+        }
+        self.update_unsaved_changes_prompt(ctx, _frame);
+        self.update_sharing(ctx);
+        #[cfg(not(target_arch = "wasm32"))]
+        self.update_hdr_export();
+        self.update_conflict(ctx);
+        self.update_revision_history(ctx, _frame);
+        if let Some(shaders) = self.shadertoy_import.update(ctx) {
+            self.open_imported(shaders);
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             let mut recomp_on_invalid = true; //self.inf.dep_graph.recompute_on_invalidation;
             let mut behav = TreeBehavior {
@@ -159,13 +1110,26 @@ impl eframe::App for App {
                 compile_on_change: &mut self.compile_on_change,
                 recompute_on_invalidate: &mut recomp_on_invalid,
                 renderstate: _frame.wgpu_render_state().as_ref().unwrap(),
+                read_only: self.read_only,
+                gallery: &mut self.gallery,
+                gpu_pane: &mut self.gpu_pane,
+                previews: &mut self.previews,
+                compare_pane: &mut self.compare_pane,
+                render_settings: &mut self.render_settings,
+                api: &self.storage_manager.api,
 
                 shader_text_changed: false,
                 recompute_on_textchange_changed: false,
+                fork_requested: false,
+                gallery_action: None,
+                closed_previews: Vec::new(),
             };
             self.tree.ui(&mut behav, ui);
             let shader_changed = behav.shader_text_changed;
             let recomp_changed = behav.recompute_on_textchange_changed;
+            let fork_requested = behav.fork_requested;
+            let gallery_action = behav.gallery_action.take();
+            let closed_previews = std::mem::take(&mut behav.closed_previews);
             //self.inf.dep_graph.recompute_on_invalidation = recomp_on_invalid;
 
             if self.compile_on_change && (shader_changed || recomp_changed) {
@@ -175,6 +1139,18 @@ impl eframe::App for App {
             }
             //Tree::new("tree", root, tiles)
             //egui_shaderwheels_logic::draw(&mut self.inf, ui);
+
+            if fork_requested {
+                let parent = self.storage_manager.connection.remote_id();
+                self.fork_shader(self.current_shader_inf.clone(), parent);
+            }
+            if let Some(action) = gallery_action {
+                self.open_remote(ctx, action.id, action.mode);
+            }
+            let renderstate = _frame.wgpu_render_state().unwrap();
+            for id in closed_previews {
+                self.previews.close(renderstate, id);
+            }
         });
     }
 }
@@ -0,0 +1,356 @@
+use std::time::Duration;
+
+use shaderwheels_logic::web::{self, AuthError, OidcProvider, Session, SessionHandle, config};
+
+use crate::app::executor::{Task, spawn_task};
+
+/// How long to wait before trying again after a failed token refresh.
+const REFRESH_RETRY_SECS: u64 = 30;
+
+pub enum AccountAction {
+    LogIn,
+    CancelLogin,
+    LogOut,
+}
+
+pub enum AccountEvent {
+    LoggedIn(String),
+    LoginFailed(AuthError),
+    RefreshFailed(AuthError),
+}
+
+/// The signed-in user, if any. A login task finishing with `Ok(None)` means the
+/// login carries on after a page load, which is how the web build does it.
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Account {
+    // Natively the session is kept in its own file rather than with the app state.
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub session: SessionHandle,
+
+    #[serde(skip)]
+    login: Option<Task<Result<Option<Session>, AuthError>>>,
+
+    /// Dropping this stops the native login waiting for the browser.
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip)]
+    cancel_login: Option<shaderwheels_logic::web::loopback::CancelLogin>,
+
+    #[serde(skip)]
+    refresh: Option<Task<Result<(), AuthError>>>,
+
+    #[serde(skip)]
+    next_refresh_attempt: u64,
+
+    #[serde(skip)]
+    persisted_generation: u64,
+}
+
+impl Account {
+    /// Reads back the session saved by a previous run.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn restore(&mut self) {
+        self.session = SessionHandle::new(session_file::load());
+        self.persisted_generation = self.session.generation();
+    }
+
+    pub fn is_logging_in(&self) -> bool {
+        self.login.is_some()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_login(&mut self, ctx: &egui::Context) {
+        if self.login.is_none() {
+            let (cancel, cancelled) = shaderwheels_logic::web::loopback::cancel_pair();
+            self.cancel_login = Some(cancel);
+            self.login = Some(spawn_task(ctx, login(ctx.clone(), cancelled)));
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn start_login(&mut self, ctx: &egui::Context) {
+        if self.login.is_none() {
+            self.login = Some(spawn_task(ctx, login(ctx.clone())));
+        }
+    }
+
+    /// Stops waiting for the browser. The user may have just closed the tab.
+    /// Natively this also closes the loopback listener, so its port is free for
+    /// the next login.
+    pub fn cancel_login(&mut self) {
+        self.login = None;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.cancel_login = None;
+        }
+    }
+
+    /// Finishes a login if the provider just redirected back to this page.
+    #[cfg(target_arch = "wasm32")]
+    pub fn resume_login(&mut self, ctx: &egui::Context) {
+        let Some((pending, ret)) = web_redirect::take() else {
+            return;
+        };
+        self.login = Some(spawn_task(ctx, async move {
+            let provider = OidcProvider::discover(config::current().auth.clone()).await?;
+            provider.finish_login(pending, ret).await.map(Some)
+        }));
+    }
+
+    /// Forgets the session and ends it at the provider as well, since otherwise
+    /// the next login would go through without asking for a password.
+    pub fn logout(&mut self, ctx: &egui::Context) {
+        let Some(session) = self.session.get() else {
+            return;
+        };
+        self.session.set(None);
+        self.refresh = None;
+
+        let url_ctx = ctx.clone();
+        // Nothing waits for this; the browser is simply sent to the provider.
+        let _ = spawn_task(ctx, async move {
+            let provider = OidcProvider::discover(config::current().auth.clone()).await;
+            let url = provider.map(|p| p.logout_url(&session, cfg!(target_arch = "wasm32")));
+            match url {
+                Ok(Some(url)) if cfg!(target_arch = "wasm32") => {
+                    url_ctx.open_url(egui::OpenUrl::same_tab(url))
+                }
+                Ok(Some(url)) => url_ctx.open_url(egui::OpenUrl::new_tab(url)),
+                Ok(None) => log::info!("The login provider has no end-session endpoint"),
+                Err(e) => log::warn!("Could not end the provider session: {e}"),
+            }
+        });
+    }
+
+    /// Polls the login and refresh tasks and keeps the access token fresh.
+    /// Returns what the user should hear about.
+    pub fn update(&mut self, ctx: &egui::Context) -> Option<AccountEvent> {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.persist_if_changed();
+
+        let event = self.poll_login().or_else(|| self.poll_refresh());
+        self.schedule_refresh(ctx);
+        event
+    }
+
+    fn poll_login(&mut self) -> Option<AccountEvent> {
+        let res = self.login.as_ref()?.try_take()?;
+        self.cancel_login();
+
+        match res {
+            Ok(Some(session)) => {
+                let name = session.user.display_name().to_string();
+                self.session.set(Some(session));
+                self.next_refresh_attempt = 0;
+                Some(AccountEvent::LoggedIn(name))
+            }
+            Ok(None) => None,
+            Err(e) => Some(AccountEvent::LoginFailed(e)),
+        }
+    }
+
+    fn poll_refresh(&mut self) -> Option<AccountEvent> {
+        let res = self.refresh.as_ref()?.try_take()?;
+        self.refresh = None;
+
+        match res {
+            Ok(()) => None,
+            Err(e) => {
+                self.next_refresh_attempt = web::unix_now() + REFRESH_RETRY_SECS;
+                Some(AccountEvent::RefreshFailed(e))
+            }
+        }
+    }
+
+    fn schedule_refresh(&mut self, ctx: &egui::Context) {
+        if self.refresh.is_some() {
+            return;
+        }
+        let Some(refresh_at) = self.session.refresh_at() else {
+            return;
+        };
+
+        let refresh_at = refresh_at.max(self.next_refresh_attempt);
+        let now = web::unix_now();
+        if now >= refresh_at {
+            let session = self.session.clone();
+            self.refresh = Some(spawn_task(ctx, async move { session.refresh().await }));
+        } else {
+            ctx.request_repaint_after(Duration::from_secs(refresh_at - now));
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn persist_if_changed(&mut self) {
+        let generation = self.session.generation();
+        if generation != self.persisted_generation {
+            session_file::store(self.session.get().as_ref());
+            self.persisted_generation = generation;
+        }
+    }
+
+    pub fn ui(&self, ui: &mut egui::Ui) -> Option<AccountAction> {
+        let mut action = None;
+        match self.session.get() {
+            Some(session) => {
+                ui.menu_button(session.user.display_name(), |ui| {
+                    if let Some(email) = session.user.email.as_deref() {
+                        ui.label(email);
+                    }
+                    if ui.button("Log out").clicked() {
+                        action = Some(AccountAction::LogOut);
+                        ui.close();
+                    }
+                });
+            }
+            None if self.is_logging_in() => {
+                if ui.button("Cancel").clicked() {
+                    action = Some(AccountAction::CancelLogin);
+                }
+                ui.label("Logging in...");
+                ui.spinner();
+            }
+            None => {
+                if ui.button("Log in").clicked() {
+                    action = Some(AccountAction::LogIn);
+                }
+            }
+        }
+        action
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn login(
+    ctx: egui::Context,
+    cancelled: shaderwheels_logic::web::loopback::LoginCancelled,
+) -> Result<Option<Session>, AuthError> {
+    use shaderwheels_logic::web::loopback::LoopbackListener;
+
+    let cfg = config::current().auth.clone();
+    let listener = LoopbackListener::bind(cfg.loopback_port()).await?;
+    let provider = OidcProvider::discover(cfg).await?;
+    let pending = provider.begin_login(Some(listener.redirect_uri().to_string()))?;
+
+    ctx.open_url(egui::OpenUrl::new_tab(&pending.authorize_url));
+    ctx.request_repaint();
+
+    let ret = listener.wait_for_redirect(cancelled).await?;
+    provider.finish_login(pending, ret).await.map(Some)
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn login(ctx: egui::Context) -> Result<Option<Session>, AuthError> {
+    let provider = OidcProvider::discover(config::current().auth.clone()).await?;
+    let pending = provider.begin_login(None)?;
+    web_redirect::store_pending(&pending)?;
+
+    ctx.open_url(egui::OpenUrl::same_tab(&pending.authorize_url));
+    ctx.request_repaint();
+    Ok(None)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod session_file {
+    use std::{io::Write, path::PathBuf};
+
+    use shaderwheels_logic::web::Session;
+
+    const SESSION_FILE: &str = "session.json";
+
+    fn path() -> Option<PathBuf> {
+        eframe::storage_dir(crate::app::APP_NAME).map(|dir| dir.join(SESSION_FILE))
+    }
+
+    pub fn load() -> Option<Session> {
+        let text = std::fs::read_to_string(path()?).ok()?;
+        match serde_json::from_str(&text) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                log::warn!("Ignoring unreadable saved session: {e}");
+                None
+            }
+        }
+    }
+
+    /// Writes the session where only the current user can read it, or removes
+    /// the file when logged out.
+    pub fn store(session: Option<&Session>) {
+        let Some(path) = path() else {
+            log::warn!("No config directory to keep the session in");
+            return;
+        };
+        let res = match session {
+            Some(session) => write_private(&path, &serde_json::to_vec(session).unwrap()),
+            None => match std::fs::remove_file(&path) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                res => res,
+            },
+        };
+        if let Err(e) = res {
+            log::warn!("Could not save the session to {}: {}", path.display(), e);
+        }
+    }
+
+    fn write_private(path: &std::path::Path, bytes: &[u8]) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(path)?.write_all(bytes)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web_redirect {
+    use eframe::wasm_bindgen::JsValue;
+    use shaderwheels_logic::web::{AuthError, AuthReturn, PendingLogin};
+
+    const PENDING_LOGIN_KEY: &str = "shaderwheels:pending-login";
+
+    fn session_storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.session_storage().ok().flatten()
+    }
+
+    pub fn store_pending(pending: &PendingLogin) -> Result<(), AuthError> {
+        let storage = session_storage()
+            .ok_or_else(|| AuthError::Redirect("session storage is not available".to_string()))?;
+        // Serializing plain strings cannot fail.
+        let text = serde_json::to_string(pending).unwrap();
+        storage
+            .set_item(PENDING_LOGIN_KEY, &text)
+            .map_err(|e| AuthError::Redirect(format!("{e:?}")))
+    }
+
+    /// Takes back the state stored before leaving for the provider, and strips
+    /// `code` and `state` from the address bar so a reload does not replay them.
+    pub fn take() -> Option<(PendingLogin, AuthReturn)> {
+        let window = web_sys::window()?;
+        let location = window.location();
+        let ret = AuthReturn::from_redirect_url(&location.href().ok()?).ok()?;
+        if !ret.is_auth_response() {
+            return None;
+        }
+
+        if let (Ok(history), Ok(path), Ok(hash)) =
+            (window.history(), location.pathname(), location.hash())
+        {
+            let _ =
+                history.replace_state_with_url(&JsValue::NULL, "", Some(&format!("{path}{hash}")));
+        }
+
+        let storage = session_storage()?;
+        let pending = storage.get_item(PENDING_LOGIN_KEY).ok().flatten();
+        let _ = storage.remove_item(PENDING_LOGIN_KEY);
+        match pending.and_then(|text| serde_json::from_str(&text).ok()) {
+            Some(pending) => Some((pending, ret)),
+            None => {
+                log::warn!("Got a login redirect, but no login was started from this tab");
+                None
+            }
+        }
+    }
+}
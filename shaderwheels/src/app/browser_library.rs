@@ -0,0 +1,78 @@
+use crate::app::{browser_storage, shader_content_manager::BrowserShaderEntry};
+
+pub enum LibraryAction {
+    Open(BrowserShaderEntry),
+    Rename(BrowserShaderEntry, String),
+    Delete(BrowserShaderEntry),
+}
+
+/// Lists the shaders kept in browser storage.
+#[derive(Default)]
+pub struct BrowserLibraryWindow {
+    pub open: bool,
+    renaming: Option<(BrowserShaderEntry, String)>,
+}
+
+impl BrowserLibraryWindow {
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<LibraryAction> {
+        let mut action = None;
+        let mut open = self.open;
+
+        egui::Window::new("Saved Shaders")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                let listings = match browser_storage::list() {
+                    Ok(listings) => listings,
+                    Err(e) => {
+                        ui.colored_label(ui.visuals().error_fg_color, e.to_string());
+                        return;
+                    }
+                };
+
+                if listings.is_empty() {
+                    ui.label("No shaders have been saved in this browser yet.");
+                    return;
+                }
+
+                egui::Grid::new("saved shaders grid")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for listing in listings {
+                            let mut finished_rename = false;
+                            match &mut self.renaming {
+                                Some((entry, name)) if *entry == listing.entry => {
+                                    ui.text_edit_singleline(name);
+                                    if ui.button("Done").clicked() {
+                                        action = Some(LibraryAction::Rename(
+                                            entry.clone(),
+                                            name.clone(),
+                                        ));
+                                        finished_rename = true;
+                                    }
+                                }
+                                _ => {
+                                    ui.label(&listing.name);
+                                    if ui.button("Open").clicked() {
+                                        action = Some(LibraryAction::Open(listing.entry.clone()));
+                                    }
+                                }
+                            }
+                            if finished_rename {
+                                self.renaming = None;
+                            }
+
+                            if ui.button("Rename").clicked() {
+                                self.renaming = Some((listing.entry.clone(), listing.name.clone()));
+                            }
+                            if ui.button("Delete").clicked() {
+                                action = Some(LibraryAction::Delete(listing.entry.clone()));
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+
+        self.open = open;
+        action
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt::Display;
+
+use web_sys::Storage;
+
+use crate::app::{
+    project_file::{self, ProjectFileError},
+    shader_content_manager::{BrowserShaderEntry, ShaderInfo},
+};
+
+const INDEX_KEY: &str = "shaderwheels:index";
+const SHADER_KEY_PREFIX: &str = "shaderwheels:shader:";
+/// The id the next new shader gets. Only ever goes up, so the id of a deleted
+/// shader is not handed out again.
+const NEXT_ID_KEY: &str = "shaderwheels:next-id";
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct BrowserShaderListing {
+    pub entry: BrowserShaderEntry,
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub enum BrowserStorageError {
+    Unavailable,
+    Js(String),
+    Missing,
+    Malformed(ProjectFileError),
+}
+
+impl Display for BrowserStorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BrowserStorageError::Unavailable => write!(f, "browser storage is not available"),
+            BrowserStorageError::Js(e) => write!(f, "{e}"),
+            BrowserStorageError::Missing => write!(f, "shader is no longer in browser storage"),
+            BrowserStorageError::Malformed(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+fn local_storage() -> Result<Storage, BrowserStorageError> {
+    web_sys::window()
+        .and_then(|w| w.local_storage().ok().flatten())
+        .ok_or(BrowserStorageError::Unavailable)
+}
+
+fn js_err(e: eframe::wasm_bindgen::JsValue) -> BrowserStorageError {
+    BrowserStorageError::Js(format!("{e:?}"))
+}
+
+fn shader_key(entry: &BrowserShaderEntry) -> String {
+    format!("{SHADER_KEY_PREFIX}{}", entry.id)
+}
+
+/// Ids of every shader in storage, listed in the index or not.
+fn stored_ids(storage: &Storage) -> Result<Vec<u64>, BrowserStorageError> {
+    let mut ids = Vec::new();
+    for i in 0..storage.length().map_err(js_err)? {
+        let id = storage
+            .key(i)
+            .map_err(js_err)?
+            .and_then(|key| key.strip_prefix(SHADER_KEY_PREFIX)?.parse().ok());
+        ids.extend(id);
+    }
+    Ok(ids)
+}
+
+pub fn list() -> Result<Vec<BrowserShaderListing>, BrowserStorageError> {
+    let storage = local_storage()?;
+    let Some(text) = storage.get_item(INDEX_KEY).map_err(js_err)? else {
+        return Ok(Vec::new());
+    };
+    match serde_json::from_str(&text) {
+        Ok(index) => Ok(index),
+        Err(e) => {
+            log::warn!("Rebuilding the unreadable browser shader index: {e}");
+            rebuild_index(&storage)
+        }
+    }
+}
+
+/// Lists the stored shaders themselves, so that a corrupt index neither hides
+/// them nor gets written back over them.
+fn rebuild_index(storage: &Storage) -> Result<Vec<BrowserShaderListing>, BrowserStorageError> {
+    let mut index: Vec<_> = stored_ids(storage)?
+        .into_iter()
+        .map(|id| {
+            let entry = BrowserShaderEntry { id };
+            let name = load(&entry)
+                .map(|info| info.name)
+                .unwrap_or_else(|_| format!("Shader {id}"));
+            BrowserShaderListing { entry, name }
+        })
+        .collect();
+    index.sort_by_key(|listing| listing.entry.id);
+    write_index(storage, &index)?;
+    Ok(index)
+}
+
+fn write_index(
+    storage: &Storage,
+    index: &[BrowserShaderListing],
+) -> Result<(), BrowserStorageError> {
+    // Serializing plain strings and ids cannot fail.
+    let text = serde_json::to_string(index).unwrap();
+    storage.set_item(INDEX_KEY, &text).map_err(js_err)
+}
+
+/// Reserves an id that no shader, stored or deleted, has had yet.
+pub fn new_entry() -> Result<BrowserShaderEntry, BrowserStorageError> {
+    let storage = local_storage()?;
+    let counter = storage
+        .get_item(NEXT_ID_KEY)
+        .map_err(js_err)?
+        .and_then(|text| text.parse().ok())
+        .unwrap_or(0);
+    // Shaders saved before there was a counter, or with a counter that got lost.
+    let next_id = stored_ids(&storage)?
+        .into_iter()
+        .map(|id| id + 1)
+        .fold(counter, Ord::max);
+    storage
+        .set_item(NEXT_ID_KEY, &(next_id + 1).to_string())
+        .map_err(js_err)?;
+    Ok(BrowserShaderEntry { id: next_id })
+}
+
+pub fn load(entry: &BrowserShaderEntry) -> Result<ShaderInfo, BrowserStorageError> {
+    let text = local_storage()?
+        .get_item(&shader_key(entry))
+        .map_err(js_err)?
+        .ok_or(BrowserStorageError::Missing)?;
+    project_file::deserialize_project(&text, &format!("Shader {}", entry.id))
+        .map_err(BrowserStorageError::Malformed)
+}
+
+pub fn save(entry: &BrowserShaderEntry, info: &ShaderInfo) -> Result<(), BrowserStorageError> {
+    let storage = local_storage()?;
+    storage
+        .set_item(&shader_key(entry), &project_file::serialize_project(info))
+        .map_err(js_err)?;
+
+    let mut index = list()?;
+    match index.iter_mut().find(|listing| listing.entry == *entry) {
+        Some(listing) => listing.name = info.name.clone(),
+        None => index.push(BrowserShaderListing {
+            entry: entry.clone(),
+            name: info.name.clone(),
+        }),
+    }
+    write_index(&storage, &index)
+}
+
+pub fn rename(entry: &BrowserShaderEntry, new_name: &str) -> Result<(), BrowserStorageError> {
+    let mut info = load(entry)?;
+    info.name = new_name.to_string();
+    save(entry, &info)
+}
+
+pub fn delete(entry: &BrowserShaderEntry) -> Result<(), BrowserStorageError> {
+    let storage = local_storage()?;
+    storage.remove_item(&shader_key(entry)).map_err(js_err)?;
+
+    let mut index = list()?;
+    index.retain(|listing| listing.entry != *entry);
+    write_index(&storage, &index)
+}
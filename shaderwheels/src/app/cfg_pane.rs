@@ -1,9 +1,22 @@
+use std::time::Duration;
+
 use egui::Ui;
+use shaderwheels_logic::rendering::{
+    communication::{PreoutFormat, ToneMapper},
+    graphics_backend_client::GraphicsClient,
+};
+
+use crate::app::render_settings::RenderSettings;
+
+/// What the time budget starts at when turned on.
+const DEFAULT_TIME_BUDGET: Duration = Duration::from_millis(33);
 
 pub fn add_transient_cfg_pane(
     compile_on_change: &mut bool,
     recompute_on_invalidate: &mut bool,
     recompile_on_textchange_changed: &mut bool,
+    client: &mut GraphicsClient,
+    render_settings: &mut RenderSettings,
     ui: &mut Ui,
 ) {
     *recompile_on_textchange_changed = ui
@@ -11,4 +24,74 @@ pub fn add_transient_cfg_pane(
         .changed();
 
     ui.checkbox(recompute_on_invalidate, "Recompute on recompile");
+
+    let mut animating = client.is_animating();
+    if ui.checkbox(&mut animating, "Animate").changed() {
+        client.set_animating(animating);
+    }
+
+    let mut tiling = client.tiling();
+    ui.horizontal(|ui| {
+        ui.label("Tiles per side");
+        ui.add(egui::DragValue::new(&mut tiling.tiles_per_side).range(1..=16))
+            .on_hover_text(
+                "Draws heavy shaders a piece at a time, so the GPU is never busy for long.",
+            );
+    });
+    ui.horizontal(|ui| {
+        let mut limited = tiling.time_budget.is_some();
+        ui.checkbox(&mut limited, "Frame time budget")
+            .on_hover_text("Lowers the resolution while frames take longer than this.");
+        let mut budget_ms = tiling
+            .time_budget
+            .unwrap_or(DEFAULT_TIME_BUDGET)
+            .as_millis() as u64;
+        ui.add_enabled(
+            limited,
+            egui::DragValue::new(&mut budget_ms)
+                .range(1..=1000)
+                .suffix(" ms"),
+        );
+        tiling.time_budget = limited.then(|| Duration::from_millis(budget_ms));
+    });
+    client.set_tiling(tiling);
+
+    ui.collapsing("Resolution", |ui| {
+        render_settings.ui(ui);
+        if let Some((width, height)) = client.get_preout_size() {
+            ui.label(format!("Rendering at {width}x{height}"));
+        }
+    });
+
+    ui.collapsing("Output", |ui| {
+        let mut format = client.preout_format();
+        egui::ComboBox::from_label("Format")
+            .selected_text(format.name())
+            .show_ui(ui, |ui| {
+                for option in PreoutFormat::ALL {
+                    ui.selectable_value(&mut format, option, option.name());
+                }
+            })
+            .response
+            .on_hover_text(
+                "Float formats keep colors above 1 for tonemapping. Compute shaders \
+                 have to declare their storage texture in the same format.",
+            );
+        client.set_preout_format(format);
+
+        let mut tonemapping = client.tonemapping();
+        egui::ComboBox::from_label("Tonemapping")
+            .selected_text(tonemapping.mapper.name())
+            .show_ui(ui, |ui| {
+                for mapper in ToneMapper::ALL {
+                    ui.selectable_value(&mut tonemapping.mapper, mapper, mapper.name());
+                }
+            });
+        ui.add(
+            egui::Slider::new(&mut tonemapping.exposure, -8.0..=8.0)
+                .text("Exposure")
+                .suffix(" EV"),
+        );
+        client.set_tonemapping(tonemapping);
+    });
 }
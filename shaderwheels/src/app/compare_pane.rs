@@ -0,0 +1,376 @@
+use eframe::egui_wgpu::RenderState;
+use egui::{Color32, CursorIcon, Rect, Sense, Stroke, TextureId, Ui, pos2};
+use shaderwheels_logic::rendering::{DEFAULT_WGSL_VERT, WGSL_VERT_ENTRY};
+use wgpu::{
+    BindGroupLayout, Buffer, Device, RenderPipeline, ShaderModuleDescriptor, TextureFormat,
+    TextureView,
+};
+
+use crate::app::{
+    egui_shaderwheels_logic::{self, RenderCtx, TextureInfo},
+    previews::{PreviewId, Previews},
+    render_settings::RenderSettings,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CompareSource {
+    /// The shader in the editor.
+    Live,
+    Preview(PreviewId),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CompareMode {
+    Wipe,
+    Difference,
+}
+
+/// Shows two outputs on top of each other, split by a draggable divider, or
+/// the difference between them.
+pub struct ComparePane {
+    a: CompareSource,
+    b: Option<CompareSource>,
+    mode: CompareMode,
+    /// Where the divider sits, from 0 at the left edge to 1 at the right.
+    wipe: f32,
+    gain: f32,
+    difference: Option<DifferenceView>,
+}
+
+impl Default for ComparePane {
+    fn default() -> Self {
+        Self {
+            a: CompareSource::Live,
+            b: None,
+            mode: CompareMode::Wipe,
+            wipe: 0.5,
+            gain: 4.0,
+            difference: None,
+        }
+    }
+}
+
+impl ComparePane {
+    pub fn ui(
+        &mut self,
+        ui: &mut Ui,
+        live: &mut RenderCtx,
+        previews: &mut Previews,
+        renderstate: &RenderState,
+        settings: &RenderSettings,
+    ) {
+        // Previews closed since the last frame can't be compared anymore.
+        let exists = |source: CompareSource| match source {
+            CompareSource::Live => true,
+            CompareSource::Preview(id) => previews.get(id).is_some(),
+        };
+        if !exists(self.a) {
+            self.a = CompareSource::Live;
+        }
+        if !self.b.is_some_and(exists) {
+            self.b = previews
+                .iter()
+                .next()
+                .map(|(id, _)| CompareSource::Preview(id));
+        }
+
+        ui.horizontal(|ui| {
+            source_combo(ui, "A", &mut self.a, previews);
+            if let Some(b) = &mut self.b {
+                source_combo(ui, "B", b, previews);
+            }
+            ui.separator();
+            ui.selectable_value(&mut self.mode, CompareMode::Wipe, "Wipe");
+            ui.selectable_value(&mut self.mode, CompareMode::Difference, "Difference");
+            if self.mode == CompareMode::Difference {
+                ui.add(egui::Slider::new(&mut self.gain, 1.0..=64.0).text("Gain"));
+            }
+        });
+
+        let Some(b) = self.b else {
+            ui.label("Open a preview from the View menu to compare against.");
+            return;
+        };
+
+        // Both sides are stretched over the same rect, at whatever size their
+        // own panes render them.
+        let pane = ui.available_rect_before_wrap();
+        let max_size = renderstate.device.limits().max_texture_dimension_2d;
+        let (rect, size) = settings.layout(pane, ui.ctx().pixels_per_point(), max_size);
+        if rect.width() < 1.0 || rect.height() < 1.0 {
+            return;
+        }
+        if rect != pane {
+            ui.painter().rect_filled(pane, 0.0, Color32::BLACK);
+        }
+        let tex_a = current_frame(self.a, live, previews, renderstate, ui.ctx());
+        let tex_b = current_frame(b, live, previews, renderstate, ui.ctx());
+
+        match self.mode {
+            CompareMode::Wipe => {
+                let response = ui
+                    .allocate_rect(rect, Sense::drag())
+                    .on_hover_cursor(CursorIcon::ResizeHorizontal);
+                if let Some(pos) = response.interact_pointer_pos() {
+                    self.wipe = ((pos.x - rect.left()) / rect.width()).clamp(0.0, 1.0);
+                }
+
+                let split = rect.left() + rect.width() * self.wipe;
+                let painter = ui.painter_at(rect);
+                if let Some(tex) = tex_a {
+                    painter.image(
+                        tex,
+                        Rect::from_min_max(rect.min, pos2(split, rect.bottom())),
+                        Rect::from_min_max(pos2(0.0, 0.0), pos2(self.wipe, 1.0)),
+                        Color32::WHITE,
+                    );
+                }
+                if let Some(tex) = tex_b {
+                    painter.image(
+                        tex,
+                        Rect::from_min_max(pos2(split, rect.top()), rect.max),
+                        Rect::from_min_max(pos2(self.wipe, 0.0), pos2(1.0, 1.0)),
+                        Color32::WHITE,
+                    );
+                }
+                painter.vline(split, rect.y_range(), Stroke::new(2.0, Color32::WHITE));
+            }
+            CompareMode::Difference => {
+                let views = (
+                    source_ctx(self.a, live, previews).and_then(|rctx| rctx.presented_view()),
+                    source_ctx(b, live, previews).and_then(|rctx| rctx.presented_view()),
+                );
+                let (Some(view_a), Some(view_b)) = views else {
+                    ui.label("The difference needs both sides rendering on the window's device.");
+                    return;
+                };
+                let difference = self
+                    .difference
+                    .get_or_insert_with(|| DifferenceView::new(&renderstate.device));
+                let tex = difference.render(renderstate, view_a, view_b, size, self.gain);
+                let uv = Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0));
+                ui.painter().image(tex, rect, uv, Color32::WHITE);
+            }
+        }
+    }
+}
+
+fn source_combo(ui: &mut Ui, label: &str, source: &mut CompareSource, previews: &Previews) {
+    let title = |source: CompareSource| match source {
+        CompareSource::Live => "Editor".to_string(),
+        CompareSource::Preview(id) => previews
+            .get(id)
+            .map(|preview| preview.title.clone())
+            .unwrap_or_default(),
+    };
+    egui::ComboBox::from_label(label)
+        .selected_text(title(*source))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(source, CompareSource::Live, title(CompareSource::Live));
+            for (id, _) in previews.iter() {
+                let option = CompareSource::Preview(id);
+                ui.selectable_value(source, option, title(option));
+            }
+        });
+}
+
+fn source_ctx<'a>(
+    source: CompareSource,
+    live: &'a RenderCtx,
+    previews: &'a Previews,
+) -> Option<&'a RenderCtx> {
+    match source {
+        CompareSource::Live => Some(live),
+        CompareSource::Preview(id) => previews.get(id).map(|preview| &preview.rctx),
+    }
+}
+
+/// The source's latest frame. Its size is left to the pane that shows the
+/// source itself, which would otherwise be set back and forth every frame.
+fn current_frame(
+    source: CompareSource,
+    live: &mut RenderCtx,
+    previews: &mut Previews,
+    renderstate: &RenderState,
+    ctx: &egui::Context,
+) -> Option<TextureId> {
+    let rctx = match source {
+        CompareSource::Live => live,
+        CompareSource::Preview(id) => &mut previews.get_mut(id)?.rctx,
+    };
+    let size = rctx.client.get_preout_size()?;
+    egui_shaderwheels_logic::present_finished_frame(renderstate, rctx, size, ctx);
+    rctx.presented_texture()
+}
+
+/// Renders the difference of two frames into a texture egui can draw.
+struct DifferenceView {
+    pipeline: RenderPipeline,
+    layout: BindGroupLayout,
+    params: Buffer,
+    output: Option<TextureInfo>,
+}
+
+impl DifferenceView {
+    fn new(device: &Device) -> Self {
+        let vert = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Difference Vertex Shader"),
+            source: wgpu::ShaderSource::Wgsl(DEFAULT_WGSL_VERT.into()),
+        });
+        let frag = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Difference Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("difference.wgsl").into()),
+        });
+
+        let frame_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Difference Bind Group Layout"),
+            entries: &[
+                frame_entry(0),
+                frame_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Difference Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Difference Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vert,
+                entry_point: Some(WGSL_VERT_ENTRY),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &frag,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: TextureFormat::Rgba8Unorm,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Difference Params"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            layout,
+            params,
+            output: None,
+        }
+    }
+
+    fn render(
+        &mut self,
+        renderstate: &RenderState,
+        a: &TextureView,
+        b: &TextureView,
+        size: (u32, u32),
+        gain: f32,
+    ) -> TextureId {
+        if self
+            .output
+            .as_ref()
+            .is_none_or(|output| output.size != size)
+        {
+            if let Some(old) = self.output.take() {
+                egui_shaderwheels_logic::free_texture_info(renderstate, &old);
+            }
+            self.output = Some(egui_shaderwheels_logic::create_texture_info(
+                renderstate,
+                None,
+                size,
+            ));
+        }
+        let output = self.output.as_ref().unwrap();
+
+        let mut params = [0u8; 16];
+        for (bytes, value) in params
+            .chunks_exact_mut(4)
+            .zip([size.0 as f32, size.1 as f32, gain])
+        {
+            bytes.copy_from_slice(&value.to_le_bytes());
+        }
+        renderstate.queue.write_buffer(&self.params, 0, &params);
+
+        let device = &renderstate.device;
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Difference Bind Group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(a),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(b),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.params.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Difference Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Difference Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &output.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        renderstate.queue.submit([encoder.finish()]);
+
+        output.id.unwrap()
+    }
+}
@@ -0,0 +1,24 @@
+// Shows where two frames differ, brightened by `gain` so small changes stand out.
+
+struct Params {
+    output_size: vec2<f32>,
+    gain: f32,
+}
+
+@group(0) @binding(0) var frame_a: texture_2d<f32>;
+@group(0) @binding(1) var frame_b: texture_2d<f32>;
+@group(0) @binding(2) var<uniform> params: Params;
+
+// Both frames are stretched over the output, as the wipe view draws them.
+fn texel_at(frame: texture_2d<f32>, uv: vec2<f32>) -> vec4<f32> {
+    let size = textureDimensions(frame);
+    let texel = min(vec2<u32>(uv * vec2<f32>(size)), size - 1u);
+    return textureLoad(frame, texel, 0);
+}
+
+@fragment
+fn fs_main(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let uv = pos.xy / params.output_size;
+    let diff = abs(texel_at(frame_a, uv) - texel_at(frame_b, uv));
+    return vec4<f32>(min(diff.rgb * params.gain, vec3<f32>(1.0)), 1.0);
+}
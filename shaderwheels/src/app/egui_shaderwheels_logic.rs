@@ -1,23 +1,69 @@
-use eframe::egui_wgpu::RenderState;
+use std::sync::Arc;
+
+use eframe::egui_wgpu::{RenderState, WgpuConfiguration, WgpuSetup, WgpuSetupCreateNew};
 use egui::{Color32, Rect, TextureId, Ui, pos2};
 use shaderwheels_logic::rendering::{
     graphics_backend_client::GraphicsClient,
-    shader_config::{GPUAdapterInfo, ShaderConfig},
+    shader_config::{DeviceEvents, GPUAdapterInfo, ShaderConfig},
 };
-use wgpu::{Device, Extent3d, TextureDescriptor, TextureFormat, TextureView};
+use wgpu::{Device, Extent3d, Queue, Texture, TextureDescriptor, TextureFormat, TextureView};
 
-use crate::app::egui_shaderwheels_logic;
+use crate::app::{
+    egui_shaderwheels_logic, pipeline_cache::DiskPipelineCache, render_settings::RenderSettings,
+};
 
 pub struct TextureInfo {
+    pub texture: Texture,
     pub view: TextureView,
-    pub id: TextureId,
+    /// `None` for textures on the worker's own device, which egui cannot draw.
+    pub id: Option<TextureId>,
     pub size: (u32, u32),
 }
 
+/// A device the worker has to itself, on an adapter picked in the GPU pane.
+/// Its frames reach the window as a copy through the CPU.
+pub struct OwnDevice {
+    pub adapter: wgpu::Adapter,
+    pub device: Device,
+    pub queue: Queue,
+    pipeline_cache: Option<DiskPipelineCache>,
+    device_events: DeviceEvents,
+    copy: Option<egui::TextureHandle>,
+}
+
+impl OwnDevice {
+    pub async fn open(adapter: wgpu::Adapter) -> Result<Self, wgpu::RequestDeviceError> {
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("Worker Device"),
+                required_features: adapter.features() & WORKER_FEATURES,
+                required_limits: adapter.limits(),
+                ..Default::default()
+            })
+            .await?;
+        let pipeline_cache = DiskPipelineCache::open(&adapter, &device);
+        let device_events = DeviceEvents::watch(&device);
+        Ok(Self {
+            adapter,
+            device,
+            queue,
+            pipeline_cache,
+            device_events,
+            copy: None,
+        })
+    }
+}
+
 pub struct RenderCtx {
     pub client: GraphicsClient,
     pub present_buffer: Option<TextureInfo>,
     pub backend_buffer: Option<TextureInfo>,
+    /// `None` while the worker shares egui's device.
+    pub own_device: Option<OwnDevice>,
+    /// The cache for pipelines built on egui's device.
+    pipeline_cache: Option<DiskPipelineCache>,
+    /// Errors and loss of egui's device, shared with the previews on it.
+    device_events: DeviceEvents,
 }
 
 impl Default for RenderCtx {
@@ -26,19 +72,137 @@ impl Default for RenderCtx {
             client: GraphicsClient::new(ShaderConfig::default()),
             present_buffer: Default::default(),
             backend_buffer: Default::default(),
+            own_device: None,
+            pipeline_cache: None,
+            device_events: DeviceEvents::default(),
         }
     }
 }
 
+impl RenderCtx {
+    /// The device and queue the worker renders with.
+    pub fn hardware(&self, renderstate: &RenderState) -> GPUAdapterInfo {
+        match &self.own_device {
+            Some(own) => GPUAdapterInfo {
+                deviceref: own.device.clone(),
+                queueref: own.queue.clone(),
+                pipeline_cache: own.pipeline_cache.as_ref().map(|c| c.cache.clone()),
+                device_events: own.device_events.clone(),
+            },
+            None => GPUAdapterInfo {
+                deviceref: renderstate.device.clone(),
+                queueref: renderstate.queue.clone(),
+                pipeline_cache: self.pipeline_cache.as_ref().map(|c| c.cache.clone()),
+                device_events: self.device_events.clone(),
+            },
+        }
+    }
+
+    /// Writes out what the worker's pipelines have cached so far.
+    pub fn save_pipeline_caches(&self) {
+        let own_cache = self
+            .own_device
+            .as_ref()
+            .and_then(|own| own.pipeline_cache.as_ref());
+        for cache in [self.pipeline_cache.as_ref(), own_cache]
+            .into_iter()
+            .flatten()
+        {
+            cache.save();
+        }
+    }
+
+    /// A second context rendering `shader` on egui's device, sharing this
+    /// one's pipeline cache and output settings.
+    pub(crate) fn new_preview(
+        &self,
+        renderstate: &RenderState,
+        egui_ctx: &egui::Context,
+        shader: ShaderConfig,
+    ) -> RenderCtx {
+        let mut preview = new_render_ctx(
+            renderstate,
+            egui_ctx,
+            shader,
+            self.pipeline_cache.clone(),
+            self.device_events.clone(),
+        );
+        preview
+            .client
+            .set_preout_format(self.client.preout_format());
+        preview.client.set_tonemapping(self.client.tonemapping());
+        preview
+    }
+
+    /// The presented frame, as egui draws it.
+    pub(crate) fn presented_texture(&self) -> Option<TextureId> {
+        match &self.own_device {
+            Some(own) => own.copy.as_ref().map(|copy| copy.id()),
+            None => self.present_buffer.as_ref().and_then(|f| f.id),
+        }
+    }
+
+    /// The view of the presented frame, if it is on egui's device.
+    pub(crate) fn presented_view(&self) -> Option<&TextureView> {
+        if self.own_device.is_some() {
+            return None;
+        }
+        self.present_buffer.as_ref().map(|present| &present.view)
+    }
+}
+
+/// Optional features the worker uses where the adapter has them.
+const WORKER_FEATURES: wgpu::Features =
+    wgpu::Features::TIMESTAMP_QUERY.union(wgpu::Features::PIPELINE_CACHE);
+
+/// egui's device setup, plus GPU timestamps for the stats pane and pipeline
+/// caching where the adapter has them.
+pub fn wgpu_options() -> WgpuConfiguration {
+    let setup = WgpuSetupCreateNew::default();
+    let default_descriptor = setup.device_descriptor.clone();
+    WgpuConfiguration {
+        wgpu_setup: WgpuSetup::CreateNew(WgpuSetupCreateNew {
+            device_descriptor: Arc::new(move |adapter| wgpu::DeviceDescriptor {
+                required_features: adapter.features() & WORKER_FEATURES,
+                ..default_descriptor(adapter)
+            }),
+            ..setup
+        }),
+        ..Default::default()
+    }
+}
+
 pub(crate) fn onetime_hardware_setup(cc: &eframe::CreationContext<'_>) -> RenderCtx {
     let renderstate = cc.wgpu_render_state.as_ref().unwrap();
     let draw_size = (512u32, 512u32);
 
-    let mut client = GraphicsClient::new(ShaderConfig::default());
+    let pipeline_cache = DiskPipelineCache::open(&renderstate.adapter, &renderstate.device);
+    new_render_ctx(
+        renderstate,
+        &cc.egui_ctx,
+        ShaderConfig::default(),
+        pipeline_cache,
+        DeviceEvents::watch(&renderstate.device),
+    )
+}
+
+fn new_render_ctx(
+    renderstate: &RenderState,
+    egui_ctx: &egui::Context,
+    shader: ShaderConfig,
+    pipeline_cache: Option<DiskPipelineCache>,
+    device_events: DeviceEvents,
+) -> RenderCtx {
+    let mut client = GraphicsClient::new(shader);
     client.set_hardware(GPUAdapterInfo {
         deviceref: renderstate.device.clone(),
         queueref: renderstate.queue.clone(),
+        pipeline_cache: pipeline_cache.as_ref().map(|c| c.cache.clone()),
+        device_events: device_events.clone(),
     });
+    // The worker sleeps between changes, so new frames have to ask for a repaint.
+    let ctx = egui_ctx.clone();
+    client.set_render_notifier(move || ctx.request_repaint());
 
     let targ_size = (512, 512);
 
@@ -60,17 +224,81 @@ pub(crate) fn onetime_hardware_setup(cc: &eframe::CreationContext<'_>) -> Render
         client,
         present_buffer: None,
         backend_buffer: None,
+        own_device: None,
+        pipeline_cache,
+        device_events,
     };
 
-    rctx.present_buffer = Some(create_texture_info(renderstate, targ_size));
-    rctx.backend_buffer = Some(create_texture_info(renderstate, targ_size));
+    rctx.present_buffer = Some(create_texture_info(renderstate, None, targ_size));
+    rctx.backend_buffer = Some(create_texture_info(renderstate, None, targ_size));
     rctx.client
         .set_output_view(rctx.backend_buffer.as_ref().unwrap().view.clone());
     rctx
 }
 
+/// Moves the worker onto `own_device`, or back onto egui's device for `None`.
+pub(crate) fn switch_device(
+    renderstate: &RenderState,
+    rctx: &mut RenderCtx,
+    own_device: Option<OwnDevice>,
+) {
+    rctx.save_pipeline_caches();
+    rctx.own_device = own_device;
+    rebuild_output(renderstate, rctx);
+}
+
+/// Gets the worker going again after its device was lost. A lost device never
+/// comes back, so a new one is opened on the same adapter. When the lost one
+/// was egui's, the worker keeps the new device to itself and its frames reach
+/// the window through the CPU, as with an adapter picked in the GPU pane.
+pub(crate) fn recreate_device(renderstate: &RenderState, rctx: &mut RenderCtx) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let adapter = match rctx.own_device.take() {
+            Some(own) => own.adapter,
+            None => renderstate.adapter.clone(),
+        };
+        let name = adapter.get_info().name;
+        match pollster::block_on(OwnDevice::open(adapter)) {
+            Ok(opened) => rctx.own_device = Some(opened),
+            Err(e) => {
+                log::warn!("Could not open a new device on {name} ({e}), using egui's device")
+            }
+        }
+    }
+    // The browser can't copy frames between devices, so there egui's device
+    // is handed over again.
+    rebuild_output(renderstate, rctx);
+}
+
+/// Replaces both output textures and hands the worker its device anew, so it
+/// rebuilds everything.
+fn rebuild_output(renderstate: &RenderState, rctx: &mut RenderCtx) {
+    let size = rctx.client.get_preout_size().unwrap_or((512u32, 512u32));
+    release_output(renderstate, rctx);
+    let own_device = rctx.own_device.as_ref();
+    rctx.present_buffer = Some(create_texture_info(renderstate, own_device, size));
+    rctx.backend_buffer = Some(create_texture_info(renderstate, own_device, size));
+
+    let hardware = rctx.hardware(renderstate);
+    rctx.client.recreate_device(hardware);
+    rctx.client
+        .set_output_view(rctx.backend_buffer.as_ref().unwrap().view.clone());
+}
+
+/// Frees both output textures, which egui would otherwise hold on to.
+pub(crate) fn release_output(renderstate: &RenderState, rctx: &mut RenderCtx) {
+    for info in [rctx.present_buffer.take(), rctx.backend_buffer.take()]
+        .into_iter()
+        .flatten()
+    {
+        free_texture_info(renderstate, &info);
+    }
+}
+
 fn fix_texture_info(
     egui_renderstate: &RenderState,
+    own_device: Option<&OwnDevice>,
     correct_size: (u32, u32),
     to_fix: &mut Option<TextureInfo>,
 ) {
@@ -79,55 +307,121 @@ fn fix_texture_info(
             return;
         }
 
-        egui_renderstate.renderer.write().free_texture(&val.id);
+        free_texture_info(egui_renderstate, val);
     }
 
-    *to_fix = Some(create_texture_info(egui_renderstate, correct_size));
+    *to_fix = Some(create_texture_info(
+        egui_renderstate,
+        own_device,
+        correct_size,
+    ));
 }
 
 pub(crate) fn prep_to_render(
     egui_renderstate: &RenderState,
     rctx: &mut RenderCtx,
     correct_size: (u32, u32),
+    ctx: &egui::Context,
 ) -> Option<TextureId> {
+    present_finished_frame(egui_renderstate, rctx, correct_size, ctx);
+    if rctx.client.get_preout_size() != Some(correct_size) {
+        rctx.client.set_preout_size(correct_size);
+    }
+    rctx.presented_texture()
+}
+
+/// Presents the frame the worker just finished, if there is one, and gives it
+/// a buffer of `next_size` for the next.
+pub(crate) fn present_finished_frame(
+    egui_renderstate: &RenderState,
+    rctx: &mut RenderCtx,
+    next_size: (u32, u32),
+    ctx: &egui::Context,
+) {
     if rctx.client.get_should_swap() {
         println!("Render succeeded, swap!");
         swap_backend_buffer_and_update_backbuffer_and_inform_client(
             egui_renderstate,
             rctx,
-            correct_size,
+            next_size,
+            ctx,
         );
     }
-    if rctx.client.get_preout_size() != Some(correct_size) {
-        rctx.client.set_preout_size(correct_size);
-    }
-
-    rctx.present_buffer.as_ref().map(|f| f.id)
 }
 
 fn swap_backend_buffer_and_update_backbuffer_and_inform_client(
     egui_renderstate: &RenderState,
     rctx: &mut RenderCtx,
     correct_size: (u32, u32),
+    ctx: &egui::Context,
 ) {
     std::mem::swap(&mut rctx.backend_buffer, &mut rctx.present_buffer);
-    fix_texture_info(egui_renderstate, correct_size, &mut rctx.backend_buffer);
+    #[cfg(not(target_arch = "wasm32"))]
+    copy_own_frame(rctx, ctx);
+    #[cfg(target_arch = "wasm32")]
+    let _ = ctx;
+    fix_texture_info(
+        egui_renderstate,
+        rctx.own_device.as_ref(),
+        correct_size,
+        &mut rctx.backend_buffer,
+    );
     rctx.client
         .set_output_view(rctx.backend_buffer.as_ref().unwrap().view.clone());
 }
 
-fn create_texture_info(egui_renderstate: &RenderState, size: (u32, u32)) -> TextureInfo {
-    let view = create_texture(&egui_renderstate.device, size);
-    let id = egui_renderstate.renderer.write().register_native_texture(
-        &egui_renderstate.device,
-        &view,
-        eframe::wgpu::FilterMode::Linear,
+/// Brings the frame just presented on the worker's own device over to egui.
+#[cfg(not(target_arch = "wasm32"))]
+fn copy_own_frame(rctx: &mut RenderCtx, ctx: &egui::Context) {
+    let (Some(own), Some(present)) = (&mut rctx.own_device, &rctx.present_buffer) else {
+        return;
+    };
+    let Some(frame) = read_texture(&own.device, &own.queue, present) else {
+        return;
+    };
+    let image = egui::ColorImage::from_rgba_unmultiplied(
+        [frame.size.0 as usize, frame.size.1 as usize],
+        &frame.rgba,
     );
-    TextureInfo { view, id, size }
+    match &mut own.copy {
+        Some(copy) => copy.set(image, egui::TextureOptions::LINEAR),
+        None => {
+            own.copy = Some(ctx.load_texture("Worker Frame", image, egui::TextureOptions::LINEAR))
+        }
+    }
+}
+
+pub(crate) fn create_texture_info(
+    egui_renderstate: &RenderState,
+    own_device: Option<&OwnDevice>,
+    size: (u32, u32),
+) -> TextureInfo {
+    let device = own_device.map_or(&egui_renderstate.device, |own| &own.device);
+    let texture = create_texture(device, size);
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let id = own_device.is_none().then(|| {
+        egui_renderstate.renderer.write().register_native_texture(
+            &egui_renderstate.device,
+            &view,
+            eframe::wgpu::FilterMode::Linear,
+        )
+    });
+    TextureInfo {
+        texture,
+        view,
+        id,
+        size,
+    }
+}
+
+pub(crate) fn free_texture_info(egui_renderstate: &RenderState, info: &TextureInfo) {
+    if let Some(id) = &info.id {
+        egui_renderstate.renderer.write().free_texture(id);
+    }
 }
 
-fn create_texture(dev: &Device, size: (u32, u32)) -> TextureView {
-    let texture = dev.create_texture(&TextureDescriptor {
+fn create_texture(dev: &Device, size: (u32, u32)) -> Texture {
+    dev.create_texture(&TextureDescriptor {
         label: Some("OUTPUT TEXTURE"),
         size: Extent3d {
             width: size.0,
@@ -140,17 +434,98 @@ fn create_texture(dev: &Device, size: (u32, u32)) -> TextureView {
         format: TextureFormat::Rgba8Unorm,
         usage: wgpu::TextureUsages::COPY_DST
             | wgpu::TextureUsages::TEXTURE_BINDING
-            | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            | wgpu::TextureUsages::RENDER_ATTACHMENT
+            | wgpu::TextureUsages::COPY_SRC,
         view_formats: &[],
+    })
+}
+
+pub struct CapturedFrame {
+    pub size: (u32, u32),
+    pub rgba: Vec<u8>,
+}
+
+// Blocks until the copy lands, so this is only usable where the device can be polled.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn capture_present_buffer(
+    egui_renderstate: &RenderState,
+    rctx: &RenderCtx,
+) -> Option<CapturedFrame> {
+    let present = rctx.present_buffer.as_ref()?;
+    let hardware = rctx.hardware(egui_renderstate);
+    read_texture(&hardware.deviceref, &hardware.queueref, present)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_texture(device: &Device, queue: &Queue, texture: &TextureInfo) -> Option<CapturedFrame> {
+    let (width, height) = texture.size;
+    let unpadded_row = width * 4;
+    let padded_row = unpadded_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+        * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: padded_row as u64 * height as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
     });
 
-    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-    view
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &readback,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: None,
+            },
+        },
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit([encoder.finish()]);
+
+    let slice = readback.slice(..);
+    let (send, recv) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |res| {
+        let _ = send.send(res);
+    });
+    device.poll(wgpu::PollType::Wait).ok()?;
+    recv.recv().ok()?.ok()?;
+
+    let mut rgba = Vec::with_capacity((unpadded_row * height) as usize);
+    {
+        let mapped = slice.get_mapped_range();
+        for row in mapped.chunks(padded_row as usize) {
+            rgba.extend_from_slice(&row[..unpadded_row as usize]);
+        }
+    }
+    readback.unmap();
+
+    Some(CapturedFrame {
+        size: (width, height),
+        rgba,
+    })
 }
 
-pub(crate) fn draw(rctx: &mut RenderCtx, renderstate: &RenderState, ui: &mut Ui) {
-    let rect = ui.available_rect_before_wrap();
-    let cur_size = (rect.width() as u32, rect.height() as u32);
+pub(crate) fn draw(
+    rctx: &mut RenderCtx,
+    renderstate: &RenderState,
+    settings: &RenderSettings,
+    ui: &mut Ui,
+) {
+    let pane = ui.available_rect_before_wrap();
+    let max_size = renderstate.device.limits().max_texture_dimension_2d;
+    let (rect, cur_size) = settings.layout(pane, ui.ctx().pixels_per_point(), max_size);
+    if rect != pane {
+        ui.painter().rect_filled(pane, 0.0, Color32::BLACK);
+    }
 
     /*
     let retexture = rctx
@@ -163,7 +538,7 @@ pub(crate) fn draw(rctx: &mut RenderCtx, renderstate: &RenderState, ui: &mut Ui)
         egui_shaderwheels_logic::replace_base_texture(&renderstate, rctx, cur_size);
     }*/
 
-    let id_to_render = prep_to_render(renderstate, rctx, cur_size);
+    let id_to_render = prep_to_render(renderstate, rctx, cur_size, ui.ctx());
     //println!("Testing for render");
 
     if let Some(tex_id) = id_to_render {
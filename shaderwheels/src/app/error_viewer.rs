@@ -1,25 +1,58 @@
+use eframe::egui_wgpu::RenderState;
 use egui::{Color32, RichText, Ui};
+use shaderwheels_logic::rendering::graphics_backend_client::CompileState;
 
-use crate::app::egui_shaderwheels_logic::RenderCtx;
+use crate::app::{
+    egui_shaderwheels_logic::{self, RenderCtx},
+    previews::Previews,
+};
 
-pub fn add_error_viewer(rctx: &RenderCtx, ui: &mut Ui) {
-    let rt = RichText::new("No support for now");
-    /*if let Some(err) = rctx.dep_graph.get_compilation_error() {
-    let err_text = match err {
-    wgpu::Error::OutOfMemory { source: _ } => "",
-    wgpu::Error::Validation {
-    source: _,
-    description,
-    } => &description,
-    wgpu::Error::Internal {
-    source: _,
-    description,
-    } => &description,
+pub fn add_error_viewer(
+    rctx: &mut RenderCtx,
+    previews: &mut Previews,
+    renderstate: &RenderState,
+    ui: &mut Ui,
+) {
+    if let Some(reason) = rctx.client.device_lost() {
+        ui.label(
+            RichText::new(format!("The GPU device was lost: {reason}"))
+                .color(Color32::RED)
+                .size(14f32),
+        );
+        if ui.button("Recreate Device").clicked() {
+            egui_shaderwheels_logic::recreate_device(renderstate, rctx);
+            // Previews on the same device were lost with it.
+            previews.recreate_lost_devices(renderstate);
+        }
+        return;
+    }
+
+    let client = &rctx.client;
+    let rt = match client.compile_state() {
+        CompileState::Idle => RichText::new("Nothing compiled yet."),
+        CompileState::Compiling(_) => RichText::new("Compiling..."),
+        CompileState::Finished {
+            error: Some(err), ..
+        } => RichText::new(err).color(Color32::RED),
+        CompileState::Finished { shader_seq, .. } if *shader_seq != client.shader_seq() => {
+            RichText::new("Waiting for the latest changes...")
+        }
+        CompileState::Finished { .. } => RichText::new("Latest compilation successful."),
     };
-    RichText::new(err_text).color(Color32::RED)
-    } else {
-    RichText::new("Latest compilation successful.")
-    };*/
 
     ui.label(rt.size(14f32));
+
+    let errors = rctx.client.errors().len();
+    if errors > 0 {
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label(format!("GPU errors: {errors}"));
+            if ui.button("Clear").clicked() {
+                rctx.client.take_errors();
+            }
+        });
+        for error in rctx.client.errors().iter().rev() {
+            ui.label(RichText::new(error).color(Color32::YELLOW));
+        }
+    }
 }
@@ -0,0 +1,63 @@
+use std::{
+    future::Future,
+    sync::mpsc::{self, Receiver},
+};
+
+/// The receiving end of a future started with [`spawn_task`].
+pub struct Task<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> Task<T> {
+    /// Returns the result once the future has finished. If the future was dropped
+    /// before finishing, this never yields anything.
+    pub fn try_take(&self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub trait MaybeSend: Send {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Send> MaybeSend for T {}
+
+// Futures on the web are driven by the browser's event loop on the only thread there is.
+#[cfg(target_arch = "wasm32")]
+pub trait MaybeSend {}
+#[cfg(target_arch = "wasm32")]
+impl<T> MaybeSend for T {}
+
+// reqwest needs to run inside a tokio runtime natively, so every task shares one.
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn(fut: impl Future<Output = ()> + MaybeSend + 'static) {
+    static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+    let runtime = RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("Could not start the background runtime")
+    });
+    runtime.spawn(fut);
+}
+
+#[cfg(target_arch = "wasm32")]
+fn spawn(fut: impl Future<Output = ()> + MaybeSend + 'static) {
+    wasm_bindgen_futures::spawn_local(fut);
+}
+
+/// Runs `fut` in the background (a tokio runtime natively, the browser's microtask
+/// queue on the web) and asks egui to repaint once it has finished.
+pub fn spawn_task<T: MaybeSend + 'static>(
+    ctx: &egui::Context,
+    fut: impl Future<Output = T> + MaybeSend + 'static,
+) -> Task<T> {
+    let (writer, receiver) = mpsc::channel();
+    let ctx = ctx.clone();
+
+    spawn(async move {
+        let _ = writer.send(fut.await);
+        ctx.request_repaint();
+    });
+
+    Task { receiver }
+}
@@ -0,0 +1,253 @@
+use std::path::PathBuf;
+
+use egui::{KeyboardShortcut, Modifiers, Ui};
+use shaderwheels_logic::rendering::{self, shader_config::ShaderLanguage};
+
+use crate::app::{
+    gallery::RemoteOpenMode,
+    png_export::ExportMetadata,
+    shader_content_manager::{BrowserShaderEntry, ShaderDBEntry, ShaderInfo},
+    shadertoy_import::ImportedShader,
+};
+
+const MAX_RECENT_FILES: usize = 10;
+
+const SAVE_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::CTRL, egui::Key::S);
+const SAVE_AS_SHORTCUT: KeyboardShortcut =
+    KeyboardShortcut::new(Modifiers::CTRL.plus(Modifiers::SHIFT), egui::Key::S);
+const OPEN_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::CTRL, egui::Key::O);
+const NEW_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::CTRL, egui::Key::N);
+
+pub struct ShaderTemplate {
+    pub name: &'static str,
+    pub language: ShaderLanguage,
+    pub source: &'static str,
+}
+
+pub const TEMPLATES: &[ShaderTemplate] = &[
+    ShaderTemplate {
+        name: "Fragment (WGSL)",
+        language: ShaderLanguage::Wgsl,
+        source: rendering::DEFAULT_WGSL_FRAG,
+    },
+    ShaderTemplate {
+        name: "Fragment (GLSL)",
+        language: ShaderLanguage::Glsl,
+        source: rendering::DEFAULT_GLSL_FRAG,
+    },
+];
+
+impl ShaderTemplate {
+    pub fn to_info(&self) -> ShaderInfo {
+        ShaderInfo {
+            contents: self.source.to_string(),
+            language: self.language,
+            ..ShaderInfo::default()
+        }
+    }
+}
+
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+pub struct RecentFiles {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentFiles {
+    pub fn push(&mut self, path: PathBuf) {
+        self.paths.retain(|p| *p != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(MAX_RECENT_FILES);
+    }
+
+    pub fn remove(&mut self, path: &PathBuf) {
+        self.paths.retain(|p| p != path);
+    }
+}
+
+#[derive(Clone)]
+pub enum FileAction {
+    New(&'static ShaderTemplate),
+    Open,
+    OpenRecent(PathBuf),
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    OpenBrowserShader(BrowserShaderEntry),
+    OpenShared(ShaderInfo),
+    OpenRemote(ShaderDBEntry, ShaderInfo, RemoteOpenMode),
+    OpenImported(Vec<ImportedShader>),
+    OpenPngExport(ExportMetadata),
+    ImportShadertoy,
+    ShowSavedShaders,
+    ShowRevisionHistory,
+    Save,
+    SaveAs,
+    CopyShareLink,
+    ExportPng,
+    ExportHdr,
+    Quit,
+}
+
+impl FileAction {
+    /// Actions that throw away the shader currently being edited.
+    pub fn discards_current(&self) -> bool {
+        matches!(
+            self,
+            FileAction::New(_)
+                | FileAction::Open
+                | FileAction::OpenRecent(_)
+                | FileAction::OpenBrowserShader(_)
+                | FileAction::OpenShared(_)
+                | FileAction::OpenRemote(..)
+                | FileAction::OpenImported(_)
+                | FileAction::OpenPngExport(_)
+                | FileAction::ImportShadertoy
+                | FileAction::Quit
+        )
+    }
+}
+
+pub fn consume_file_shortcuts(ctx: &egui::Context) -> Option<FileAction> {
+    ctx.input_mut(|i| {
+        // Shift+S has to be checked first, since Ctrl+S also matches it.
+        if i.consume_shortcut(&SAVE_AS_SHORTCUT) {
+            Some(FileAction::SaveAs)
+        } else if i.consume_shortcut(&SAVE_SHORTCUT) {
+            Some(FileAction::Save)
+        } else if i.consume_shortcut(&OPEN_SHORTCUT) {
+            if cfg!(target_arch = "wasm32") {
+                Some(FileAction::ShowSavedShaders)
+            } else {
+                Some(FileAction::Open)
+            }
+        } else if i.consume_shortcut(&NEW_SHORTCUT) {
+            Some(FileAction::New(&TEMPLATES[0]))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+pub fn add_file_menu(ui: &mut Ui, recent: &RecentFiles) -> Option<FileAction> {
+    let mut action = None;
+    let ctx = ui.ctx().clone();
+
+    let mut item =
+        |ui: &mut Ui, label: &str, shortcut: Option<&KeyboardShortcut>, act: FileAction| {
+            let mut button = egui::Button::new(label);
+            if let Some(shortcut) = shortcut {
+                button = button.shortcut_text(ctx.format_shortcut(shortcut));
+            }
+            if ui.add(button).clicked() {
+                action = Some(act);
+                ui.close();
+            }
+        };
+
+    item(
+        ui,
+        "New",
+        Some(&NEW_SHORTCUT),
+        FileAction::New(&TEMPLATES[0]),
+    );
+    ui.menu_button("New from Template", |ui| {
+        for template in TEMPLATES {
+            item(ui, template.name, None, FileAction::New(template));
+        }
+    });
+    #[cfg(target_arch = "wasm32")]
+    item(ui, "Saved Shaders...", None, FileAction::ShowSavedShaders);
+    #[cfg(not(target_arch = "wasm32"))]
+    item(ui, "Open...", Some(&OPEN_SHORTCUT), FileAction::Open);
+    #[cfg(not(target_arch = "wasm32"))]
+    ui.menu_button("Open Recent", |ui| {
+        if recent.paths.is_empty() {
+            ui.label("No recent files");
+        }
+        for path in &recent.paths {
+            item(
+                ui,
+                &path.display().to_string(),
+                None,
+                FileAction::OpenRecent(path.clone()),
+            );
+        }
+    });
+    item(
+        ui,
+        "Import Shadertoy JSON...",
+        None,
+        FileAction::ImportShadertoy,
+    );
+    ui.separator();
+    item(ui, "Save", Some(&SAVE_SHORTCUT), FileAction::Save);
+    item(
+        ui,
+        "Save As...",
+        Some(&SAVE_AS_SHORTCUT),
+        FileAction::SaveAs,
+    );
+    item(
+        ui,
+        "Revision History...",
+        None,
+        FileAction::ShowRevisionHistory,
+    );
+    ui.separator();
+    item(ui, "Copy Share Link", None, FileAction::CopyShareLink);
+    // NOTE: no File->Quit on web pages!
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        ui.separator();
+        item(ui, "Export PNG...", None, FileAction::ExportPng);
+        item(ui, "Export HDR...", None, FileAction::ExportHdr);
+        ui.separator();
+        item(ui, "Quit", None, FileAction::Quit);
+    }
+
+    action
+}
+
+pub enum UnsavedChangesChoice {
+    Save,
+    Discard,
+    Cancel,
+}
+
+pub fn unsaved_changes_prompt(
+    ctx: &egui::Context,
+    shader_name: &str,
+    currently_saving: bool,
+) -> Option<UnsavedChangesChoice> {
+    let mut choice = None;
+    let modal = egui::Modal::new(egui::Id::new("unsaved changes prompt")).show(ctx, |ui| {
+        ui.heading("Unsaved changes");
+        ui.label(format!(
+            "\"{shader_name}\" has changes that have not been saved."
+        ));
+
+        if currently_saving {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Saving...");
+            });
+            return;
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked() {
+                choice = Some(UnsavedChangesChoice::Save);
+            }
+            if ui.button("Discard").clicked() {
+                choice = Some(UnsavedChangesChoice::Discard);
+            }
+            if ui.button("Cancel").clicked() {
+                choice = Some(UnsavedChangesChoice::Cancel);
+            }
+        });
+    });
+
+    if modal.should_close() && choice.is_none() && !currently_saving {
+        return Some(UnsavedChangesChoice::Cancel);
+    }
+    choice
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+
+use egui::Ui;
+use shaderwheels_logic::web::shader_api::{
+    ListQuery, ShaderApiClient, ShaderApiError, ShaderId, ShaderSummary, SortOrder,
+};
+
+use crate::app::executor::{Task, spawn_task};
+
+const PAGE_SIZE: u32 = 24;
+const THUMBNAIL_SIZE: f32 = 128.0;
+
+/// How a shader fetched from the database gets opened.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RemoteOpenMode {
+    /// Viewing only; it has to be forked or saved elsewhere to keep changes.
+    ReadOnly,
+    /// A new database entry derived from the original, once saved.
+    Fork,
+    /// An unsaved copy, as when following a share link.
+    Copy,
+}
+
+pub struct GalleryAction {
+    pub id: ShaderId,
+    pub mode: RemoteOpenMode,
+}
+
+enum Thumbnail {
+    Loading(Task<Result<Option<Vec<u8>>, String>>),
+    Ready(egui::TextureHandle),
+    Missing,
+}
+
+/// Pages through the public shaders in the shader database.
+#[derive(Default)]
+pub struct Gallery {
+    search: String,
+    tag: String,
+    sort: SortOrder,
+    page: u32,
+
+    results: Vec<ShaderSummary>,
+    loaded_once: bool,
+    listing: Option<Task<Result<Vec<ShaderSummary>, ShaderApiError>>>,
+    error: Option<String>,
+
+    thumbnails: HashMap<ShaderId, Thumbnail>,
+}
+
+impl Gallery {
+    fn refresh(&mut self, ctx: &egui::Context, api: &ShaderApiClient) {
+        let query = ListQuery {
+            page: Some(self.page),
+            per_page: Some(PAGE_SIZE),
+            search: Some(self.search.trim().to_string()).filter(|s| !s.is_empty()),
+            tag: Some(self.tag.trim().to_string()).filter(|s| !s.is_empty()),
+            sort: Some(self.sort),
+            public: true,
+        };
+        let api = api.clone();
+        self.listing = Some(spawn_task(ctx, async move { api.list(&query).await }));
+        self.loaded_once = true;
+    }
+
+    fn poll(&mut self) {
+        let Some(res) = self.listing.as_ref().and_then(|t| t.try_take()) else {
+            return;
+        };
+        self.listing = None;
+        match res {
+            Ok(results) => {
+                self.results = results;
+                self.error = None;
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui, api: &ShaderApiClient) -> Option<GalleryAction> {
+        if api.base_url().is_none() {
+            ui.label("No shader database is configured.");
+            return None;
+        }
+
+        let ctx = ui.ctx().clone();
+        self.poll();
+        if !self.loaded_once {
+            self.refresh(&ctx, api);
+        }
+
+        let mut search_changed = false;
+        ui.horizontal(|ui| {
+            let search = ui.add(egui::TextEdit::singleline(&mut self.search).hint_text("Search"));
+            let tag = ui.add(
+                egui::TextEdit::singleline(&mut self.tag)
+                    .hint_text("Tag")
+                    .desired_width(80.0),
+            );
+            let entered = (search.lost_focus() || tag.lost_focus())
+                && ui.input(|i| i.key_pressed(egui::Key::Enter));
+
+            let mut sort_changed = false;
+            egui::ComboBox::from_id_salt("gallery sort")
+                .selected_text(self.sort.name())
+                .show_ui(ui, |ui| {
+                    for sort in SortOrder::ALL {
+                        sort_changed |= ui
+                            .selectable_value(&mut self.sort, sort, sort.name())
+                            .changed();
+                    }
+                });
+
+            search_changed = entered || sort_changed || ui.button("Search").clicked();
+        });
+        if search_changed {
+            self.page = 0;
+            self.refresh(&ctx, api);
+        }
+
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+
+        let mut action = None;
+        let mut page_changed = false;
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(self.page > 0, egui::Button::new("Previous"))
+                .clicked()
+            {
+                self.page -= 1;
+                page_changed = true;
+            }
+            ui.label(format!("Page {}", self.page + 1));
+            // A full page suggests there is more after it.
+            let has_more = self.results.len() as u32 == PAGE_SIZE;
+            if ui
+                .add_enabled(has_more, egui::Button::new("Next"))
+                .clicked()
+            {
+                self.page += 1;
+                page_changed = true;
+            }
+            if self.listing.is_some() {
+                ui.spinner();
+            }
+        });
+        if page_changed {
+            self.refresh(&ctx, api);
+        }
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            if self.results.is_empty() && self.listing.is_none() {
+                ui.label("No shaders found.");
+            }
+            ui.horizontal_wrapped(|ui| {
+                let results = std::mem::take(&mut self.results);
+                for summary in &results {
+                    if let Some(a) = self.card_ui(ui, api, summary) {
+                        action = Some(a);
+                    }
+                }
+                self.results = results;
+            });
+        });
+
+        action
+    }
+
+    fn card_ui(
+        &mut self,
+        ui: &mut Ui,
+        api: &ShaderApiClient,
+        summary: &ShaderSummary,
+    ) -> Option<GalleryAction> {
+        let mut action = None;
+        ui.group(|ui| {
+            ui.set_width(THUMBNAIL_SIZE);
+            ui.vertical(|ui| {
+                let size = egui::vec2(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+                match self.thumbnail(ui.ctx(), api, summary) {
+                    Some(texture) => {
+                        ui.add(egui::Image::new((texture.id(), size)));
+                    }
+                    None => {
+                        ui.allocate_space(size);
+                    }
+                }
+
+                ui.strong(&summary.name);
+                if let Some(owner) = &summary.owner {
+                    ui.weak(format!("by {owner}"));
+                }
+                if !summary.tags.is_empty() {
+                    ui.small(summary.tags.join(", "));
+                }
+                ui.horizontal(|ui| {
+                    let mut open = |mode| {
+                        action = Some(GalleryAction {
+                            id: summary.id,
+                            mode,
+                        })
+                    };
+                    if ui.button("View").clicked() {
+                        open(RemoteOpenMode::ReadOnly);
+                    }
+                    if ui.button("Fork").clicked() {
+                        open(RemoteOpenMode::Fork);
+                    }
+                });
+            });
+        });
+        action
+    }
+
+    fn thumbnail(
+        &mut self,
+        ctx: &egui::Context,
+        api: &ShaderApiClient,
+        summary: &ShaderSummary,
+    ) -> Option<&egui::TextureHandle> {
+        let entry = self.thumbnails.entry(summary.id).or_insert_with(|| {
+            let api = api.clone();
+            let summary = summary.clone();
+            Thumbnail::Loading(spawn_task(ctx, async move {
+                thumbnail_cache::fetch(&api, &summary).await
+            }))
+        });
+
+        if let Thumbnail::Loading(task) = entry {
+            match task.try_take() {
+                Some(Ok(Some(bytes))) => {
+                    *entry = match decode_thumbnail(ctx, summary.id, &bytes) {
+                        Some(texture) => Thumbnail::Ready(texture),
+                        None => Thumbnail::Missing,
+                    }
+                }
+                Some(Ok(None)) => *entry = Thumbnail::Missing,
+                Some(Err(e)) => {
+                    log::warn!(
+                        "Could not load the thumbnail of shader #{}: {e}",
+                        summary.id
+                    );
+                    *entry = Thumbnail::Missing;
+                }
+                None => {}
+            }
+        }
+
+        match entry {
+            Thumbnail::Ready(texture) => Some(texture),
+            _ => None,
+        }
+    }
+}
+
+fn decode_thumbnail(
+    ctx: &egui::Context,
+    id: ShaderId,
+    bytes: &[u8],
+) -> Option<egui::TextureHandle> {
+    let image = match image::load_from_memory(bytes) {
+        Ok(image) => image.to_rgba8(),
+        Err(e) => {
+            log::warn!("Thumbnail of shader #{id} is not a readable image: {e}");
+            return None;
+        }
+    };
+    let size = [image.width() as usize, image.height() as usize];
+    let color_image = egui::ColorImage::from_rgba_unmultiplied(size, image.as_raw());
+    Some(ctx.load_texture(
+        format!("thumbnail {id}"),
+        color_image,
+        egui::TextureOptions::LINEAR,
+    ))
+}
+
+/// Natively thumbnails are kept on disk, named after the shader and the time it
+/// was last changed, so an edited shader gets a fresh one.
+#[cfg(not(target_arch = "wasm32"))]
+mod thumbnail_cache {
+    use std::path::PathBuf;
+
+    use shaderwheels_logic::web::shader_api::{ShaderApiClient, ShaderSummary};
+
+    fn cache_path(summary: &ShaderSummary) -> Option<PathBuf> {
+        let dir = eframe::storage_dir(crate::app::APP_NAME)?.join("thumbnails");
+        Some(dir.join(format!(
+            "{}-{}.img",
+            summary.id,
+            summary.updated_at.unwrap_or(0)
+        )))
+    }
+
+    pub async fn fetch(
+        api: &ShaderApiClient,
+        summary: &ShaderSummary,
+    ) -> Result<Option<Vec<u8>>, String> {
+        let path = cache_path(summary);
+        if let Some(bytes) = path.as_ref().and_then(|p| std::fs::read(p).ok()) {
+            return Ok(Some(bytes));
+        }
+
+        let bytes = api.thumbnail(summary).await.map_err(|e| e.to_string())?;
+        if let (Some(path), Some(bytes)) = (&path, &bytes) {
+            let written = path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|()| std::fs::write(path, bytes));
+            if let Err(e) = written {
+                log::warn!("Could not cache thumbnail at {}: {}", path.display(), e);
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+/// On the web the decoded textures held by the gallery are the cache.
+#[cfg(target_arch = "wasm32")]
+mod thumbnail_cache {
+    use shaderwheels_logic::web::shader_api::{ShaderApiClient, ShaderSummary};
+
+    pub async fn fetch(
+        api: &ShaderApiClient,
+        summary: &ShaderSummary,
+    ) -> Result<Option<Vec<u8>>, String> {
+        api.thumbnail(summary).await.map_err(|e| e.to_string())
+    }
+}
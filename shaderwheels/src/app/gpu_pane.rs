@@ -0,0 +1,154 @@
+use eframe::egui_wgpu::RenderState;
+use egui::{Color32, RichText, Ui};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::app::executor::spawn_task;
+use crate::app::{
+    egui_shaderwheels_logic::{self, OwnDevice, RenderCtx},
+    executor::Task,
+};
+
+/// Lists the adapters wgpu finds, and lets the worker render on any of them.
+#[derive(Default)]
+pub struct GpuPane {
+    /// Found on first use, on every backend wgpu was built with.
+    #[cfg(not(target_arch = "wasm32"))]
+    adapters: Option<Vec<wgpu::Adapter>>,
+    opening: Option<Task<Result<OwnDevice, String>>>,
+    error: Option<String>,
+}
+
+impl GpuPane {
+    pub fn ui(&mut self, ui: &mut Ui, rctx: &mut RenderCtx, renderstate: &RenderState) {
+        if let Some(opened) = self.opening.as_ref().and_then(|t| t.try_take()) {
+            self.opening = None;
+            match opened {
+                Ok(own) => {
+                    self.error = None;
+                    egui_shaderwheels_logic::switch_device(renderstate, rctx, Some(own));
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+
+        let current = match &rctx.own_device {
+            Some(own) => own.adapter.get_info(),
+            None => renderstate.adapter.get_info(),
+        };
+        ui.label(format!(
+            "The shader renders on {} ({})",
+            current.name, current.backend
+        ));
+        if rctx.own_device.is_some() {
+            ui.label("Frames are copied to the window through the CPU.");
+            if ui.button("Use the Window's Device").clicked() {
+                egui_shaderwheels_logic::switch_device(renderstate, rctx, None);
+            }
+        }
+        if self.opening.is_some() {
+            ui.spinner();
+        }
+        if let Some(e) = &self.error {
+            ui.label(RichText::new(e).color(Color32::RED));
+        }
+        ui.separator();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            ui.collapsing("The window's adapter", |ui| {
+                adapter_details(ui, &renderstate.adapter);
+            });
+            self.adapter_list(ui);
+        });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn adapter_list(&mut self, ui: &mut Ui) {
+        let adapters = self.adapters.get_or_insert_with(|| {
+            let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::from_env_or_default());
+            instance.enumerate_adapters(wgpu::Backends::all())
+        });
+
+        let mut picked = None;
+        for (i, adapter) in adapters.iter().enumerate() {
+            let info = adapter.get_info();
+            let software = info.device_type == wgpu::DeviceType::Cpu;
+            let title = format!(
+                "{} ({}){}",
+                info.name,
+                info.backend,
+                if software { ", software" } else { "" }
+            );
+            egui::CollapsingHeader::new(title)
+                .id_salt(("adapter", i))
+                .show(ui, |ui| {
+                    if ui
+                        .add_enabled(self.opening.is_none(), egui::Button::new("Render on This"))
+                        .clicked()
+                    {
+                        picked = Some(adapter.clone());
+                    }
+                    adapter_details(ui, adapter);
+                });
+        }
+        if adapters.is_empty() {
+            ui.label("wgpu found no adapters.");
+        }
+
+        if let Some(adapter) = picked {
+            self.opening = Some(spawn_task(ui.ctx(), async move {
+                let name = adapter.get_info().name;
+                OwnDevice::open(adapter)
+                    .await
+                    .map_err(|e| format!("Could not open {name}: {e}"))
+            }));
+        }
+    }
+
+    // Copying frames between devices blocks on the GPU, which the browser does not allow.
+    #[cfg(target_arch = "wasm32")]
+    fn adapter_list(&mut self, ui: &mut Ui) {
+        ui.label("Picking another adapter needs the desktop app.");
+    }
+}
+
+fn adapter_details(ui: &mut Ui, adapter: &wgpu::Adapter) {
+    let info = adapter.get_info();
+    let limits = adapter.limits();
+    egui::Grid::new(("adapter details", info.name.as_str(), info.backend))
+        .striped(true)
+        .show(ui, |ui| {
+            let mut row = |name: &str, value: String| {
+                ui.label(name);
+                ui.label(value);
+                ui.end_row();
+            };
+            row("Backend", info.backend.to_string());
+            row("Type", format!("{:?}", info.device_type));
+            row("Driver", format!("{} {}", info.driver, info.driver_info));
+            row(
+                "Max texture size",
+                limits.max_texture_dimension_2d.to_string(),
+            );
+            row("Max buffer size", limits.max_buffer_size.to_string());
+            row("Max bind groups", limits.max_bind_groups.to_string());
+            row(
+                "Max workgroup size",
+                format!(
+                    "{}x{}x{}",
+                    limits.max_compute_workgroup_size_x,
+                    limits.max_compute_workgroup_size_y,
+                    limits.max_compute_workgroup_size_z
+                ),
+            );
+            row(
+                "Max invocations per workgroup",
+                limits.max_compute_invocations_per_workgroup.to_string(),
+            );
+        });
+
+    ui.collapsing("Features", |ui| {
+        for (name, _) in adapter.features().iter_names() {
+            ui.label(name);
+        }
+    });
+}
@@ -0,0 +1,22 @@
+use std::path::Path;
+
+use image::{DynamicImage, ImageError, ImageFormat, Rgba32FImage};
+use shaderwheels_logic::rendering::headless::HdrFrame;
+
+/// Writes `frame` as OpenEXR, or as Radiance HDR for a `.hdr` path. Radiance
+/// files have no alpha channel, so it is dropped.
+pub fn write_hdr(frame: HdrFrame, path: &Path) -> Result<(), ImageError> {
+    let (width, height) = frame.size;
+    let image = Rgba32FImage::from_raw(width, height, frame.rgba)
+        .expect("the frame holds four floats per pixel");
+    let is_radiance = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("hdr"));
+    if is_radiance {
+        DynamicImage::ImageRgba32F(image)
+            .to_rgb32f()
+            .save_with_format(path, ImageFormat::Hdr)
+    } else {
+        image.save_with_format(path, ImageFormat::OpenExr)
+    }
+}
@@ -0,0 +1,270 @@
+use egui::{Color32, RichText};
+use shaderwheels_logic::web::shader_api::RevisionId;
+
+use crate::app::shader_content_manager::ShaderInfo;
+
+/// Above this many line pairs the diff gives up and treats both sides as one change.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum DiffLine<'a> {
+    Same(&'a str),
+    Mine(&'a str),
+    Theirs(&'a str),
+}
+
+/// Line diff of two texts, based on their longest common subsequence.
+pub fn line_diff<'a>(mine: &'a str, theirs: &'a str) -> Vec<DiffLine<'a>> {
+    let a: Vec<&str> = mine.lines().collect();
+    let b: Vec<&str> = theirs.lines().collect();
+
+    if a.len().saturating_mul(b.len()) > MAX_DIFF_CELLS {
+        return a
+            .iter()
+            .map(|l| DiffLine::Mine(l))
+            .chain(b.iter().map(|l| DiffLine::Theirs(l)))
+            .collect();
+    }
+
+    // lcs[i][j] is the common subsequence length of a[i..] and b[j..].
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut out = Vec::with_capacity(a.len().max(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            out.push(DiffLine::Same(a[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+            out.push(DiffLine::Mine(a[i]));
+            i += 1;
+        } else {
+            out.push(DiffLine::Theirs(b[j]));
+            j += 1;
+        }
+    }
+    out.extend(a[i..].iter().map(|l| DiffLine::Mine(l)));
+    out.extend(b[j..].iter().map(|l| DiffLine::Theirs(l)));
+    out
+}
+
+/// Joins both versions, with git-style conflict markers around every hunk
+/// where they differ.
+pub fn merge_with_markers(diff: &[DiffLine]) -> String {
+    let mut merged = String::new();
+    let mut mine = Vec::new();
+    let mut theirs = Vec::new();
+
+    let flush = |merged: &mut String, mine: &mut Vec<&str>, theirs: &mut Vec<&str>| {
+        if mine.is_empty() && theirs.is_empty() {
+            return;
+        }
+        merged.push_str("<<<<<<< mine\n");
+        for line in mine.drain(..) {
+            merged.push_str(line);
+            merged.push('\n');
+        }
+        merged.push_str("=======\n");
+        for line in theirs.drain(..) {
+            merged.push_str(line);
+            merged.push('\n');
+        }
+        merged.push_str(">>>>>>> theirs\n");
+    };
+
+    for line in diff {
+        match line {
+            DiffLine::Same(line) => {
+                flush(&mut merged, &mut mine, &mut theirs);
+                merged.push_str(line);
+                merged.push('\n');
+            }
+            DiffLine::Mine(line) => mine.push(line),
+            DiffLine::Theirs(line) => theirs.push(line),
+        }
+    }
+    flush(&mut merged, &mut mine, &mut theirs);
+    merged
+}
+
+pub enum ConflictChoice {
+    KeepMine,
+    TakeTheirs,
+    SaveMerged(String),
+    DecideLater,
+}
+
+/// Shown when a save finds that the remote shader moved on since it was loaded.
+pub struct ConflictDialog {
+    pub theirs: ShaderInfo,
+    pub revision: Option<RevisionId>,
+    pub owner: Option<String>,
+    /// Lines prefixed with `+` (mine), `-` (theirs) or a space.
+    diff: Vec<String>,
+    merged: String,
+}
+
+impl ConflictDialog {
+    pub fn new(
+        mine: &ShaderInfo,
+        theirs: ShaderInfo,
+        revision: Option<RevisionId>,
+        owner: Option<String>,
+    ) -> Self {
+        let lines = line_diff(&mine.contents, &theirs.contents);
+        let merged = merge_with_markers(&lines);
+        let diff = lines
+            .iter()
+            .map(|line| match line {
+                DiffLine::Same(l) => format!("  {l}"),
+                DiffLine::Mine(l) => format!("+ {l}"),
+                DiffLine::Theirs(l) => format!("- {l}"),
+            })
+            .collect();
+        Self {
+            theirs,
+            revision,
+            owner,
+            diff,
+            merged,
+        }
+    }
+
+    pub fn ui(&mut self, ctx: &egui::Context) -> Option<ConflictChoice> {
+        let mut choice = None;
+        let modal = egui::Modal::new(egui::Id::new("save conflict dialog")).show(ctx, |ui| {
+            ui.set_max_width(ctx.screen_rect().width() * 0.8);
+            ui.heading("Save conflict");
+            ui.label(match &self.owner {
+                Some(owner) => format!(
+                    "\"{}\" was saved by {owner} while you were editing.",
+                    self.theirs.name
+                ),
+                None => format!(
+                    "\"{}\" was saved elsewhere while you were editing.",
+                    self.theirs.name
+                ),
+            });
+
+            ui.columns(2, |columns| {
+                columns[0].label(RichText::new("Differences").strong());
+                egui::ScrollArea::vertical()
+                    .id_salt("conflict diff")
+                    .max_height(400.0)
+                    .show(&mut columns[0], |ui| {
+                        for line in &self.diff {
+                            let mut text = RichText::new(line).monospace();
+                            if line.starts_with('+') {
+                                text = text.color(Color32::from_rgb(80, 180, 80));
+                            } else if line.starts_with('-') {
+                                text = text.color(Color32::from_rgb(210, 90, 90));
+                            }
+                            ui.label(text);
+                        }
+                    });
+                columns[0].small("+ yours   - theirs");
+
+                columns[1].label(RichText::new("Merged").strong());
+                egui::ScrollArea::vertical()
+                    .id_salt("conflict merged")
+                    .max_height(400.0)
+                    .show(&mut columns[1], |ui| {
+                        ui.add(
+                            egui::TextEdit::multiline(&mut self.merged)
+                                .code_editor()
+                                .desired_width(f32::INFINITY),
+                        );
+                    });
+            });
+
+            let has_markers = self.merged.contains("<<<<<<< mine");
+            ui.horizontal(|ui| {
+                if ui.button("Keep Mine").clicked() {
+                    choice = Some(ConflictChoice::KeepMine);
+                }
+                if ui.button("Take Theirs").clicked() {
+                    choice = Some(ConflictChoice::TakeTheirs);
+                }
+                let merged = ui
+                    .add_enabled(!has_markers, egui::Button::new("Save Merged"))
+                    .on_disabled_hover_text("Resolve the conflict markers first");
+                if merged.clicked() {
+                    choice = Some(ConflictChoice::SaveMerged(self.merged.clone()));
+                }
+                if ui.button("Decide Later").clicked() {
+                    choice = Some(ConflictChoice::DecideLater);
+                }
+            });
+        });
+
+        if modal.should_close() && choice.is_none() {
+            return Some(ConflictChoice::DecideLater);
+        }
+        choice
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DiffLine::{Mine, Same, Theirs};
+
+    #[test]
+    fn identical_texts_are_all_same() {
+        assert_eq!(line_diff("a\nb", "a\nb"), vec![Same("a"), Same("b")]);
+        assert_eq!(line_diff("", ""), vec![]);
+    }
+
+    #[test]
+    fn finds_insertions_deletions_and_changes() {
+        assert_eq!(
+            line_diff("a\nc", "a\nb\nc"),
+            vec![Same("a"), Theirs("b"), Same("c")]
+        );
+        assert_eq!(
+            line_diff("a\nb\nc", "a\nc"),
+            vec![Same("a"), Mine("b"), Same("c")]
+        );
+        assert_eq!(
+            line_diff("a\nx\nc", "a\ny\nc"),
+            vec![Same("a"), Mine("x"), Theirs("y"), Same("c")]
+        );
+        assert_eq!(line_diff("", "a"), vec![Theirs("a")]);
+    }
+
+    #[test]
+    fn huge_diffs_become_one_change() {
+        let mine = "m\n".repeat(2001);
+        let theirs = "t\n".repeat(2001);
+        let diff = line_diff(&mine, &theirs);
+        assert_eq!(diff.len(), 4002);
+        assert_eq!(diff[2000], Mine("m"));
+        assert_eq!(diff[2001], Theirs("t"));
+    }
+
+    #[test]
+    fn merging_without_changes_has_no_markers() {
+        assert_eq!(merge_with_markers(&line_diff("a\nb", "a\nb")), "a\nb\n");
+    }
+
+    #[test]
+    fn every_hunk_gets_markers() {
+        let diff = line_diff("a\nx\nc\nd", "a\ny\nc");
+        assert_eq!(
+            merge_with_markers(&diff),
+            "a\n<<<<<<< mine\nx\n=======\ny\n>>>>>>> theirs\nc\n\
+             <<<<<<< mine\nd\n=======\n>>>>>>> theirs\n"
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use std::time::Duration;
+
+use egui::Ui;
+use shaderwheels_logic::web::shader_api::{ShaderApiClient, ShaderId};
+
+use crate::app::{
+    executor::{Task, spawn_task},
+    shader_content_manager::{SaveError, ShaderDBEntry, ShaderInfo},
+};
+
+const INITIAL_BACKOFF_SECS: f64 = 2.0;
+const MAX_BACKOFF_SECS: f64 = 300.0;
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+struct PendingSave {
+    /// The revision the upload is based on, for conflict detection.
+    entry: ShaderDBEntry,
+    info: ShaderInfo,
+    /// Set when the upload failed in a way that retrying will not fix.
+    #[serde(default)]
+    error: Option<String>,
+    /// Bumped whenever newer content replaces the queued one.
+    #[serde(skip)]
+    generation: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SyncStatus {
+    Synced,
+    Pending,
+    Failed,
+}
+
+impl SyncStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SyncStatus::Synced => "synced",
+            SyncStatus::Pending => "sync pending",
+            SyncStatus::Failed => "sync failed",
+        }
+    }
+}
+
+pub enum OutboxEvent {
+    /// The entry is at the revision its upload created.
+    Synced(ShaderDBEntry),
+    Failed(ShaderId, SaveError),
+}
+
+/// The upload in flight: the shader, the generation of the save and the request.
+type Upload = (ShaderId, u64, Task<Result<ShaderDBEntry, SaveError>>);
+
+/// Saves to the shader database waiting to be uploaded. The queue is written
+/// to disk (or local storage on the web) on every change, and uploads are
+/// retried with exponential backoff while the API is unreachable.
+#[derive(Default)]
+pub struct Outbox {
+    pending: Vec<PendingSave>,
+    next_generation: u64,
+    upload: Option<Upload>,
+    backoff_secs: f64,
+    next_attempt: f64,
+}
+
+impl Outbox {
+    /// Picks up saves left over from an earlier run.
+    pub fn restore(&mut self) {
+        self.pending = store::load();
+        for save in &mut self.pending {
+            self.next_generation += 1;
+            save.generation = self.next_generation;
+        }
+    }
+
+    fn persist(&self) {
+        store::save(&self.pending);
+    }
+
+    /// Queues `info` to be uploaded over `entry`. A save already waiting for the
+    /// same shader is replaced, but keeps the revision it was based on.
+    pub fn enqueue(&mut self, entry: &ShaderDBEntry, info: ShaderInfo) {
+        self.next_generation += 1;
+        let generation = self.next_generation;
+        match self.pending.iter_mut().find(|p| p.entry.id == entry.id) {
+            Some(save) => {
+                save.info = info;
+                save.error = None;
+                save.generation = generation;
+            }
+            None => self.pending.push(PendingSave {
+                entry: entry.clone(),
+                info,
+                error: None,
+                generation,
+            }),
+        }
+        // Saving is a good moment to check whether the connection is back.
+        self.next_attempt = 0.0;
+        self.persist();
+    }
+
+    pub fn discard(&mut self, id: ShaderId) {
+        self.pending.retain(|p| p.entry.id != id);
+        self.persist();
+    }
+
+    pub fn retry_failed(&mut self) {
+        for save in &mut self.pending {
+            save.error = None;
+        }
+        self.backoff_secs = 0.0;
+        self.next_attempt = 0.0;
+        self.persist();
+    }
+
+    pub fn status_of(&self, id: ShaderId) -> SyncStatus {
+        match self.pending.iter().find(|p| p.entry.id == id) {
+            None => SyncStatus::Synced,
+            Some(save) if save.error.is_some() => SyncStatus::Failed,
+            Some(_) => SyncStatus::Pending,
+        }
+    }
+
+    fn failed_count(&self) -> usize {
+        self.pending.iter().filter(|p| p.error.is_some()).count()
+    }
+
+    /// Starts the next upload once the backoff allows it, and reports the
+    /// outcome of a finished one.
+    pub fn update(&mut self, ctx: &egui::Context, api: &ShaderApiClient) -> Option<OutboxEvent> {
+        let now = ctx.input(|i| i.time);
+
+        if let Some((id, generation, task)) = &self.upload {
+            let res = task.try_take()?;
+            let (id, generation) = (*id, *generation);
+            self.upload = None;
+            return self.finish_upload(now, id, generation, res);
+        }
+
+        let save = self.pending.iter().find(|p| p.error.is_none())?.clone();
+        if now < self.next_attempt {
+            ctx.request_repaint_after(Duration::from_secs_f64(self.next_attempt - now));
+            return None;
+        }
+        let api = api.clone();
+        self.upload = Some((
+            save.entry.id,
+            save.generation,
+            spawn_task(ctx, async move {
+                save.info.save_remote(&api, &save.entry).await
+            }),
+        ));
+        None
+    }
+
+    fn finish_upload(
+        &mut self,
+        now: f64,
+        id: ShaderId,
+        generation: u64,
+        res: Result<ShaderDBEntry, SaveError>,
+    ) -> Option<OutboxEvent> {
+        // Discarded while the upload was in flight.
+        let index = self.pending.iter().position(|p| p.entry.id == id);
+
+        let event = match res {
+            Ok(saved) => {
+                self.backoff_secs = 0.0;
+                if let Some(index) = index {
+                    if self.pending[index].generation == generation {
+                        self.pending.remove(index);
+                    } else {
+                        // Newer content was queued meanwhile; it builds on this revision.
+                        self.pending[index].entry.revision = saved.revision.clone();
+                    }
+                }
+                OutboxEvent::Synced(saved)
+            }
+            Err(SaveError::Remote(e)) if e.is_transient() => {
+                self.backoff_secs =
+                    (self.backoff_secs * 2.0).clamp(INITIAL_BACKOFF_SECS, MAX_BACKOFF_SECS);
+                self.next_attempt = now + self.backoff_secs;
+                log::info!(
+                    "Could not sync shader #{id} ({e}), retrying in {}s",
+                    self.backoff_secs
+                );
+                return None;
+            }
+            Err(e) => {
+                if let Some(index) = index {
+                    self.pending[index].error = Some(e.to_string());
+                }
+                OutboxEvent::Failed(id, e)
+            }
+        };
+        self.persist();
+        Some(event)
+    }
+
+    /// Shows how many saves are waiting, with ways to retry or drop the ones
+    /// that failed. Draws nothing when everything is synced.
+    pub fn ui(&mut self, ui: &mut Ui) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let failed = self.failed_count();
+        let label = if failed > 0 {
+            format!("{failed} not synced")
+        } else {
+            format!("{} syncing", self.pending.len())
+        };
+
+        let mut discard = None;
+        let mut retry = false;
+        ui.menu_button(label, |ui| {
+            for save in &self.pending {
+                ui.horizontal(|ui| {
+                    ui.label(&save.info.name);
+                    match &save.error {
+                        Some(e) => {
+                            ui.colored_label(ui.visuals().error_fg_color, e);
+                        }
+                        None => {
+                            ui.weak("waiting to upload");
+                        }
+                    }
+                    if ui.small_button("Discard").clicked() {
+                        discard = Some(save.entry.id);
+                    }
+                });
+            }
+            if failed > 0 {
+                ui.separator();
+                retry = ui.button("Retry Failed").clicked();
+            }
+        });
+
+        if let Some(id) = discard {
+            self.discard(id);
+        }
+        if retry {
+            self.retry_failed();
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod store {
+    use std::path::PathBuf;
+
+    use super::PendingSave;
+
+    const OUTBOX_FILE: &str = "outbox.json";
+
+    fn path() -> Option<PathBuf> {
+        eframe::storage_dir(crate::app::APP_NAME).map(|dir| dir.join(OUTBOX_FILE))
+    }
+
+    pub fn load() -> Vec<PendingSave> {
+        let Some(text) = path().and_then(|path| std::fs::read_to_string(path).ok()) else {
+            return Vec::new();
+        };
+        serde_json::from_str(&text).unwrap_or_else(|e| {
+            log::warn!("Ignoring unreadable outbox: {e}");
+            Vec::new()
+        })
+    }
+
+    pub fn save(pending: &[PendingSave]) {
+        let Some(path) = path() else {
+            log::warn!("No config directory to keep unsynced saves in");
+            return;
+        };
+        let res = std::fs::create_dir_all(path.parent().unwrap())
+            .and_then(|_| std::fs::write(&path, serde_json::to_vec(pending).unwrap()));
+        if let Err(e) = res {
+            log::warn!("Could not write the outbox to {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod store {
+    use super::PendingSave;
+
+    const OUTBOX_KEY: &str = "shaderwheels:outbox";
+
+    fn local_storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok().flatten()
+    }
+
+    pub fn load() -> Vec<PendingSave> {
+        let Some(text) = local_storage().and_then(|s| s.get_item(OUTBOX_KEY).ok().flatten()) else {
+            return Vec::new();
+        };
+        serde_json::from_str(&text).unwrap_or_else(|e| {
+            log::warn!("Ignoring unreadable outbox: {e}");
+            Vec::new()
+        })
+    }
+
+    pub fn save(pending: &[PendingSave]) {
+        let Some(storage) = local_storage() else {
+            log::warn!("No local storage to keep unsynced saves in");
+            return;
+        };
+        let text = serde_json::to_string(pending).unwrap();
+        if let Err(e) = storage.set_item(OUTBOX_KEY, &text) {
+            log::warn!("Could not write the outbox: {e:?}");
+        }
+    }
+}
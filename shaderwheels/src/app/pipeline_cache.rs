@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+use wgpu::{Adapter, Device, Features, PipelineCache, PipelineCacheDescriptor};
+
+/// A device's [`PipelineCache`], kept in a file per adapter so reopening a project
+/// does not compile every pipeline from scratch.
+#[derive(Clone)]
+pub struct DiskPipelineCache {
+    pub cache: PipelineCache,
+    path: PathBuf,
+}
+
+impl DiskPipelineCache {
+    /// `None` where the backend has no pipeline caches, which is everywhere but Vulkan.
+    pub fn open(adapter: &Adapter, device: &Device) -> Option<Self> {
+        if !device.features().contains(Features::PIPELINE_CACHE) {
+            return None;
+        }
+        let path = cache_dir()?.join(wgpu::util::pipeline_cache_key(&adapter.get_info())?);
+        let data = std::fs::read(&path).ok();
+        // SAFETY: the file only ever holds what `get_data` returned, and is named by the key
+        // wgpu uses to tell which adapters can share it.
+        let cache = unsafe {
+            device.create_pipeline_cache(&PipelineCacheDescriptor {
+                label: Some("Pipeline Cache"),
+                data: data.as_deref(),
+                fallback: true,
+            })
+        };
+        Some(Self { cache, path })
+    }
+
+    /// Writes through a temporary file, so a crash mid-write can't leave half a cache behind.
+    pub fn save(&self) {
+        let Some(data) = self.cache.get_data() else {
+            return;
+        };
+        let temp = self.path.with_extension("tmp");
+        let res = std::fs::create_dir_all(self.path.parent().unwrap())
+            .and_then(|_| std::fs::write(&temp, data))
+            .and_then(|_| std::fs::rename(&temp, &self.path));
+        if let Err(e) = res {
+            log::warn!(
+                "Could not save the pipeline cache to {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn cache_dir() -> Option<PathBuf> {
+    eframe::storage_dir(crate::app::APP_NAME).map(|dir| dir.join("pipeline_cache"))
+}
+
+// Browsers don't hand out pipeline caches.
+#[cfg(target_arch = "wasm32")]
+fn cache_dir() -> Option<PathBuf> {
+    None
+}
@@ -0,0 +1,214 @@
+use std::io::Cursor;
+
+use shaderwheels_logic::rendering::{
+    communication::{PreoutFormat, ToneMapper, Tonemapping},
+    shader_config::{ShaderBackend, ShaderLanguage},
+};
+
+use crate::app::{egui_shaderwheels_logic::CapturedFrame, shader_content_manager::ShaderInfo};
+
+// PNG keywords are limited to 79 Latin-1 characters, so the prefix stays short.
+const KEY_NAME: &str = "Title";
+const KEY_SOFTWARE: &str = "Software";
+const KEY_CREATION_TIME: &str = "Creation Time";
+const KEY_SOURCE: &str = "shaderwheels:source";
+const KEY_LANGUAGE: &str = "shaderwheels:language";
+const KEY_BACKEND: &str = "shaderwheels:backend";
+const KEY_ENTRY_POINT: &str = "shaderwheels:entry-point";
+const KEY_RESOLUTION: &str = "shaderwheels:resolution";
+const KEY_TIME_MS: &str = "shaderwheels:time-ms";
+const KEY_PREOUT_FORMAT: &str = "shaderwheels:preout-format";
+const KEY_TONE_MAPPER: &str = "shaderwheels:tone-mapper";
+const KEY_EXPOSURE: &str = "shaderwheels:exposure";
+
+#[derive(Clone)]
+pub struct ExportMetadata {
+    pub info: ShaderInfo,
+    pub resolution: (u32, u32),
+    /// Seconds since the unix epoch at which the frame was exported.
+    pub created_at: Option<u64>,
+    /// The shader time of the frame, in milliseconds.
+    pub time_ms: Option<u32>,
+    pub preout_format: Option<PreoutFormat>,
+    pub tonemapping: Option<Tonemapping>,
+}
+
+pub fn encode_png(
+    frame: &CapturedFrame,
+    meta: &ExportMetadata,
+) -> Result<Vec<u8>, png::EncodingError> {
+    let mut bytes = Vec::new();
+
+    let mut encoder = png::Encoder::new(&mut bytes, frame.size.0, frame.size.1);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder.add_text_chunk(KEY_SOFTWARE.to_string(), "ShaderWheels".to_string())?;
+    encoder.add_itxt_chunk(KEY_NAME.to_string(), meta.info.name.clone())?;
+    encoder.add_itxt_chunk(KEY_SOURCE.to_string(), meta.info.contents.clone())?;
+    encoder.add_text_chunk(
+        KEY_LANGUAGE.to_string(),
+        meta.info.language.name().to_string(),
+    )?;
+    encoder.add_text_chunk(
+        KEY_BACKEND.to_string(),
+        meta.info.backend.name().to_string(),
+    )?;
+    encoder.add_text_chunk(KEY_ENTRY_POINT.to_string(), meta.info.entry_point.clone())?;
+    encoder.add_text_chunk(
+        KEY_RESOLUTION.to_string(),
+        format!("{}x{}", meta.resolution.0, meta.resolution.1),
+    )?;
+    if let Some(created_at) = meta.created_at {
+        encoder.add_text_chunk(KEY_CREATION_TIME.to_string(), created_at.to_string())?;
+    }
+    if let Some(time_ms) = meta.time_ms {
+        encoder.add_text_chunk(KEY_TIME_MS.to_string(), time_ms.to_string())?;
+    }
+    if let Some(format) = meta.preout_format {
+        encoder.add_text_chunk(KEY_PREOUT_FORMAT.to_string(), format.name().to_string())?;
+    }
+    if let Some(tonemapping) = meta.tonemapping {
+        encoder.add_text_chunk(
+            KEY_TONE_MAPPER.to_string(),
+            tonemapping.mapper.name().to_string(),
+        )?;
+        encoder.add_text_chunk(KEY_EXPOSURE.to_string(), tonemapping.exposure.to_string())?;
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&frame.rgba)?;
+    writer.finish()?;
+
+    Ok(bytes)
+}
+
+/// Reads back the metadata written by [`encode_png`]. Returns `None` for PNGs
+/// that were not exported by ShaderWheels.
+pub fn decode_png_metadata(bytes: &[u8]) -> Option<ExportMetadata> {
+    let reader = png::Decoder::new(Cursor::new(bytes)).read_info().ok()?;
+    let info = reader.info();
+
+    let text = |key: &str| -> Option<String> {
+        let latin1 = info
+            .uncompressed_latin1_text
+            .iter()
+            .find(|chunk| chunk.keyword == key)
+            .map(|chunk| chunk.text.clone());
+        latin1.or_else(|| {
+            info.utf8_text
+                .iter()
+                .find(|chunk| chunk.keyword == key)
+                .and_then(|chunk| chunk.get_text().ok())
+        })
+    };
+
+    let contents = text(KEY_SOURCE)?;
+    let name = text(KEY_NAME).unwrap_or_else(|| ShaderInfo::default().name);
+    let language = text(KEY_LANGUAGE)
+        .and_then(|name| ShaderLanguage::from_name(&name))
+        .unwrap_or_default();
+    let backend = text(KEY_BACKEND)
+        .and_then(|name| ShaderBackend::from_name(&name))
+        .unwrap_or_default();
+    let entry_point = text(KEY_ENTRY_POINT).unwrap_or_else(|| ShaderInfo::default().entry_point);
+    let resolution = text(KEY_RESOLUTION)
+        .and_then(|res| {
+            let (w, h) = res.split_once('x')?;
+            Some((w.parse().ok()?, h.parse().ok()?))
+        })
+        .unwrap_or((info.width, info.height));
+    let created_at = text(KEY_CREATION_TIME).and_then(|t| t.parse().ok());
+    let time_ms = text(KEY_TIME_MS).and_then(|t| t.parse().ok());
+    let preout_format = text(KEY_PREOUT_FORMAT).and_then(|name| PreoutFormat::from_name(&name));
+    let tonemapping = text(KEY_TONE_MAPPER)
+        .and_then(|name| ToneMapper::from_name(&name))
+        .map(|mapper| Tonemapping {
+            mapper,
+            exposure: text(KEY_EXPOSURE)
+                .and_then(|e| e.parse().ok())
+                .unwrap_or_default(),
+        });
+
+    Some(ExportMetadata {
+        info: ShaderInfo {
+            contents,
+            name,
+            language,
+            backend,
+            entry_point,
+            newer_format: None,
+        },
+        resolution,
+        created_at,
+        time_ms,
+        preout_format,
+        tonemapping,
+    })
+}
+
+pub fn metadata_from_dropped_file(file: &egui::DroppedFile) -> Option<ExportMetadata> {
+    if let Some(bytes) = &file.bytes {
+        return decode_png_metadata(bytes);
+    }
+
+    let bytes = std::fs::read(file.path.as_ref()?).ok()?;
+    decode_png_metadata(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_survives_a_round_trip() {
+        let frame = CapturedFrame {
+            size: (2, 1),
+            rgba: vec![255, 0, 0, 255, 0, 0, 255, 255],
+        };
+        let meta = ExportMetadata {
+            info: ShaderInfo {
+                contents: "// Ünïcode stays intact\nvoid main() {}".to_string(),
+                name: "Waves".to_string(),
+                language: ShaderLanguage::Glsl,
+                backend: ShaderBackend::FullCompute,
+                entry_point: "main".to_string(),
+                newer_format: None,
+            },
+            resolution: (1920, 1080),
+            created_at: Some(1_700_000_000),
+            time_ms: Some(12_345),
+            preout_format: Some(PreoutFormat::Rgba16Float),
+            tonemapping: Some(Tonemapping {
+                mapper: ToneMapper::Aces,
+                exposure: -1.5,
+            }),
+        };
+
+        let bytes = encode_png(&frame, &meta).unwrap();
+        let read = decode_png_metadata(&bytes).unwrap();
+
+        assert_eq!(read.info.contents, meta.info.contents);
+        assert_eq!(read.info.name, "Waves");
+        assert_eq!(read.info.language, ShaderLanguage::Glsl);
+        assert_eq!(read.info.backend, ShaderBackend::FullCompute);
+        assert_eq!(read.info.entry_point, "main");
+        assert_eq!(read.resolution, (1920, 1080));
+        assert_eq!(read.created_at, Some(1_700_000_000));
+        assert_eq!(read.time_ms, Some(12_345));
+        assert_eq!(read.preout_format, Some(PreoutFormat::Rgba16Float));
+        assert_eq!(read.tonemapping, meta.tonemapping);
+    }
+
+    #[test]
+    fn plain_pngs_have_no_metadata() {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 1, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0, 0, 0, 255]).unwrap();
+        writer.finish().unwrap();
+
+        assert!(decode_png_metadata(&bytes).is_none());
+    }
+}
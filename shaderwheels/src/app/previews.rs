@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+
+use eframe::egui_wgpu::RenderState;
+use egui::Ui;
+use shaderwheels_logic::rendering::graphics_backend_client::CompileState;
+
+use crate::app::{
+    egui_shaderwheels_logic::{self, RenderCtx},
+    render_settings::RenderSettings,
+};
+
+pub type PreviewId = usize;
+
+/// A preview pane's own worker, rendering a fixed copy of a shader.
+pub struct Preview {
+    pub title: String,
+    pub rctx: RenderCtx,
+}
+
+/// The previews open next to the editor's render pane.
+#[derive(Default)]
+pub struct Previews {
+    previews: BTreeMap<PreviewId, Preview>,
+    next_id: PreviewId,
+}
+
+impl Previews {
+    pub fn open(&mut self, title: String, rctx: RenderCtx) -> PreviewId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.previews.insert(id, Preview { title, rctx });
+        id
+    }
+
+    /// Stops the preview's worker and frees its textures.
+    pub fn close(&mut self, renderstate: &RenderState, id: PreviewId) {
+        if let Some(mut preview) = self.previews.remove(&id) {
+            egui_shaderwheels_logic::release_output(renderstate, &mut preview.rctx);
+        }
+    }
+
+    pub fn get(&self, id: PreviewId) -> Option<&Preview> {
+        self.previews.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: PreviewId) -> Option<&mut Preview> {
+        self.previews.get_mut(&id)
+    }
+
+    /// Moves every preview whose device was lost onto a new one, the same way
+    /// as the editor's worker.
+    pub fn recreate_lost_devices(&mut self, renderstate: &RenderState) {
+        for preview in self.previews.values_mut() {
+            if preview.rctx.client.device_lost().is_some() {
+                egui_shaderwheels_logic::recreate_device(renderstate, &mut preview.rctx);
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (PreviewId, &Preview)> {
+        self.previews.iter().map(|(id, preview)| (*id, preview))
+    }
+}
+
+pub enum ViewAction {
+    PreviewCurrent,
+    Compare,
+}
+
+pub fn add_view_menu(ui: &mut Ui) -> Option<ViewAction> {
+    let mut action = None;
+    let mut item = |ui: &mut Ui, label: &str, act: ViewAction| {
+        if ui.button(label).clicked() {
+            action = Some(act);
+            ui.close();
+        }
+    };
+    item(ui, "Preview a Snapshot", ViewAction::PreviewCurrent);
+    item(ui, "Compare Previews", ViewAction::Compare);
+    action
+}
+
+pub fn add_preview_pane(
+    preview: &mut Preview,
+    renderstate: &RenderState,
+    settings: &RenderSettings,
+    ui: &mut Ui,
+) {
+    if let Some(reason) = preview.rctx.client.device_lost() {
+        ui.colored_label(
+            ui.visuals().error_fg_color,
+            format!("The GPU device was lost: {reason}"),
+        );
+        if ui.button("Recreate Device").clicked() {
+            egui_shaderwheels_logic::recreate_device(renderstate, &mut preview.rctx);
+        }
+        return;
+    }
+    if let CompileState::Finished {
+        error: Some(err), ..
+    } = preview.rctx.client.compile_state()
+    {
+        ui.colored_label(ui.visuals().error_fg_color, err);
+    }
+    egui_shaderwheels_logic::draw(&mut preview.rctx, renderstate, settings, ui);
+}
@@ -0,0 +1,259 @@
+use std::{fmt::Display, path::Path};
+
+use serde_json::{Map, Value, json};
+use shaderwheels_logic::rendering::shader_config::{ShaderBackend, ShaderLanguage};
+
+use crate::app::shader_content_manager::ShaderInfo;
+
+pub const PROJECT_EXTENSION: &str = "shwl";
+pub const OPENABLE_EXTENSIONS: &[&str] = &[PROJECT_EXTENSION, "wgsl", "glsl", "frag"];
+
+// Bump this and add a `migrate_vN_to_vN+1` step to `upgrade` whenever the layout changes.
+const CURRENT_VERSION: u64 = 1;
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct ProjectFile {
+    version: u64,
+    name: String,
+    language: ShaderLanguage,
+    backend: ShaderBackend,
+    entry_point: String,
+    source: String,
+    /// Only kept for files newer than [`CURRENT_VERSION`].
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// The version and the fields this version of the app does not know, of a
+/// project written by a newer one. They are written back as they were.
+#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct NewerFormat {
+    version: u64,
+    fields: Map<String, Value>,
+}
+
+#[derive(Debug)]
+pub enum ProjectFileError {
+    Io(std::io::Error),
+    NotUtf8,
+    Malformed(serde_json::Error),
+    MissingVersion,
+}
+
+impl Display for ProjectFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProjectFileError::Io(e) => write!(f, "could not read file: {e}"),
+            ProjectFileError::NotUtf8 => write!(f, "file is not valid UTF-8"),
+            ProjectFileError::Malformed(e) => write!(f, "malformed project file: {e}"),
+            ProjectFileError::MissingVersion => write!(f, "project file has no version"),
+        }
+    }
+}
+
+pub fn serialize_project(info: &ShaderInfo) -> String {
+    let (version, unknown) = match &info.newer_format {
+        Some(newer) => (newer.version, newer.fields.clone()),
+        None => (CURRENT_VERSION, Map::new()),
+    };
+    let file = ProjectFile {
+        version,
+        name: info.name.clone(),
+        language: info.language,
+        backend: info.backend,
+        entry_point: info.entry_point.clone(),
+        source: info.contents.clone(),
+        unknown,
+    };
+    // Serializing plain strings and enums cannot fail.
+    serde_json::to_string_pretty(&file).unwrap()
+}
+
+/// Parses a `.shwl` project. `fallback_name` names projects that predate the
+/// structured format, which stored only the raw shader source.
+pub fn deserialize_project(
+    text: &str,
+    fallback_name: &str,
+) -> Result<ShaderInfo, ProjectFileError> {
+    let doc = match serde_json::from_str::<Value>(text) {
+        Ok(doc @ Value::Object(_)) => doc,
+        _ => json!({ "version": 0, "source": text }),
+    };
+
+    let file: ProjectFile = serde_json::from_value(upgrade(doc, fallback_name)?)
+        .map_err(ProjectFileError::Malformed)?;
+
+    let newer_format = (file.version > CURRENT_VERSION).then_some(NewerFormat {
+        version: file.version,
+        fields: file.unknown,
+    });
+    Ok(ShaderInfo {
+        contents: file.source,
+        name: file.name,
+        language: file.language,
+        backend: file.backend,
+        entry_point: file.entry_point,
+        newer_format,
+    })
+}
+
+fn upgrade(mut doc: Value, fallback_name: &str) -> Result<Value, ProjectFileError> {
+    loop {
+        let version = doc
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or(ProjectFileError::MissingVersion)?;
+
+        if version >= CURRENT_VERSION {
+            if version > CURRENT_VERSION {
+                log::info!(
+                    "Project file version {version} is newer than {CURRENT_VERSION}, keeping its unknown fields"
+                );
+            }
+            return Ok(doc);
+        }
+
+        doc = match version {
+            0 => migrate_v0_to_v1(doc, fallback_name),
+            _ => unreachable!(),
+        };
+    }
+}
+
+fn migrate_v0_to_v1(mut doc: Value, fallback_name: &str) -> Value {
+    let defaults = ShaderInfo::default();
+    doc["version"] = json!(1);
+    doc["name"] = json!(fallback_name);
+    doc["language"] = json!(defaults.language);
+    doc["backend"] = json!(defaults.backend);
+    doc["entry_point"] = json!(defaults.entry_point);
+    doc
+}
+
+/// Loads either a `.shwl` project or a bare shader source file, picking the
+/// language from the extension.
+pub fn project_from_file(file_name: &Path, bytes: &[u8]) -> Result<ShaderInfo, ProjectFileError> {
+    let text = std::str::from_utf8(bytes).map_err(|_| ProjectFileError::NotUtf8)?;
+    let name = file_name
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| ShaderInfo::default().name);
+
+    let language = match file_name.extension().and_then(|ext| ext.to_str()) {
+        Some("wgsl") => ShaderLanguage::Wgsl,
+        Some("glsl" | "frag") => ShaderLanguage::Glsl,
+        _ => return deserialize_project(text, &name),
+    };
+
+    Ok(ShaderInfo {
+        contents: text.to_string(),
+        name,
+        language,
+        ..ShaderInfo::default()
+    })
+}
+
+/// A path in `dir` for a new project called `name` that does not replace an
+/// existing file.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn unused_project_path(dir: &Path, name: &str) -> std::path::PathBuf {
+    let stem: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+    let mut path = dir.join(format!("{stem}.{PROJECT_EXTENSION}"));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{stem} ({n}).{PROJECT_EXTENSION}"));
+        n += 1;
+    }
+    path
+}
+
+pub fn load_project_from_path(path: &Path) -> Result<ShaderInfo, ProjectFileError> {
+    let bytes = std::fs::read(path).map_err(ProjectFileError::Io)?;
+    project_from_file(path, &bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_source_projects_are_migrated() {
+        let info = deserialize_project("@fragment fn main() {}", "Old").unwrap();
+        assert_eq!(info.name, "Old");
+        assert_eq!(info.contents, "@fragment fn main() {}");
+        assert_eq!(info.language, ShaderInfo::default().language);
+        assert_eq!(info.entry_point, ShaderInfo::default().entry_point);
+        assert_eq!(info.newer_format, None);
+    }
+
+    #[test]
+    fn explicit_v0_projects_are_migrated() {
+        let info = deserialize_project(r#"{"version": 0, "source": "x"}"#, "Old").unwrap();
+        assert_eq!((info.name.as_str(), info.contents.as_str()), ("Old", "x"));
+    }
+
+    #[test]
+    fn current_projects_round_trip() {
+        let info = ShaderInfo {
+            contents: "void main() {}".to_string(),
+            name: "Waves".to_string(),
+            language: ShaderLanguage::Glsl,
+            ..ShaderInfo::default()
+        };
+        let text = serialize_project(&info);
+        assert!(deserialize_project(&text, "unused").unwrap() == info);
+        assert!(!text.contains("newer_format"));
+    }
+
+    #[test]
+    fn newer_projects_keep_their_version_and_fields() {
+        let text = r#"{
+            "version": 7,
+            "name": "Future",
+            "language": "Wgsl",
+            "backend": "FullFragment",
+            "entry_point": "main",
+            "source": "fn main() {}",
+            "uniforms": [{"name": "speed", "value": 2.5}]
+        }"#;
+        let mut info = deserialize_project(text, "unused").unwrap();
+        info.contents = "fn main() { }".to_string();
+
+        let saved: Value = serde_json::from_str(&serialize_project(&info)).unwrap();
+        assert_eq!(saved["version"], json!(7));
+        assert_eq!(saved["uniforms"], json!([{"name": "speed", "value": 2.5}]));
+        assert_eq!(saved["source"], json!("fn main() { }"));
+    }
+
+    #[test]
+    fn projects_without_a_version_are_rejected() {
+        assert!(matches!(
+            deserialize_project(r#"{"name": "x"}"#, "unused"),
+            Err(ProjectFileError::MissingVersion)
+        ));
+    }
+
+    #[test]
+    fn plain_shader_files_take_their_language_from_the_extension() {
+        let wgsl = project_from_file(Path::new("dir/waves.wgsl"), b"fn main() {}").unwrap();
+        assert_eq!(wgsl.name, "waves");
+        assert_eq!(wgsl.language, ShaderLanguage::Wgsl);
+        assert_eq!(wgsl.contents, "fn main() {}");
+
+        let glsl = project_from_file(Path::new("sky.glsl"), b"void main() {}").unwrap();
+        assert_eq!(glsl.language, ShaderLanguage::Glsl);
+        let frag = project_from_file(Path::new("sky.frag"), b"void main() {}").unwrap();
+        assert_eq!(frag.language, ShaderLanguage::Glsl);
+
+        assert!(matches!(
+            project_from_file(Path::new("bad.wgsl"), &[0xff, 0xfe]),
+            Err(ProjectFileError::NotUtf8)
+        ));
+    }
+}
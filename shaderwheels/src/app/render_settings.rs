@@ -0,0 +1,209 @@
+use egui::{Rect, Ui, vec2};
+
+/// Width and height of an aspect ratio, such as 16:9.
+type Ratio = (u32, u32);
+
+const RATIOS: [Ratio; 4] = [(16, 9), (4, 3), (1, 1), (9, 16)];
+
+/// Name, fixed size and aspect ratio.
+type Preset = (&'static str, Option<(u32, u32)>, Option<Ratio>);
+
+const PRESETS: [Preset; 4] = [
+    ("Pane", None, None),
+    ("1080p", Some((1920, 1080)), Some((16, 9))),
+    ("Square", Some((1080, 1080)), Some((1, 1))),
+    ("9:16", Some((1080, 1920)), Some((9, 16))),
+];
+
+/// How many pixels the shader renders, and where they are shown in a pane.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    /// `None` follows the pane's size in physical pixels.
+    pub fixed_size: Option<(u32, u32)>,
+    /// Letterboxes a pane-sized render, and keeps a fixed size's sides in
+    /// proportion while editing.
+    pub aspect: Option<Ratio>,
+    /// Multiplies the resolution; below 1 trades sharpness for speed.
+    pub scale: f32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            fixed_size: None,
+            aspect: None,
+            scale: 1.0,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Where to show the frame inside `rect`, and the size to render it at.
+    pub fn layout(&self, rect: Rect, pixels_per_point: f32, max_size: u32) -> (Rect, (u32, u32)) {
+        let aspect = match self.fixed_size {
+            Some((width, height)) => Some(width as f32 / height as f32),
+            None => self.aspect.map(|(w, h)| w as f32 / h as f32),
+        };
+        let shown = match aspect {
+            Some(aspect) => {
+                let width = rect.width().min(rect.height() * aspect);
+                Rect::from_center_size(rect.center(), vec2(width, width / aspect))
+            }
+            None => rect,
+        };
+        let (width, height) = match self.fixed_size {
+            Some((width, height)) => (width as f32, height as f32),
+            None => (
+                shown.width() * pixels_per_point,
+                shown.height() * pixels_per_point,
+            ),
+        };
+        // Sizes over the limit shrink as a whole, so they keep their aspect ratio.
+        let scale = self.scale.min(max_size as f32 / width.max(height));
+        let pixels = |len: f32| ((len * scale).round() as u32).clamp(1, max_size);
+        (shown, (pixels(width), pixels(height)))
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Preset");
+            for (name, fixed_size, aspect) in PRESETS {
+                if ui.button(name).clicked() {
+                    self.fixed_size = fixed_size;
+                    self.aspect = aspect;
+                }
+            }
+        });
+
+        let mut fixed = self.fixed_size.is_some();
+        if ui.checkbox(&mut fixed, "Fixed resolution").changed() {
+            self.fixed_size = fixed.then_some((1920, 1080));
+            fit_to_aspect(&mut self.fixed_size, self.aspect);
+        }
+        if let Some((width, height)) = &mut self.fixed_size {
+            ui.horizontal(|ui| {
+                let width_changed = ui
+                    .add(egui::DragValue::new(width).range(1..=8192))
+                    .changed();
+                ui.label("x");
+                let height_changed = ui
+                    .add(egui::DragValue::new(height).range(1..=8192))
+                    .changed();
+                if let Some((w, h)) = self.aspect {
+                    if width_changed {
+                        *height = (*width * h).div_ceil(w);
+                    } else if height_changed {
+                        *width = (*height * w).div_ceil(h);
+                    }
+                }
+            });
+        }
+
+        let ratio_name = |ratio: Option<Ratio>| match ratio {
+            Some((w, h)) => format!("{w}:{h}"),
+            None => "Free".to_string(),
+        };
+        let before = self.aspect;
+        egui::ComboBox::from_label("Aspect ratio")
+            .selected_text(ratio_name(self.aspect))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.aspect, None, ratio_name(None));
+                for ratio in RATIOS {
+                    ui.selectable_value(&mut self.aspect, Some(ratio), ratio_name(Some(ratio)));
+                }
+            });
+        if self.aspect != before {
+            fit_to_aspect(&mut self.fixed_size, self.aspect);
+        }
+
+        ui.add(
+            egui::Slider::new(&mut self.scale, 0.25..=2.0)
+                .text("Scale")
+                .suffix("x"),
+        )
+        .on_hover_text("Renders at a fraction or multiple of the resolution above.");
+    }
+}
+
+/// Keeps the width of a fixed size and changes the height to match `aspect`.
+fn fit_to_aspect(fixed_size: &mut Option<(u32, u32)>, aspect: Option<Ratio>) {
+    if let (Some((width, height)), Some((w, h))) = (fixed_size, aspect) {
+        *height = (*width * h).div_ceil(w);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::pos2;
+
+    use super::*;
+
+    fn settings(
+        fixed_size: Option<(u32, u32)>,
+        aspect: Option<Ratio>,
+        scale: f32,
+    ) -> RenderSettings {
+        RenderSettings {
+            fixed_size,
+            aspect,
+            scale,
+        }
+    }
+
+    fn pane(width: f32, height: f32) -> Rect {
+        Rect::from_min_size(pos2(10.0, 20.0), vec2(width, height))
+    }
+
+    #[test]
+    fn pane_sized_renders_follow_pixels_per_point() {
+        let (shown, size) = RenderSettings::default().layout(pane(100.0, 50.0), 2.0, 4096);
+        assert_eq!(shown, pane(100.0, 50.0));
+        assert_eq!(size, (200, 100));
+    }
+
+    #[test]
+    fn aspect_ratios_letterbox_the_pane() {
+        let (shown, size) = settings(None, Some((1, 1)), 1.0).layout(pane(200.0, 100.0), 1.0, 4096);
+        assert_eq!(
+            shown,
+            Rect::from_min_size(pos2(60.0, 20.0), vec2(100.0, 100.0))
+        );
+        assert_eq!(size, (100, 100));
+
+        let (shown, _) = settings(None, Some((16, 9)), 1.0).layout(pane(160.0, 160.0), 1.0, 4096);
+        assert_eq!(
+            shown,
+            Rect::from_min_size(pos2(10.0, 55.0), vec2(160.0, 90.0))
+        );
+    }
+
+    #[test]
+    fn fixed_sizes_ignore_the_pane_but_keep_their_shape() {
+        let (shown, size) =
+            settings(Some((1920, 1080)), None, 1.0).layout(pane(320.0, 320.0), 2.0, 4096);
+        assert_eq!(shown.size(), vec2(320.0, 180.0));
+        assert_eq!(size, (1920, 1080));
+    }
+
+    #[test]
+    fn scale_multiplies_the_resolution() {
+        let half = settings(None, None, 0.5);
+        assert_eq!(half.layout(pane(100.0, 50.0), 1.0, 4096).1, (50, 25));
+        let fixed = settings(Some((1920, 1080)), None, 0.25);
+        assert_eq!(fixed.layout(pane(10.0, 10.0), 1.0, 4096).1, (480, 270));
+        // Never below one pixel a side.
+        let tiny = settings(None, None, 0.001);
+        assert_eq!(tiny.layout(pane(100.0, 10.0), 1.0, 4096).1, (1, 1));
+    }
+
+    #[test]
+    fn oversized_renders_shrink_as_a_whole() {
+        let fixed = settings(Some((1920, 1080)), None, 1.0);
+        assert_eq!(fixed.layout(pane(10.0, 10.0), 1.0, 1024).1, (1024, 576));
+        let tall = settings(Some((1080, 4320)), None, 2.0);
+        assert_eq!(tall.layout(pane(10.0, 10.0), 1.0, 2048).1, (512, 2048));
+        let hidpi = RenderSettings::default();
+        assert_eq!(hidpi.layout(pane(1000.0, 500.0), 3.0, 1500).1, (1500, 750));
+    }
+}
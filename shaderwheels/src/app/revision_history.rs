@@ -0,0 +1,116 @@
+use shaderwheels_logic::web::shader_api::{
+    RemoteShader, RevisionSummary, ShaderApiClient, ShaderApiError, ShaderId,
+};
+
+use crate::app::executor::{Task, spawn_task};
+
+pub enum RevisionAction {
+    Restore(RemoteShader),
+    /// Opens the revision in a preview pane, leaving the editor alone.
+    Preview(RemoteShader),
+}
+
+/// Lists the saved revisions of a database shader and fetches one to restore
+/// or preview.
+#[derive(Default)]
+pub struct RevisionHistory {
+    shader: Option<ShaderId>,
+    revisions: Vec<RevisionSummary>,
+    listing: Option<Task<Result<Vec<RevisionSummary>, ShaderApiError>>>,
+    /// Whether the fetched revision is for a preview, and the fetch.
+    fetching: Option<(bool, Task<Result<RemoteShader, ShaderApiError>>)>,
+    error: Option<String>,
+}
+
+impl RevisionHistory {
+    pub fn open(&mut self, ctx: &egui::Context, api: &ShaderApiClient, id: ShaderId) {
+        *self = Self {
+            shader: Some(id),
+            ..Default::default()
+        };
+        let api = api.clone();
+        self.listing = Some(spawn_task(ctx, async move { api.revisions(id).await }));
+    }
+
+    /// Returns a revision the user chose to restore or preview.
+    pub fn ui(&mut self, ctx: &egui::Context, api: &ShaderApiClient) -> Option<RevisionAction> {
+        let id = self.shader?;
+
+        if let Some(res) = self.listing.as_ref().and_then(|t| t.try_take()) {
+            self.listing = None;
+            match res {
+                Ok(revisions) => self.revisions = revisions,
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        }
+        let mut action = None;
+        if let Some((preview, res)) = self
+            .fetching
+            .as_ref()
+            .and_then(|(preview, t)| Some((*preview, t.try_take()?)))
+        {
+            self.fetching = None;
+            match res {
+                Ok(remote) if preview => action = Some(RevisionAction::Preview(remote)),
+                Ok(remote) => action = Some(RevisionAction::Restore(remote)),
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        }
+
+        let mut open = true;
+        egui::Window::new(format!("Revisions of shader #{id}"))
+            .open(&mut open)
+            .default_width(320.0)
+            .show(ctx, |ui| {
+                if let Some(e) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, e);
+                }
+                if self.listing.is_some() || self.fetching.is_some() {
+                    ui.spinner();
+                }
+                if self.listing.is_none() && self.revisions.is_empty() && self.error.is_none() {
+                    ui.label("No revisions yet");
+                }
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("revision list")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for rev in &self.revisions {
+                                ui.monospace(&rev.revision);
+                                ui.label(rev.author.as_deref().unwrap_or(""));
+                                ui.label(rev.created_at.map(format_age).unwrap_or_default());
+                                let idle = self.fetching.is_none();
+                                let restore = ui.add_enabled(idle, egui::Button::new("Restore"));
+                                let preview = ui.add_enabled(idle, egui::Button::new("Preview"));
+                                if restore.clicked() || preview.clicked() {
+                                    let api = api.clone();
+                                    let revision = rev.revision.clone();
+                                    let fetch = spawn_task(ctx, async move {
+                                        api.read_revision(id, &revision).await
+                                    });
+                                    self.fetching = Some((preview.clicked(), fetch));
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+            });
+
+        // Previewing keeps the list open, so several revisions can be lined up.
+        if !open || matches!(action, Some(RevisionAction::Restore(_))) {
+            *self = Self::default();
+        }
+        action
+    }
+}
+
+fn format_age(created_at: u64) -> String {
+    let secs = shaderwheels_logic::web::unix_now().saturating_sub(created_at);
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", secs / 60),
+        3600..86400 => format!("{} h ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
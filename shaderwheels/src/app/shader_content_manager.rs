@@ -1,6 +1,22 @@
-use std::{path::PathBuf, sync::mpsc::Receiver};
+use std::{fmt::Display, path::PathBuf};
 
-use shaderwheels_logic::rendering::{self};
+use shaderwheels_logic::{
+    rendering::{
+        self,
+        shader_config::{ShaderBackend, ShaderConfig, ShaderLanguage},
+    },
+    web::shader_api::{
+        RemoteShader, RevisionId, ShaderApiClient, ShaderApiError, ShaderId, ShaderUpload,
+    },
+};
+
+#[cfg(target_arch = "wasm32")]
+use crate::app::browser_storage;
+use crate::app::{
+    executor::{Task, spawn_task},
+    outbox::{Outbox, OutboxEvent},
+    project_file,
+};
 
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct ShaderFileLocation {
@@ -9,6 +25,15 @@ pub struct ShaderFileLocation {
 
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct ShaderDBEntry {
+    pub id: ShaderId,
+    /// The revision last loaded or saved, which the next save must be based on.
+    #[serde(default)]
+    pub revision: Option<RevisionId>,
+}
+
+/// A shader kept in the browser's local storage on the web build.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq)]
+pub struct BrowserShaderEntry {
     pub id: u64,
 }
 
@@ -16,33 +41,75 @@ pub struct ShaderDBEntry {
 pub enum ShaderStorageTypePreference {
     File,
     DB,
+    /// A new database entry that records the shader it was forked from.
+    Fork(ShaderId),
+    Browser,
 }
 
 impl ShaderStorageTypePreference {
-    #[cfg(not(target_arch = "wasm32"))]
-    pub async fn realize(&self) -> Option<ShaderStorageLocation> {
+    /// Picks a concrete place to save to. A database location is created together
+    /// with its first contents, since the API hands out ids on creation.
+    pub async fn realize(
+        &self,
+        api: &ShaderApiClient,
+        content: &ShaderInfo,
+    ) -> Result<ShaderStorageLocation, SaveError> {
         match self {
-            ShaderStorageTypePreference::File => {
-                let picker = rfd::AsyncFileDialog::new()
-                    .add_filter("ShaderWheels", &["shwl"])
-                    .save_file()
-                    .await;
-
-                let file_handle = picker?;
-
-                Some(ShaderStorageLocation::File(ShaderFileLocation {
-                    path: file_handle.path().to_path_buf(),
-                }))
-            }
-            ShaderStorageTypePreference::DB => {
-                todo!()
+            ShaderStorageTypePreference::File => Self::realize_file().await,
+            ShaderStorageTypePreference::DB => Self::realize_remote(api, content, None).await,
+            ShaderStorageTypePreference::Fork(parent) => {
+                Self::realize_remote(api, content, Some(*parent)).await
             }
+            ShaderStorageTypePreference::Browser => Self::realize_browser(),
         }
     }
 
+    async fn realize_remote(
+        api: &ShaderApiClient,
+        content: &ShaderInfo,
+        parent: Option<ShaderId>,
+    ) -> Result<ShaderStorageLocation, SaveError> {
+        let upload = ShaderUpload {
+            parent,
+            ..content.to_upload()
+        };
+        let created = api.create(&upload).await.map_err(SaveError::Remote)?;
+        Ok(ShaderStorageLocation::RemoteDB(ShaderDBEntry {
+            id: created.id,
+            revision: created.revision,
+        }))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn realize_file() -> Result<ShaderStorageLocation, SaveError> {
+        let file_handle = rfd::AsyncFileDialog::new()
+            .add_filter("ShaderWheels", &[project_file::PROJECT_EXTENSION])
+            .save_file()
+            .await
+            .ok_or(SaveError::NoLocation)?;
+
+        Ok(ShaderStorageLocation::File(ShaderFileLocation {
+            path: file_handle.path().to_path_buf(),
+        }))
+    }
+
     #[cfg(target_arch = "wasm32")]
-    pub async fn realize(&self) -> Option<ShaderStorageLocation> {
-        todo!()
+    async fn realize_file() -> Result<ShaderStorageLocation, SaveError> {
+        log::warn!("Saving to files is not possible on the web");
+        Err(SaveError::Unsupported)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn realize_browser() -> Result<ShaderStorageLocation, SaveError> {
+        log::warn!("Browser storage is only available on the web");
+        Err(SaveError::Unsupported)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn realize_browser() -> Result<ShaderStorageLocation, SaveError> {
+        browser_storage::new_entry()
+            .map(ShaderStorageLocation::Browser)
+            .map_err(SaveError::Browser)
     }
 }
 
@@ -50,6 +117,43 @@ impl ShaderStorageTypePreference {
 pub enum ShaderStorageLocation {
     File(ShaderFileLocation),
     RemoteDB(ShaderDBEntry),
+    Browser(BrowserShaderEntry),
+}
+
+impl Display for ShaderStorageLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShaderStorageLocation::File(loc) => write!(f, "{}", loc.path.display()),
+            ShaderStorageLocation::RemoteDB(entry) => write!(f, "shader #{}", entry.id),
+            ShaderStorageLocation::Browser(_) => write!(f, "browser storage"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    NoLocation,
+    Io(std::io::Error),
+    #[cfg(target_arch = "wasm32")]
+    Browser(browser_storage::BrowserStorageError),
+    Remote(ShaderApiError),
+    /// Someone else saved the remote shader first. Holds their version.
+    Conflict(Box<RemoteShader>),
+    Unsupported,
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::NoLocation => write!(f, "no location was chosen"),
+            SaveError::Io(e) => write!(f, "{e}"),
+            #[cfg(target_arch = "wasm32")]
+            SaveError::Browser(e) => write!(f, "{e}"),
+            SaveError::Remote(e) => write!(f, "{e}"),
+            SaveError::Conflict(_) => write!(f, "someone else saved this shader first"),
+            SaveError::Unsupported => write!(f, "this storage location is not supported yet"),
+        }
+    }
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
@@ -59,12 +163,16 @@ pub enum ConcreteOrUndecidedLocation {
 }
 
 impl ConcreteOrUndecidedLocation {
-    pub async fn to_real_loc(self) -> Option<ShaderStorageLocation> {
+    pub async fn to_real_loc(
+        self,
+        api: &ShaderApiClient,
+        content: &ShaderInfo,
+    ) -> Result<ShaderStorageLocation, SaveError> {
         match self {
             ConcreteOrUndecidedLocation::Undecided(shader_storage_type_preference) => {
-                shader_storage_type_preference.realize().await
+                shader_storage_type_preference.realize(api, content).await
             }
-            ConcreteOrUndecidedLocation::Concrete(loc) => Some(loc),
+            ConcreteOrUndecidedLocation::Concrete(loc) => Ok(loc),
         }
     }
 }
@@ -72,7 +180,7 @@ impl ConcreteOrUndecidedLocation {
 impl Default for ConcreteOrUndecidedLocation {
     #[cfg(target_arch = "wasm32")]
     fn default() -> Self {
-        Self::Undecided(ShaderStorageTypePreference::DB)
+        Self::Undecided(ShaderStorageTypePreference::Browser)
     }
 
     #[cfg(not(target_arch = "wasm32"))]
@@ -85,18 +193,94 @@ impl Default for ConcreteOrUndecidedLocation {
 pub struct ShaderInfo {
     pub contents: String,
     pub name: String,
+    #[serde(default)]
+    pub language: ShaderLanguage,
+    #[serde(default)]
+    pub backend: ShaderBackend,
+    #[serde(default = "default_entry_point")]
+    pub entry_point: String,
+    /// Set when the project came from a newer version of the app, so that
+    /// saving it does not downgrade the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub newer_format: Option<project_file::NewerFormat>,
+}
+
+fn default_entry_point() -> String {
+    rendering::WGSL_ENTRY.to_string()
 }
 
 impl ShaderInfo {
-    async fn save_in_location(&self, loc: &ShaderStorageLocation) -> bool {
+    pub fn to_shader_config(&self) -> ShaderConfig {
+        ShaderConfig {
+            content: self.contents.clone(),
+            language: self.language,
+            backend: self.backend,
+            entry_point: self.entry_point.clone(),
+        }
+    }
+
+    pub fn to_upload(&self) -> ShaderUpload {
+        ShaderUpload {
+            name: self.name.clone(),
+            project: project_file::serialize_project(self),
+            parent: None,
+        }
+    }
+
+    /// Returns the location as it is after the save, which for a remote shader
+    /// carries the new revision.
+    async fn save_in_location(
+        &self,
+        api: &ShaderApiClient,
+        loc: &ShaderStorageLocation,
+    ) -> Result<ShaderStorageLocation, SaveError> {
         match loc {
-            ShaderStorageLocation::File(shader_file_location) => {
-                let res = std::fs::write(shader_file_location.path.clone(), self.contents.clone());
-                res.is_ok()
-            }
-            ShaderStorageLocation::RemoteDB(_shader_dbentry) => todo!(),
+            ShaderStorageLocation::File(shader_file_location) => std::fs::write(
+                shader_file_location.path.clone(),
+                project_file::serialize_project(self),
+            )
+            .map(|_| loc.clone())
+            .map_err(SaveError::Io),
+            ShaderStorageLocation::RemoteDB(entry) => self
+                .save_remote(api, entry)
+                .await
+                .map(ShaderStorageLocation::RemoteDB),
+            #[cfg(target_arch = "wasm32")]
+            ShaderStorageLocation::Browser(entry) => browser_storage::save(entry, self)
+                .map(|_| loc.clone())
+                .map_err(SaveError::Browser),
+            #[cfg(not(target_arch = "wasm32"))]
+            ShaderStorageLocation::Browser(_) => Err(SaveError::Unsupported),
         }
     }
+
+    /// Uploads over an existing database entry, returning it at its new revision.
+    pub async fn save_remote(
+        &self,
+        api: &ShaderApiClient,
+        entry: &ShaderDBEntry,
+    ) -> Result<ShaderDBEntry, SaveError> {
+        let upload = self.to_upload();
+        let saved = match api
+            .update(entry.id, &upload, entry.revision.as_deref())
+            .await
+        {
+            Ok(saved) => saved,
+            Err(ShaderApiError::Conflict) => {
+                let latest = api.read(entry.id).await.map_err(SaveError::Remote)?;
+                // A retried update can conflict with its own first attempt.
+                if latest.project != upload.project {
+                    return Err(SaveError::Conflict(Box::new(latest)));
+                }
+                latest
+            }
+            Err(e) => return Err(SaveError::Remote(e)),
+        };
+        Ok(ShaderDBEntry {
+            id: entry.id,
+            revision: saved.revision,
+        })
+    }
 }
 
 impl Default for ShaderInfo {
@@ -105,6 +289,10 @@ impl Default for ShaderInfo {
             contents: rendering::DEFAULT_WGSL_FRAG.to_string(),
             //rendering::DEFAULT_WGSL_COMPUTE.to_string(),
             name: "Untitled Shader".to_string(),
+            language: ShaderLanguage::default(),
+            backend: ShaderBackend::default(),
+            entry_point: default_entry_point(),
+            newer_format: None,
         }
     }
 }
@@ -124,6 +312,25 @@ impl ShaderStorageConnection {
     pub fn get_content(&self) -> &ShaderInfo {
         &self.info_in_location
     }
+    pub fn remote_id(&self) -> Option<ShaderId> {
+        self.remote_entry().map(|entry| entry.id)
+    }
+    pub fn remote_entry(&self) -> Option<&ShaderDBEntry> {
+        match &self.location {
+            ConcreteOrUndecidedLocation::Concrete(ShaderStorageLocation::RemoteDB(entry)) => {
+                Some(entry)
+            }
+            _ => None,
+        }
+    }
+    fn remote_entry_mut(&mut self) -> Option<&mut ShaderDBEntry> {
+        match &mut self.location {
+            ConcreteOrUndecidedLocation::Concrete(ShaderStorageLocation::RemoteDB(entry)) => {
+                Some(entry)
+            }
+            _ => None,
+        }
+    }
     pub fn new(location: ConcreteOrUndecidedLocation, content: ShaderInfo) -> Self {
         Self {
             location,
@@ -132,6 +339,11 @@ impl ShaderStorageConnection {
         }
     }
 
+    /// Keeps a rename done directly in storage from showing up as an unsaved change.
+    pub fn rename_in_location(&mut self, name: &str) {
+        self.info_in_location.name = name.to_string();
+    }
+
     pub fn saving_needed(&self, content: &ShaderInfo) -> bool {
         self.info_in_location != *content
     }
@@ -140,22 +352,25 @@ impl ShaderStorageConnection {
         !self.currently_saving && self.saving_needed(content)
     }
 
-    pub async fn save_to_new_info_if_eligible(
-        self,
+    pub async fn save_to_location(
+        api: ShaderApiClient,
+        location: ConcreteOrUndecidedLocation,
         new_content: ShaderInfo,
-    ) -> Option<ShaderStorageConnection> {
-        if !self.eligible_to_save(&new_content) {
-            return None;
-        }
-
-        let concrete_loc = self.location.to_real_loc().await?;
-
-        let success = new_content.save_in_location(&concrete_loc).await;
-        if !success {
-            return None;
+    ) -> Result<ShaderStorageConnection, SaveError> {
+        // Creating a database entry already uploads the content.
+        let created_remotely = matches!(
+            location,
+            ConcreteOrUndecidedLocation::Undecided(
+                ShaderStorageTypePreference::DB | ShaderStorageTypePreference::Fork(_)
+            )
+        );
+        let mut concrete_loc = location.to_real_loc(&api, &new_content).await?;
+
+        if !created_remotely {
+            concrete_loc = new_content.save_in_location(&api, &concrete_loc).await?;
         }
 
-        Some(ShaderStorageConnection {
+        Ok(ShaderStorageConnection {
             location: ConcreteOrUndecidedLocation::Concrete(concrete_loc),
             info_in_location: new_content,
             currently_saving: false,
@@ -163,49 +378,106 @@ impl ShaderStorageConnection {
     }
 }
 
+pub type SaveResult = Result<ShaderStorageConnection, SaveError>;
+
 #[derive(Default)]
 pub struct ShaderStorageConnectionManager {
     pub connection: ShaderStorageConnection,
-    pub channel: Option<Receiver<Option<ShaderStorageConnection>>>,
+    pub api: ShaderApiClient,
+    /// Saves over existing database entries go through here, so they survive
+    /// being offline.
+    pub outbox: Outbox,
+    task: Option<Task<SaveResult>>,
 }
 
 impl ShaderStorageConnectionManager {
-    fn save_process_task(&mut self, new_content: &ShaderInfo) {
-        if !self.connection.eligible_to_save(new_content) {
-            return;
-        }
-
-        let connection_copy = self.connection.clone();
+    fn save_process_task(
+        &mut self,
+        ctx: &egui::Context,
+        location: ConcreteOrUndecidedLocation,
+        new_content: &ShaderInfo,
+    ) {
         let content = new_content.clone();
         self.connection.currently_saving = true;
 
-        let (writer, reader) = std::sync::mpsc::channel::<Option<ShaderStorageConnection>>();
+        self.task = Some(spawn_task(
+            ctx,
+            ShaderStorageConnection::save_to_location(self.api.clone(), location, content),
+        ));
+    }
 
-        let fut = async move {
-            let con = connection_copy;
-            let res = con.save_to_new_info_if_eligible(content).await;
-            let _ = writer.send(res);
-        };
+    pub fn start_save(&mut self, ctx: &egui::Context, new_content: &ShaderInfo) {
+        if !self.connection.eligible_to_save(new_content) {
+            return;
+        }
+        // Queued content counts as saved; the outbox reports how syncing goes.
+        if let Some(entry) = self.connection.remote_entry() {
+            self.outbox.enqueue(entry, new_content.clone());
+            self.connection.info_in_location = new_content.clone();
+            ctx.request_repaint();
+            return;
+        }
+        self.save_process_task(ctx, self.connection.location.clone(), new_content);
+    }
 
-        // TODO: Probably switch to multi
-        //process_future(fut);
+    /// Saves to a freshly chosen location, even if nothing changed since the last save.
+    pub fn start_save_as(&mut self, ctx: &egui::Context, new_content: &ShaderInfo) {
+        if self.connection.currently_saving {
+            return;
+        }
+        self.save_process_task(ctx, ConcreteOrUndecidedLocation::default(), new_content);
+    }
 
-        self.channel = Some(reader);
+    /// Takes on a newer remote version after a conflict, so that the next save
+    /// overwrites it rather than conflicting again.
+    pub fn adopt_remote_version(&mut self, content: ShaderInfo, revision: Option<RevisionId>) {
+        if let Some(entry) = self.connection.remote_entry_mut() {
+            entry.revision = revision;
+        }
+        self.connection.info_in_location = content;
     }
 
-    pub fn start_save(&mut self, new_content: &ShaderInfo) {
-        self.save_process_task(new_content);
+    /// Switches to a different shader. A save still in flight for the old one is forgotten.
+    pub fn replace_connection(&mut self, connection: ShaderStorageConnection) {
+        self.connection = connection;
+        self.task = None;
     }
 
-    pub fn update(&mut self) {
-        if let Some(reader) = self.channel.as_ref() {
-            if let Ok(res) = reader.try_recv() {
-                self.connection.currently_saving = false;
-                if let Some(new_connection) = res {
+    /// Returns the outcome on the frame a save lands. On success the connection
+    /// now points at the saved content, so `saving_needed` turns false. Uploads
+    /// from the outbox are only reported for the shader currently open.
+    pub fn update(&mut self, ctx: &egui::Context) -> Option<Result<(), SaveError>> {
+        if let Some(res) = self.task.as_ref().and_then(|t| t.try_take()) {
+            self.task = None;
+            self.connection.currently_saving = false;
+
+            return match res {
+                Ok(new_connection) => {
                     self.connection = new_connection;
+                    Some(Ok(()))
                 }
+                Err(e) => Some(Err(e)),
+            };
+        }
 
-                self.channel = None;
+        match self.outbox.update(ctx, &self.api)? {
+            OutboxEvent::Synced(saved) => {
+                let entry = self.connection.remote_entry_mut()?;
+                if entry.id != saved.id {
+                    return None;
+                }
+                entry.revision = saved.revision;
+                Some(Ok(()))
+            }
+            OutboxEvent::Failed(id, e) => {
+                if self.connection.remote_id() != Some(id) {
+                    return None;
+                }
+                // The conflict dialog takes over from the queued save.
+                if matches!(e, SaveError::Conflict(_)) {
+                    self.outbox.discard(id);
+                }
+                Some(Err(e))
             }
         }
     }
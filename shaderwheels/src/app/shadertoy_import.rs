@@ -0,0 +1,561 @@
+use std::{collections::HashMap, fmt::Display};
+
+use serde::Deserialize;
+use serde_json::Value;
+use shaderwheels_logic::rendering::{self, shader_config::ShaderLanguage};
+
+use crate::app::{
+    executor::{Task, spawn_task},
+    shader_content_manager::ShaderInfo,
+};
+
+/// Input types that have no counterpart in ShaderWheels at all.
+const UNSUPPORTED_INPUTS: &[&str] = &[
+    "cubemap",
+    "video",
+    "webcam",
+    "music",
+    "musicstream",
+    "mic",
+    "volume",
+];
+
+/// Uniforms Shadertoy provides that ShaderWheels only fakes.
+const CONSTANT_UNIFORMS: &[&str] = &["iMouse", "iDate"];
+
+#[derive(Deserialize)]
+struct Document {
+    info: DocumentInfo,
+    renderpass: Vec<RenderPass>,
+}
+
+#[derive(Deserialize)]
+struct DocumentInfo {
+    #[serde(default)]
+    id: Option<String>,
+    name: String,
+    #[serde(default)]
+    username: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RenderPass {
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    code: String,
+    #[serde(default)]
+    inputs: Vec<Input>,
+    #[serde(default)]
+    outputs: Vec<Output>,
+}
+
+/// Exports from the site call the input type `type`, the API calls it `ctype`.
+#[derive(Deserialize)]
+struct Input {
+    #[serde(default)]
+    id: Value,
+    #[serde(default)]
+    ctype: Option<String>,
+    #[serde(default, rename = "type")]
+    kind: Option<String>,
+    channel: u32,
+    #[serde(default)]
+    src: String,
+    #[serde(default)]
+    sampler: Option<Sampler>,
+}
+
+#[derive(Deserialize)]
+struct Sampler {
+    #[serde(default)]
+    filter: String,
+    #[serde(default)]
+    wrap: String,
+    /// A string ("true") in most exports, a boolean in some.
+    #[serde(default)]
+    vflip: Value,
+}
+
+#[derive(Deserialize)]
+struct Output {
+    id: Value,
+}
+
+#[derive(Debug)]
+pub enum ShadertoyError {
+    NotJson(serde_json::Error),
+    Malformed(serde_json::Error),
+    /// The document is an error answer from the Shadertoy API.
+    Api(String),
+}
+
+impl Display for ShadertoyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShadertoyError::NotJson(e) => write!(f, "not a JSON file: {e}"),
+            ShadertoyError::Malformed(e) => write!(f, "not a Shadertoy shader: {e}"),
+            ShadertoyError::Api(e) => write!(f, "Shadertoy returned an error: {e}"),
+        }
+    }
+}
+
+/// A Shadertoy shader, as one project per image or buffer pass.
+#[derive(Clone)]
+pub struct ImportedShader {
+    /// The image pass comes first.
+    pub passes: Vec<ShaderInfo>,
+    pub warnings: Vec<String>,
+    /// Some passes or channel inputs were left out or read black, so the
+    /// shader will not look like it does on Shadertoy.
+    pub partial: bool,
+}
+
+/// Reads a Shadertoy export or API answer (`{"Shader": ...}`), or a list of
+/// either as bulk exports are.
+pub fn parse_shadertoy_json(text: &str) -> Result<Vec<ImportedShader>, ShadertoyError> {
+    let doc: Value = serde_json::from_str(text).map_err(ShadertoyError::NotJson)?;
+    let docs = match doc {
+        Value::Array(docs) => docs,
+        doc => vec![doc],
+    };
+
+    docs.into_iter()
+        .map(|mut doc| {
+            if let Some(error) = doc.get("Error") {
+                return Err(ShadertoyError::Api(
+                    error.as_str().unwrap_or("unknown error").to_string(),
+                ));
+            }
+            if let Some(shader) = doc.get_mut("Shader") {
+                doc = shader.take();
+            }
+            let doc: Document = serde_json::from_value(doc).map_err(ShadertoyError::Malformed)?;
+            Ok(import_document(doc))
+        })
+        .collect()
+}
+
+fn import_document(doc: Document) -> ImportedShader {
+    let shader_name = doc.info.name.clone();
+    let mut warnings = Vec::new();
+
+    let common: String = doc
+        .renderpass
+        .iter()
+        .filter(|pass| pass.kind == "common")
+        .map(|pass| pass.code.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // Buffer inputs refer to the output id of the pass that writes them.
+    let pass_names: HashMap<String, String> = doc
+        .renderpass
+        .iter()
+        .flat_map(|pass| {
+            pass.outputs
+                .iter()
+                .map(move |out| (id_key(&out.id), pass_label(pass)))
+        })
+        .collect();
+
+    let mut renderable: Vec<&RenderPass> = Vec::new();
+    let mut skipped = false;
+    for pass in &doc.renderpass {
+        match pass.kind.as_str() {
+            "image" | "buffer" => renderable.push(pass),
+            "common" => {}
+            kind => {
+                skipped = true;
+                warnings.push(format!(
+                    "{shader_name}: skipped the {} pass, {kind} passes are not supported",
+                    pass_label(pass)
+                ))
+            }
+        }
+    }
+    renderable.sort_by_key(|pass| pass.kind != "image");
+
+    if renderable.len() > 1 {
+        warnings.push(format!(
+            "{shader_name}: each pass becomes its own project, passes do not feed each other"
+        ));
+    }
+
+    let partial =
+        skipped || renderable.len() > 1 || renderable.iter().any(|pass| !pass.inputs.is_empty());
+    let passes = renderable
+        .iter()
+        .map(|pass| {
+            let name = if renderable.len() == 1 {
+                shader_name.clone()
+            } else {
+                format!("{shader_name} - {}", pass_label(pass))
+            };
+            ShaderInfo {
+                contents: pass_source(&doc.info, pass, &common, &pass_names, &mut warnings),
+                name,
+                language: ShaderLanguage::Glsl,
+                entry_point: rendering::WGSL_ENTRY.to_string(),
+                ..ShaderInfo::default()
+            }
+        })
+        .collect();
+
+    ImportedShader {
+        passes,
+        warnings,
+        partial,
+    }
+}
+
+fn pass_label(pass: &RenderPass) -> String {
+    if pass.name.is_empty() {
+        pass.kind.clone()
+    } else {
+        pass.name.clone()
+    }
+}
+
+fn id_key(id: &Value) -> String {
+    match id {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Stand-ins for the four channels, so passes that sample them compile. Every
+/// lookup reads black until channels can be bound.
+const CHANNELS: &str = r#"struct ShadertoyChannel {
+    int index;
+};
+#define sampler2D ShadertoyChannel
+const ShadertoyChannel iChannel0 = ShadertoyChannel(0);
+const ShadertoyChannel iChannel1 = ShadertoyChannel(1);
+const ShadertoyChannel iChannel2 = ShadertoyChannel(2);
+const ShadertoyChannel iChannel3 = ShadertoyChannel(3);
+vec4 texture(ShadertoyChannel channel, vec2 uv) { return vec4(0.0); }
+vec4 texture(ShadertoyChannel channel, vec2 uv, float bias) { return vec4(0.0); }
+vec4 textureLod(ShadertoyChannel channel, vec2 uv, float lod) { return vec4(0.0); }
+vec4 textureGrad(ShadertoyChannel channel, vec2 uv, vec2 dx, vec2 dy) { return vec4(0.0); }
+vec4 texelFetch(ShadertoyChannel channel, ivec2 texel, int lod) { return vec4(0.0); }
+ivec2 textureSize(ShadertoyChannel channel, int lod) { return ivec2(iResolution.xy); }
+#define iChannelResolution vec3[4](iResolution, iResolution, iResolution, iResolution)
+#define iChannelTime float[4](iTime, iTime, iTime, iTime)"#;
+
+/// Wraps the pass in the GLSL fragment interface ShaderWheels renders, with the
+/// Shadertoy uniforms derived from `size`, and lists its channels in comments.
+fn pass_source(
+    info: &DocumentInfo,
+    pass: &RenderPass,
+    common: &str,
+    pass_names: &HashMap<String, String>,
+    warnings: &mut Vec<String>,
+) -> String {
+    let label = pass_label(pass);
+    let origin = format!("{}, {label}", info.name);
+    let mut header = format!("// Imported from Shadertoy: \"{}\"", info.name);
+    if let Some(user) = &info.username {
+        header += &format!(" by {user}");
+    }
+    if let Some(id) = &info.id {
+        header += &format!("\n// https://www.shadertoy.com/view/{id}");
+    }
+    header += &format!("\n// Pass: {label}");
+
+    let mut inputs: Vec<&Input> = pass.inputs.iter().collect();
+    inputs.sort_by_key(|input| input.channel);
+    let mut bound = Vec::new();
+    for input in inputs {
+        let kind = input
+            .ctype
+            .as_deref()
+            .or(input.kind.as_deref())
+            .unwrap_or("unknown");
+        let source = match kind {
+            "buffer" => pass_names
+                .get(&id_key(&input.id))
+                .cloned()
+                .unwrap_or_else(|| format!("buffer {}", id_key(&input.id))),
+            "texture" => format!("texture {}", input.src),
+            kind => kind.to_string(),
+        };
+        let mut line = format!("\n// iChannel{}: {source}", input.channel);
+        if let Some(sampler) = &input.sampler {
+            line += &format!(", {} filter, {} wrap", sampler.filter, sampler.wrap);
+            if sampler.vflip == true || sampler.vflip == "true" {
+                line += ", flipped";
+            }
+        }
+        header += &line;
+
+        if UNSUPPORTED_INPUTS.contains(&kind) {
+            warnings.push(format!(
+                "{origin}: iChannel{} is a {kind} input, which is not supported",
+                input.channel
+            ));
+        } else {
+            bound.push(format!("iChannel{}", input.channel));
+        }
+    }
+    if !bound.is_empty() {
+        warnings.push(format!(
+            "{origin}: reads {}, channel inputs are not bound yet so they read as black",
+            bound.join(", ")
+        ));
+    }
+
+    for uniform in CONSTANT_UNIFORMS {
+        if pass.code.contains(uniform) || common.contains(uniform) {
+            warnings.push(format!("{origin}: uses {uniform}, which is always zero"));
+        }
+    }
+
+    let common = if common.is_empty() {
+        String::new()
+    } else {
+        format!("// Common\n{common}\n\n")
+    };
+
+    format!(
+        r#"#version 450
+{header}
+layout(set = 0, binding = 0) uniform Size {{
+    uvec4 size;
+}};
+
+layout(location = 0) out vec4 out_color;
+
+#define iResolution vec3(vec2(size.xy), 1.0)
+#define iTime (float(size.z) / 1000.0)
+#define iTimeDelta (1.0 / 60.0)
+#define iFrameRate 60.0
+#define iFrame int(iTime * 60.0)
+#define iMouse vec4(0.0)
+#define iDate vec4(0.0)
+#define iSampleRate 44100.0
+
+{CHANNELS}
+
+{common}// {label}
+{code}
+
+void main() {{
+    // Shadertoy puts the origin in the bottom left corner.
+    mainImage(out_color, vec2(gl_FragCoord.x, iResolution.y - gl_FragCoord.y));
+    out_color.a = 1.0;
+}}
+"#,
+        code = pass.code.trim_end(),
+    )
+}
+
+/// Name and contents of each picked file.
+type PickedFiles = Vec<(String, Vec<u8>)>;
+
+/// Picks Shadertoy JSON files, imports them and lists what did not carry over.
+#[derive(Default)]
+pub struct ShadertoyImporter {
+    picking: Option<Task<PickedFiles>>,
+    warnings: Vec<String>,
+}
+
+impl ShadertoyImporter {
+    pub fn pick_files(&mut self, ctx: &egui::Context) {
+        self.picking = Some(spawn_task(ctx, async {
+            let Some(handles) = rfd::AsyncFileDialog::new()
+                .add_filter("Shadertoy JSON", &["json"])
+                .pick_files()
+                .await
+            else {
+                return Vec::new();
+            };
+            let mut files = Vec::new();
+            for handle in handles {
+                files.push((handle.file_name(), handle.read().await));
+            }
+            files
+        }));
+    }
+
+    /// Returns the projects of every shader in `files`, in order.
+    pub fn import_files(&mut self, files: Vec<(String, Vec<u8>)>) -> Vec<ImportedShader> {
+        self.warnings.clear();
+        let mut shaders = Vec::new();
+        for (name, bytes) in files {
+            let parsed = std::str::from_utf8(&bytes)
+                .map_err(|_| "file is not valid UTF-8".to_string())
+                .and_then(|text| parse_shadertoy_json(text).map_err(|e| e.to_string()));
+            match parsed {
+                Ok(imported) => shaders.extend(imported),
+                Err(e) => self.warnings.push(format!("{name}: {e}")),
+            }
+        }
+        for shader in &mut shaders {
+            self.warnings.append(&mut shader.warnings);
+        }
+        shaders
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.warnings.len()
+    }
+
+    /// Returns the shaders from the files picked with [`Self::pick_files`]
+    /// once they have been read.
+    pub fn update(&mut self, ctx: &egui::Context) -> Option<Vec<ImportedShader>> {
+        let mut imported = None;
+        if let Some(files) = self.picking.as_ref().and_then(|t| t.try_take()) {
+            self.picking = None;
+            // Nothing was picked when the dialog was cancelled.
+            if !files.is_empty() {
+                imported = Some(self.import_files(files));
+            }
+        }
+
+        if !self.warnings.is_empty() {
+            let mut open = true;
+            egui::Window::new("Shadertoy Import")
+                .open(&mut open)
+                .default_width(420.0)
+                .show(ctx, |ui| {
+                    ui.label("Some parts of the imported shaders did not carry over:");
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        for warning in &self.warnings {
+                            ui.label(warning);
+                        }
+                    });
+                });
+            if !open {
+                self.warnings.clear();
+            }
+        }
+        imported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(input: &str) -> String {
+        format!(
+            r#"{{
+                "info": {{ "id": "abc123", "name": "Waves", "username": "tester" }},
+                "renderpass": [
+                    {{
+                        "name": "Buffer A",
+                        "type": "buffer",
+                        "code": "void mainImage(out vec4 c, in vec2 p) {{ c = vec4(shade()); }}",
+                        "inputs": [],
+                        "outputs": [{{ "id": 257, "channel": 0 }}]
+                    }},
+                    {{
+                        "name": "Image",
+                        "type": "image",
+                        "code": "void mainImage(out vec4 c, in vec2 p) {{ c = texture(iChannel0, p); }}",
+                        "inputs": [{input}],
+                        "outputs": []
+                    }},
+                    {{ "name": "Common", "type": "common", "code": "float shade() {{ return 0.5; }}" }}
+                ]
+            }}"#
+        )
+    }
+
+    #[test]
+    fn reads_exports_and_api_answers_alike() {
+        let from_site = export(r#"{ "id": 257, "type": "buffer", "channel": 0 }"#);
+        let from_api = format!(
+            r#"{{ "Shader": {} }}"#,
+            export(r#"{ "id": 257, "ctype": "buffer", "channel": 0 }"#)
+        );
+
+        for text in [from_site, from_api] {
+            let shaders = parse_shadertoy_json(&text).unwrap();
+            assert_eq!(shaders.len(), 1);
+            let passes = &shaders[0].passes;
+            assert_eq!(passes.len(), 2);
+            assert_eq!(passes[0].name, "Waves - Image");
+            assert_eq!(passes[1].name, "Waves - Buffer A");
+            assert!(passes[0].contents.contains("// iChannel0: Buffer A"));
+            assert!(
+                passes[0]
+                    .contents
+                    .contains("// https://www.shadertoy.com/view/abc123")
+            );
+        }
+    }
+
+    #[test]
+    fn reads_bulk_exports() {
+        let text = format!("[{}, {}]", export(""), export(""));
+        assert_eq!(parse_shadertoy_json(&text).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn puts_common_code_before_every_pass() {
+        let shaders = parse_shadertoy_json(&export("")).unwrap();
+        for pass in &shaders[0].passes {
+            let common = pass.contents.find("float shade()").unwrap();
+            let main_image = pass.contents.find("void mainImage").unwrap();
+            assert!(common < main_image);
+        }
+    }
+
+    #[test]
+    fn declares_every_channel() {
+        let shaders = parse_shadertoy_json(&export("")).unwrap();
+        let contents = &shaders[0].passes[0].contents;
+        for name in [
+            "iChannel0",
+            "iChannel3",
+            "iChannelResolution",
+            "iChannelTime",
+        ] {
+            assert!(contents.contains(name), "{name} is not declared");
+        }
+    }
+
+    #[test]
+    fn warns_about_unsupported_inputs() {
+        let shaders =
+            parse_shadertoy_json(&export(r#"{ "id": 1, "ctype": "webcam", "channel": 1 }"#))
+                .unwrap();
+        let warnings = &shaders[0].warnings;
+        assert!(
+            warnings
+                .iter()
+                .any(|w| w == "Waves, Image: iChannel1 is a webcam input, which is not supported")
+        );
+        assert!(!warnings.iter().any(|w| w.contains("reads iChannel1")));
+    }
+
+    #[test]
+    fn reports_api_errors() {
+        let res = parse_shadertoy_json(r#"{ "Error": "Shader not found" }"#);
+        assert!(matches!(res, Err(ShadertoyError::Api(e)) if e == "Shader not found"));
+        assert!(matches!(
+            parse_shadertoy_json(r#"{ "info": {} }"#),
+            Err(ShadertoyError::Malformed(_))
+        ));
+        assert!(matches!(
+            parse_shadertoy_json("not json"),
+            Err(ShadertoyError::NotJson(_))
+        ));
+    }
+
+    #[test]
+    fn marks_imports_with_unconnected_passes_as_partial() {
+        let buffered =
+            parse_shadertoy_json(&export(r#"{ "id": 257, "type": "buffer", "channel": 0 }"#));
+        assert!(buffered.unwrap()[0].partial);
+
+        let single = r#"{
+            "info": { "name": "Plain" },
+            "renderpass": [{ "type": "image", "code": "void mainImage(out vec4 c, in vec2 p) { c = vec4(1.0); }" }]
+        }"#;
+        assert!(!parse_shadertoy_json(single).unwrap()[0].partial);
+    }
+}
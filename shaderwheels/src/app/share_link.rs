@@ -0,0 +1,263 @@
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use shaderwheels_logic::web::shader_api::ShaderId;
+
+use crate::app::{
+    project_file::{self, ProjectFileError},
+    shader_content_manager::ShaderInfo,
+};
+
+const INLINE_KEY: &str = "shader=";
+const REMOTE_KEY: &str = "shader-id=";
+
+/// Above this, some chat apps and link shorteners start cutting links off.
+pub const WARN_LINK_LEN: usize = 2000;
+/// Above this, browsers and servers cannot be relied on to keep the link whole.
+pub const MAX_LINK_LEN: usize = 32_000;
+/// Largest project a link may unpack to. A short link can inflate to gigabytes
+/// otherwise.
+const MAX_INLINE_PROJECT_LEN: u64 = 4 * 1024 * 1024;
+
+pub enum SharedShader {
+    Inline(ShaderInfo),
+    Remote(ShaderId),
+}
+
+#[derive(Debug)]
+pub enum ShareLinkError {
+    Base64(base64::DecodeError),
+    Decompress(std::io::Error),
+    TooLarge,
+    Project(ProjectFileError),
+    BadId(String),
+}
+
+impl Display for ShareLinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShareLinkError::Base64(e) => write!(f, "share link is damaged: {e}"),
+            ShareLinkError::Decompress(e) => write!(f, "share link is damaged: {e}"),
+            ShareLinkError::TooLarge => write!(
+                f,
+                "shared shader is larger than {} MiB",
+                MAX_INLINE_PROJECT_LEN / 1024 / 1024
+            ),
+            ShareLinkError::Project(e) => write!(f, "{e}"),
+            ShareLinkError::BadId(id) => write!(f, "{id:?} is not a shader id"),
+        }
+    }
+}
+
+/// A link with the whole project compressed into the fragment, which never
+/// leaves the browser, so the server does not see or limit it.
+pub fn inline_link(base: &str, info: &ShaderInfo) -> String {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    // Writing into a Vec cannot fail.
+    encoder
+        .write_all(project_file::serialize_project(info).as_bytes())
+        .unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    format!(
+        "{}#{INLINE_KEY}{}",
+        strip_fragment(base),
+        URL_SAFE_NO_PAD.encode(compressed)
+    )
+}
+
+/// A short link to a shader uploaded to the shader database.
+pub fn remote_link(base: &str, id: ShaderId) -> String {
+    format!("{}#{REMOTE_KEY}{id}", strip_fragment(base))
+}
+
+fn strip_fragment(url: &str) -> &str {
+    url.split('#').next().unwrap_or(url)
+}
+
+/// Reads a share link's fragment, with or without the leading `#`. Returns
+/// `None` for fragments that are not share links at all.
+pub fn parse_fragment(fragment: &str) -> Option<Result<SharedShader, ShareLinkError>> {
+    let fragment = fragment.trim_start_matches('#');
+
+    if let Some(encoded) = fragment.strip_prefix(INLINE_KEY) {
+        return Some(decode_inline(encoded).map(SharedShader::Inline));
+    }
+    let id = fragment.strip_prefix(REMOTE_KEY)?;
+    Some(
+        id.parse()
+            .map(SharedShader::Remote)
+            .map_err(|_| ShareLinkError::BadId(id.to_string())),
+    )
+}
+
+fn decode_inline(encoded: &str) -> Result<ShaderInfo, ShareLinkError> {
+    let compressed = URL_SAFE_NO_PAD
+        .decode(encoded)
+        .map_err(ShareLinkError::Base64)?;
+    let mut bytes = Vec::new();
+    DeflateDecoder::new(compressed.as_slice())
+        .take(MAX_INLINE_PROJECT_LEN + 1)
+        .read_to_end(&mut bytes)
+        .map_err(ShareLinkError::Decompress)?;
+    if bytes.len() as u64 > MAX_INLINE_PROJECT_LEN {
+        return Err(ShareLinkError::TooLarge);
+    }
+    let text =
+        String::from_utf8(bytes).map_err(|_| ShareLinkError::Project(ProjectFileError::NotUtf8))?;
+    project_file::deserialize_project(&text, "Shared Shader").map_err(ShareLinkError::Project)
+}
+
+pub enum ShareDialogChoice {
+    CopyAnyway,
+    Upload,
+    Cancel,
+}
+
+/// Asks what to do with a link that came out too long to share safely.
+pub fn long_link_prompt(
+    ctx: &egui::Context,
+    link_len: usize,
+    uploading: bool,
+) -> Option<ShareDialogChoice> {
+    let mut choice = None;
+    let modal = egui::Modal::new(egui::Id::new("long share link prompt")).show(ctx, |ui| {
+        ui.heading("Long share link");
+        ui.label(format!(
+            "The link is {link_len} characters long. Some apps cut off links this long."
+        ));
+        ui.label("Uploading the shader gives a short link instead.");
+
+        if uploading {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Uploading...");
+            });
+            return;
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Upload and Share").clicked() {
+                choice = Some(ShareDialogChoice::Upload);
+            }
+            let copy = ui.add_enabled(link_len <= MAX_LINK_LEN, egui::Button::new("Copy Anyway"));
+            if copy.clicked() {
+                choice = Some(ShareDialogChoice::CopyAnyway);
+            }
+            if ui.button("Cancel").clicked() {
+                choice = Some(ShareDialogChoice::Cancel);
+            }
+        });
+    });
+
+    if modal.should_close() && choice.is_none() && !uploading {
+        return Some(ShareDialogChoice::Cancel);
+    }
+    choice
+}
+
+/// Where share links point: the page itself on the web, the configured web
+/// build natively.
+pub fn base_url() -> String {
+    #[cfg(target_arch = "wasm32")]
+    if let Some(href) = web_sys::window().and_then(|w| w.location().href().ok()) {
+        return href;
+    }
+    shaderwheels_logic::web::config::current()
+        .share_base_url
+        .to_string()
+}
+
+/// Drops the fragment from the address bar, so that reloading the page does
+/// not open the shared shader over whatever was edited since.
+#[cfg(target_arch = "wasm32")]
+pub fn clear_location_fragment() {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let location = window.location();
+    if let (Ok(history), Ok(path), Ok(search)) =
+        (window.history(), location.pathname(), location.search())
+    {
+        let _ = history.replace_state_with_url(
+            &eframe::wasm_bindgen::JsValue::NULL,
+            "",
+            Some(&format!("{path}{search}")),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragment(link: &str) -> &str {
+        link.split_once('#').unwrap().1
+    }
+
+    #[test]
+    fn inline_links_round_trip() {
+        let info = ShaderInfo {
+            contents: "// ünïcode\nfn main() {}".to_string(),
+            name: "Waves".to_string(),
+            ..ShaderInfo::default()
+        };
+        let link = inline_link("https://example.com/app/#old", &info);
+        assert!(link.starts_with("https://example.com/app/#shader="));
+
+        match parse_fragment(&format!("#{}", fragment(&link))) {
+            Some(Ok(SharedShader::Inline(shared))) => assert!(shared == info),
+            _ => panic!("the link did not parse back"),
+        }
+    }
+
+    #[test]
+    fn remote_links_round_trip() {
+        let link = remote_link("https://example.com/", 42);
+        assert!(matches!(
+            parse_fragment(fragment(&link)),
+            Some(Ok(SharedShader::Remote(42)))
+        ));
+        assert!(matches!(
+            parse_fragment("shader-id=abc"),
+            Some(Err(ShareLinkError::BadId(_)))
+        ));
+    }
+
+    #[test]
+    fn other_fragments_are_not_share_links() {
+        assert!(parse_fragment("").is_none());
+        assert!(parse_fragment("#section-2").is_none());
+    }
+
+    #[test]
+    fn damaged_links_are_errors() {
+        assert!(matches!(
+            parse_fragment("shader=not*base64"),
+            Some(Err(ShareLinkError::Base64(_)))
+        ));
+        let not_deflate = URL_SAFE_NO_PAD.encode([0xff; 16]);
+        assert!(matches!(
+            parse_fragment(&format!("shader={not_deflate}")),
+            Some(Err(ShareLinkError::Decompress(_)))
+        ));
+    }
+
+    #[test]
+    fn links_that_inflate_too_far_are_refused() {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+        encoder
+            .write_all(&vec![b' '; MAX_INLINE_PROJECT_LEN as usize + 1])
+            .unwrap();
+        let encoded = URL_SAFE_NO_PAD.encode(encoder.finish().unwrap());
+        assert!(encoded.len() < MAX_LINK_LEN);
+        assert!(matches!(
+            parse_fragment(&format!("shader={encoded}")),
+            Some(Err(ShareLinkError::TooLarge))
+        ));
+    }
+}
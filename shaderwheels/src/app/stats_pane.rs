@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use egui::{Color32, Sense, Shape, Stroke, Ui, pos2, vec2};
+use shaderwheels_logic::rendering::{
+    communication::WorkerStats, graphics_backend_client::GraphicsClient,
+};
+
+const GRAPH_HEIGHT: f32 = 40.0;
+
+pub fn add_stats_pane(client: &GraphicsClient, ui: &mut Ui) {
+    let history = client.stats_history();
+
+    if client.is_animating() {
+        let intervals: Vec<Duration> = history.iter().filter_map(|s| s.frame_interval).collect();
+        if intervals.is_empty() {
+            ui.label("FPS: -");
+        } else {
+            let mean = intervals.iter().sum::<Duration>() / intervals.len() as u32;
+            ui.label(format!("FPS: {:.1}", 1.0 / mean.as_secs_f64()));
+        }
+    } else {
+        ui.label("FPS: not animating");
+    }
+
+    let last_of = |stage: fn(&WorkerStats) -> Option<Duration>| {
+        history
+            .iter()
+            .rev()
+            .find_map(stage)
+            .map_or("-".to_string(), format_ms)
+    };
+    if let Some(scale) = history.iter().rev().find_map(|s| s.render_scale) {
+        ui.label(format!("Render scale: {:.0}%", scale * 100.0));
+    }
+    ui.label(format!("Last compile: {}", last_of(|s| s.compile_time)));
+    ui.label(format!(
+        "Last pipeline build: {}",
+        last_of(|s| s.pipeline_time)
+    ));
+
+    graph(ui, "Step", history.iter().map(|s| Some(s.step_time)));
+    graph(ui, "Encode", history.iter().map(|s| s.encode_time));
+    graph(ui, "Whole frame", history.iter().map(|s| s.frame_time));
+    if history.iter().any(|s| s.gpu_time.is_some()) {
+        graph(ui, "GPU", history.iter().map(|s| s.gpu_time));
+    } else {
+        ui.label("GPU: timestamps are not supported on this device");
+    }
+    graph(
+        ui,
+        "Frame interval",
+        history.iter().map(|s| s.frame_interval),
+    );
+}
+
+fn format_ms(d: Duration) -> String {
+    format!("{:.2} ms", d.as_secs_f64() * 1000.0)
+}
+
+/// A line over the stats history, scaled to its own maximum. Steps without
+/// the stage leave gaps.
+fn graph(ui: &mut Ui, name: &str, values: impl Iterator<Item = Option<Duration>>) {
+    let values: Vec<Option<f32>> = values.map(|v| v.map(|d| d.as_secs_f32())).collect();
+    let latest = values.iter().rev().find_map(|v| *v);
+    let max = values.iter().flatten().copied().fold(0.0f32, f32::max);
+
+    ui.label(format!(
+        "{name}: {}",
+        latest.map_or("-".to_string(), |v| format_ms(Duration::from_secs_f32(v)))
+    ));
+    let (rect, _) =
+        ui.allocate_exact_size(vec2(ui.available_width(), GRAPH_HEIGHT), Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    if max <= 0.0 || values.len() < 2 {
+        return;
+    }
+
+    let stroke = Stroke::new(1.0, Color32::LIGHT_GREEN);
+    let step = rect.width() / (values.len() - 1) as f32;
+    let mut segment = Vec::new();
+    for (i, value) in values.iter().enumerate() {
+        match value {
+            Some(v) => segment.push(pos2(
+                rect.left() + i as f32 * step,
+                rect.bottom() - v / max * rect.height(),
+            )),
+            None => {
+                painter.add(Shape::line(std::mem::take(&mut segment), stroke));
+            }
+        }
+    }
+    painter.add(Shape::line(segment, stroke));
+}
@@ -1,11 +1,18 @@
 use eframe::egui_wgpu::RenderState;
-use egui_tiles::{Behavior, UiResponse};
+use egui_tiles::{Behavior, Container, Tile, TileId, Tiles, Tree, UiResponse};
+use shaderwheels_logic::web::shader_api::ShaderApiClient;
 
 use crate::app::{
     cfg_pane::add_transient_cfg_pane,
+    compare_pane::ComparePane,
     editor_gui::add_editor,
     egui_shaderwheels_logic::{self, RenderCtx},
     error_viewer::add_error_viewer,
+    gallery::{Gallery, GalleryAction},
+    gpu_pane::GpuPane,
+    previews::{PreviewId, Previews, add_preview_pane},
+    render_settings::RenderSettings,
+    stats_pane::add_stats_pane,
 };
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -14,6 +21,11 @@ pub enum PaneType {
     ErrorViewer,
     RenderTarget,
     LiveConfig,
+    Gallery,
+    Stats,
+    Gpu,
+    Preview(PreviewId),
+    Compare,
 }
 
 impl PaneType {
@@ -23,6 +35,11 @@ impl PaneType {
             PaneType::ErrorViewer => "Error Viewer",
             PaneType::RenderTarget => "Render Viewer",
             PaneType::LiveConfig => "Minute Config",
+            PaneType::Gallery => "Gallery",
+            PaneType::Stats => "Stats",
+            PaneType::Gpu => "GPU",
+            PaneType::Preview(_) => "Preview",
+            PaneType::Compare => "Compare",
         }
     }
 }
@@ -51,6 +68,9 @@ pub fn create_basic_tree() -> egui_tiles::Tree<ShaderWheelsPane> {
     let editor_pane = gen_pane(PaneType::CodeEditor);
     let error_pane = gen_pane(PaneType::ErrorViewer);
     let transient_cfg_pane = gen_pane(PaneType::LiveConfig);
+    let gallery_pane = gen_pane(PaneType::Gallery);
+    let stats_pane = gen_pane(PaneType::Stats);
+    let gpu_pane = gen_pane(PaneType::Gpu);
 
     let right_half = {
         let edit = tiles.insert_pane(editor_pane);
@@ -59,13 +79,49 @@ pub fn create_basic_tree() -> egui_tiles::Tree<ShaderWheelsPane> {
         tiles.insert_vertical_tile(vec![edit, cfg, error])
     };
 
-    let left_half = tiles.insert_pane(render_pane);
+    let left_half = {
+        let render = tiles.insert_pane(render_pane);
+        let gallery = tiles.insert_pane(gallery_pane);
+        let stats = tiles.insert_pane(stats_pane);
+        let gpu = tiles.insert_pane(gpu_pane);
+        tiles.insert_tab_tile(vec![render, gallery, stats, gpu])
+    };
 
     let root = tiles.insert_horizontal_tile(vec![left_half, right_half]);
 
     egui_tiles::Tree::new("my_tree", root, tiles)
 }
 
+/// Shows the first pane of this kind, or adds one as a tab next to the render pane.
+pub fn show_pane(tree: &mut Tree<ShaderWheelsPane>, kind: PaneType) {
+    if tree.make_active(|_, tile| matches!(tile, Tile::Pane(pane) if pane.kind == kind)) {
+        return;
+    }
+
+    let id_number = tree
+        .tiles
+        .iter()
+        .filter_map(|(_, tile)| match tile {
+            Tile::Pane(pane) => Some(pane.id_number + 1),
+            Tile::Container(_) => None,
+        })
+        .max()
+        .unwrap_or(0);
+    let new_tile = tree.tiles.insert_pane(ShaderWheelsPane { id_number, kind });
+
+    let tabs = tree.tiles.iter().find_map(|(tile_id, tile)| match tile {
+        Tile::Container(Container::Tabs(_)) => Some(*tile_id),
+        _ => None,
+    });
+    let Some(parent) = tabs.or(tree.root()) else {
+        return;
+    };
+    if let Some(Tile::Container(container)) = tree.tiles.get_mut(parent) {
+        container.add_child(new_tile);
+    }
+    tree.make_active(|tile_id, _| tile_id == new_tile);
+}
+
 // Freehanding this
 // Nvm gave up and referred to example again
 pub struct TreeBehavior<'a> {
@@ -74,10 +130,20 @@ pub struct TreeBehavior<'a> {
     pub compile_on_change: &'a mut bool,
     pub recompute_on_invalidate: &'a mut bool,
     pub renderstate: &'a RenderState,
+    pub read_only: bool,
+    pub gallery: &'a mut Gallery,
+    pub gpu_pane: &'a mut GpuPane,
+    pub previews: &'a mut Previews,
+    pub compare_pane: &'a mut ComparePane,
+    pub render_settings: &'a mut RenderSettings,
+    pub api: &'a ShaderApiClient,
 
     // outputs:
     pub shader_text_changed: bool,
     pub recompute_on_textchange_changed: bool,
+    pub fork_requested: bool,
+    pub gallery_action: Option<GalleryAction>,
+    pub closed_previews: Vec<PreviewId>,
 }
 
 impl<'a> Behavior<ShaderWheelsPane> for TreeBehavior<'a> {
@@ -90,17 +156,32 @@ impl<'a> Behavior<ShaderWheelsPane> for TreeBehavior<'a> {
         let drag_rect = match pane.kind {
             PaneType::CodeEditor => {
                 let lab = ui.label("I'm an editor");
-                add_editor(self.current_shader_text, &mut self.shader_text_changed, ui);
+                if self.read_only {
+                    ui.horizontal(|ui| {
+                        ui.label("This shader is read-only.");
+                        self.fork_requested = ui.button("Fork to Edit").clicked();
+                    });
+                    // Edits go to a throwaway copy, so text can still be selected and copied.
+                    let mut text = self.current_shader_text.clone();
+                    add_editor(&mut text, &mut false, ui);
+                } else {
+                    add_editor(self.current_shader_text, &mut self.shader_text_changed, ui);
+                }
                 lab
             }
             PaneType::ErrorViewer => {
                 let lab = ui.label("I'm an error viewer");
-                add_error_viewer(&self.rctx, ui);
+                add_error_viewer(self.rctx, self.previews, self.renderstate, ui);
                 lab
             }
             PaneType::RenderTarget => {
                 let lab = ui.label("I'm a render target");
-                egui_shaderwheels_logic::draw(self.rctx, self.renderstate, ui);
+                egui_shaderwheels_logic::draw(
+                    self.rctx,
+                    self.renderstate,
+                    self.render_settings,
+                    ui,
+                );
                 lab
             }
             PaneType::LiveConfig => {
@@ -109,7 +190,42 @@ impl<'a> Behavior<ShaderWheelsPane> for TreeBehavior<'a> {
                     self.compile_on_change,
                     self.recompute_on_invalidate,
                     &mut self.recompute_on_textchange_changed,
+                    &mut self.rctx.client,
+                    self.render_settings,
+                    ui,
+                );
+                lab
+            }
+            PaneType::Gallery => {
+                let lab = ui.label("I'm a shader gallery");
+                self.gallery_action = self.gallery.ui(ui, self.api);
+                lab
+            }
+            PaneType::Stats => {
+                let lab = ui.label("I'm a stats panel");
+                add_stats_pane(&self.rctx.client, ui);
+                lab
+            }
+            PaneType::Gpu => {
+                let lab = ui.label("I'm a GPU panel");
+                self.gpu_pane.ui(ui, self.rctx, self.renderstate);
+                lab
+            }
+            PaneType::Preview(id) => {
+                let lab = ui.label("I'm a preview");
+                if let Some(preview) = self.previews.get_mut(id) {
+                    add_preview_pane(preview, self.renderstate, self.render_settings, ui);
+                }
+                lab
+            }
+            PaneType::Compare => {
+                let lab = ui.label("I'm a comparison");
+                self.compare_pane.ui(
                     ui,
+                    self.rctx,
+                    self.previews,
+                    self.renderstate,
+                    self.render_settings,
                 );
                 lab
             }
@@ -128,6 +244,33 @@ impl<'a> Behavior<ShaderWheelsPane> for TreeBehavior<'a> {
     }
 
     fn tab_title_for_pane(&mut self, pane: &ShaderWheelsPane) -> egui::WidgetText {
-        pane.kind.name().into()
+        match pane.kind {
+            PaneType::Preview(id) => match self.previews.get(id) {
+                Some(preview) => preview.title.clone().into(),
+                None => pane.kind.name().into(),
+            },
+            kind => kind.name().into(),
+        }
+    }
+
+    fn is_tab_closable(&self, tiles: &Tiles<ShaderWheelsPane>, tile_id: TileId) -> bool {
+        matches!(
+            tiles.get(tile_id),
+            Some(Tile::Pane(ShaderWheelsPane {
+                kind: PaneType::Preview(_) | PaneType::Compare,
+                ..
+            }))
+        )
+    }
+
+    fn on_tab_close(&mut self, tiles: &mut Tiles<ShaderWheelsPane>, tile_id: TileId) -> bool {
+        if let Some(Tile::Pane(ShaderWheelsPane {
+            kind: PaneType::Preview(id),
+            ..
+        })) = tiles.get(tile_id)
+        {
+            self.closed_previews.push(*id);
+        }
+        true
     }
 }
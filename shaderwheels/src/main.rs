@@ -8,10 +8,20 @@ pub mod app;
 // When compiling natively:
 #[cfg(not(target_arch = "wasm32"))]
 fn main() -> eframe::Result {
-    use crate::app::App;
+    use crate::app::{APP_NAME, App, wgpu_options};
+    use shaderwheels_logic::web::config;
 
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
 
+    let default_config = eframe::storage_dir(APP_NAME).map(|dir| dir.join("config.json"));
+    match config::load_native(std::env::args().skip(1), default_config) {
+        Ok(cfg) => config::install(cfg),
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            std::process::exit(2);
+        }
+    }
+
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([400.0, 300.0])
@@ -20,10 +30,11 @@ fn main() -> eframe::Result {
         //eframe::icon_data::from_png_bytes(&include_bytes!("../assets/icon-256.png")[..])
         //    .expect("Failed to load icon"),
         //)
+        wgpu_options: wgpu_options(),
         ..Default::default()
     };
     eframe::run_native(
-        "ShaderWheels",
+        APP_NAME,
         native_options,
         Box::new(|cc| Ok(Box::new(App::new(cc)))),
     )
@@ -37,7 +48,10 @@ fn main() {
     // Redirect `log` message to `console.log` and friends:
     eframe::WebLogger::init(log::LevelFilter::Debug).ok();
 
-    let web_options = eframe::WebOptions::default();
+    let web_options = eframe::WebOptions {
+        wgpu_options: crate::app::wgpu_options(),
+        ..Default::default()
+    };
 
     wasm_bindgen_futures::spawn_local(async {
         let document = web_sys::window()
@@ -45,6 +59,25 @@ fn main() {
             .document()
             .expect("No document");
 
+        let page_url = web_sys::window()
+            .and_then(|w| w.location().href().ok())
+            .unwrap_or_default();
+        match shaderwheels_logic::web::config::load_web(&page_url).await {
+            Ok(cfg) => shaderwheels_logic::web::config::install(cfg),
+            Err(e) => {
+                if let Some(loading_text) = document.get_element_by_id("loading_text") {
+                    loading_text
+                        .set_inner_html(&format!("<p> Could not load the configuration: {e} </p>"));
+                }
+                panic!("Invalid configuration: {e}");
+            }
+        }
+
+        // Links made with "Copy Share Link" carry the shader in the fragment.
+        let share_fragment = web_sys::window()
+            .and_then(|w| w.location().hash().ok())
+            .filter(|hash| !hash.is_empty());
+
         let canvas = document
             .get_element_by_id("the_canvas_id")
             .expect("Failed to find the_canvas_id")
@@ -55,7 +88,13 @@ fn main() {
             .start(
                 canvas,
                 web_options,
-                Box::new(|cc| Ok(Box::new(crate::app::App::new(cc)))),
+                Box::new(move |cc| {
+                    let mut app = crate::app::App::new(cc);
+                    if let Some(fragment) = &share_fragment {
+                        app.open_share_fragment(&cc.egui_ctx, fragment);
+                    }
+                    Ok(Box::new(app))
+                }),
             )
             .await;
 